@@ -0,0 +1,63 @@
+//! op_application is a binary, so the modules needed to run its engines are included by path.
+
+#![allow(dead_code)]
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main, Throughput};
+use midly::MidiMessage;
+
+use op_engine::generator::Generator;
+
+use crate::faust::FaustGenerator;
+use crate::faust_engines::Engine;
+
+#[path = "../src/faust.rs"]
+mod faust;
+
+#[path = "../src/faust_engines.rs"]
+mod faust_engines;
+
+const BLOCK_SIZES: [usize; 3] = [64, 512, 4096];
+
+fn generator(engine: Engine) -> Box<dyn Generator> {
+    let mut dsp = engine.create();
+    dsp.init(44100);
+
+    let mut generator: Box<dyn Generator> = Box::new(FaustGenerator::new(dsp));
+    generator.handle(MidiMessage::NoteOn { key: 69.into(), vel: 100.into() });
+    generator
+}
+
+/// Compares running each engine's `compute` once per sample through `next` with running it once
+/// per block through `process`.
+fn bench_faust_generator(c: &mut Criterion) {
+    for engine in Engine::all() {
+        let mut group = c.benchmark_group(format!("faust/{}", engine.info().file_name));
+
+        for block_size in BLOCK_SIZES {
+            group.throughput(Throughput::Elements(block_size as u64));
+
+            group.bench_with_input(BenchmarkId::new("next", block_size), &block_size, |b, &block_size| {
+                let mut generator = generator(engine);
+                let mut buf = vec![0.0; block_size];
+
+                b.iter(|| {
+                    for sample in buf.iter_mut() {
+                        *sample = generator.next();
+                    }
+                });
+            });
+
+            group.bench_with_input(BenchmarkId::new("process", block_size), &block_size, |b, &block_size| {
+                let mut generator = generator(engine);
+                let mut buf = vec![0.0; block_size];
+
+                b.iter(|| generator.process(&mut buf));
+            });
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_faust_generator);
+criterion_main!(benches);
@@ -1,5 +1,7 @@
-use std::{env, process};
-use std::path::Path;
+use std::{env, fs, io, process};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
 
 fn capitalize_first(s: &str) -> String {
     s.chars().take(1).map(|c| c.to_uppercase().next().unwrap()).chain(s.chars().skip(1)).collect()
@@ -9,32 +11,239 @@ fn get_class_name(file_name: &str) -> String {
     file_name.split('_').map(capitalize_first).collect()
 }
 
+/// A .dsp file compiled to Rust.
+struct CompiledDsp {
+    file_name: String,
+    class_name: String,
+    rs_path: PathBuf,
+}
+
+enum CompileError {
+    /// The faust compiler could not be run, e.g. because it is not installed.
+    Faust(io::Error),
+
+    /// The .dsp file has errors, as reported by faust.
+    Dsp(Vec<String>),
+}
+
+/// Reformats an error from faust as `file:line: message`, which terminals and editors can link to.
+/// Faust reports errors as `file : line : ERROR : message`, or as `ERROR : file : line : message`
+/// in newer versions. Other lines are returned unchanged.
+fn format_faust_error(error: &str) -> String {
+    let parts: Vec<&str> = error.splitn(4, " : ").collect();
+
+    match parts[..] {
+        [file, line, "ERROR", message] | ["ERROR", file, line, message] if line.parse::<u32>().is_ok() => {
+            format!("{}:{}: {}", file, line, message)
+        }
+        _ => error.to_string(),
+    }
+}
+
+fn run_faust(dsp_path: &Path, class_name: &str, rs_path: &Path) -> Result<(), CompileError> {
+    let result = process::Command::new("faust")
+        .arg("-wall")
+        .arg("-light")
+        .args(["-lang", "rust"])
+        .args(["-cn", class_name])
+        .arg(dsp_path)
+        .arg("-o")
+        .arg(rs_path)
+        .output()
+        .map_err(CompileError::Faust)?;
+
+    if result.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let mut errors: Vec<String> = stderr.lines().filter(|l| !l.trim().is_empty()).map(format_faust_error).collect();
+
+    // Make sure the file is named even if faust did not say which one failed
+    let dsp_path = dsp_path.display().to_string();
+    if !errors.iter().any(|e| e.contains(&dsp_path)) {
+        errors.insert(0, format!("{}: faust exited with {}", dsp_path, result.status));
+    }
+
+    Err(CompileError::Dsp(errors))
+}
+
+/// Compiles every .dsp file in `dsp_dir` to a Rust file of the same name in `out_dir`. Returns the
+/// compiled files, sorted by name.
+///
+/// Files which cannot be compiled are replaced with their pre-generated version from
+/// `generated_dir` if there is one, and skipped otherwise. Set `OPERATOR_UPDATE_GENERATED` to
+/// copy the compiled files to `generated_dir`.
+fn compile_dir(dsp_dir: &Path, out_dir: &Path, generated_dir: &Path) -> Result<Vec<CompiledDsp>, Box<dyn std::error::Error>> {
+    fs::create_dir_all(out_dir)?;
+
+    let update_generated = env::var_os("OPERATOR_UPDATE_GENERATED").is_some();
+    let mut faust_missing = false;
+    let mut compiled = Vec::new();
+
+    for dsp_file in dsp_dir.read_dir()?.flatten() {
+        let dsp_path = dsp_file.path();
+        if dsp_path.extension() != Some(OsStr::new("dsp")) {
+            continue;
+        }
+
+        let dsp_name = dsp_path.file_stem().unwrap().to_str().unwrap().to_string();
+        let class_name = get_class_name(&dsp_name);
+        let rs_path = out_dir.join(&dsp_name).with_extension("rs");
+        let generated_path = generated_dir.join(&dsp_name).with_extension("rs");
+
+        match run_faust(&dsp_path, &class_name, &rs_path) {
+            Ok(()) => {
+                if update_generated {
+                    fs::create_dir_all(generated_dir)?;
+                    fs::copy(&rs_path, &generated_path)?;
+                }
+            }
+
+            Err(error) => {
+                match error {
+                    CompileError::Faust(error) if !faust_missing => {
+                        println!("cargo:warning=could not run faust ({}), using pre-generated Rust from {}", error, generated_dir.display());
+                        faust_missing = true;
+                    }
+                    CompileError::Faust(_) => (),
+                    CompileError::Dsp(errors) => {
+                        for error in errors {
+                            println!("cargo:warning={}", error);
+                        }
+                    }
+                }
+
+                if !generated_path.exists() {
+                    println!("cargo:warning=skipping {}, which has no pre-generated version", dsp_path.display());
+                    continue;
+                }
+
+                if !faust_missing {
+                    println!("cargo:warning=using pre-generated {} instead", generated_path.display());
+                }
+
+                fs::copy(&generated_path, &rs_path)?;
+            }
+        }
+
+        compiled.push(CompiledDsp {
+            file_name: dsp_name,
+            class_name,
+            rs_path,
+        });
+    }
+
+    compiled.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    Ok(compiled)
+}
+
+/// Returns the string literal passed for `name` in the generated `metadata` function, which Faust
+/// takes from `declare name` or the file name.
+fn get_declared_name(rs_source: &str) -> Option<&str> {
+    const PREFIX: &str = "m.declare(\"name\", ";
+
+    let start = rs_source.find(PREFIX)? + PREFIX.len();
+    let end = start + rs_source[start..].find(");")?;
+    Some(&rs_source[start..end])
+}
+
+/// Returns a string literal with the name Faust declares for `dsp`.
+fn declared_name_literal(dsp: &CompiledDsp) -> io::Result<String> {
+    let rs_source = fs::read_to_string(&dsp.rs_path)?;
+
+    Ok(match get_declared_name(&rs_source) {
+        Some(literal) => literal.to_string(),
+        None => format!("{:?}", dsp.file_name),
+    })
+}
+
+/// Writes a module which includes every engine and lists them in `ENGINES`.
+fn write_engine_registry(engines: &[CompiledDsp], out_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut registry = String::from("// Generated by build.rs from the files in engines/.\n\n");
+
+    for engine in engines {
+        writeln!(registry, "include!({:?});", engine.rs_path)?;
+    }
+
+    for engine in engines {
+        writeln!(registry)?;
+        writeln!(registry, "fn create_{}() -> Box<dyn FaustDsp<T=F32>> {{", engine.file_name)?;
+        writeln!(registry, "    Box::new({}::new())", engine.class_name)?;
+        writeln!(registry, "}}")?;
+    }
+
+    writeln!(registry)?;
+    writeln!(registry, "pub const ENGINES: &[EngineInfo] = &[")?;
+
+    for engine in engines {
+        writeln!(registry, "    EngineInfo {{")?;
+        writeln!(registry, "        file_name: {:?},", engine.file_name)?;
+        writeln!(registry, "        class_name: {:?},", engine.class_name)?;
+        writeln!(registry, "        name: {},", declared_name_literal(engine)?)?;
+        writeln!(registry, "        constructor: create_{},", engine.file_name)?;
+        writeln!(registry, "    }},")?;
+    }
+
+    writeln!(registry, "];")?;
+
+    fs::write(out_path, registry)?;
+    Ok(())
+}
+
+/// Writes a module which includes every effect and defines `register`, which adds them to an
+/// `EffectRegistry` as `faust:<file name>`.
+fn write_effect_registry(effects: &[CompiledDsp], out_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut registry = String::from("// Generated by build.rs from the files in effects/.\n\n");
+
+    for effect in effects {
+        writeln!(registry, "include!({:?});", effect.rs_path)?;
+    }
+
+    writeln!(registry)?;
+    writeln!(registry, "/// Adds the effects compiled from `effects/` to `registry`.")?;
+    writeln!(registry, "pub fn register(registry: &mut EffectRegistry) {{")?;
+
+    for effect in effects {
+        let kind = format!("faust:{}", effect.file_name);
+        let name = declared_name_literal(effect)?;
+        writeln!(registry, "    registry.register({:?}, {}, FaustEffect::<{}>::boxed);", kind, name, effect.class_name)?;
+    }
+
+    writeln!(registry, "}}")?;
+
+    fs::write(out_path, registry)?;
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rerun-if-changed=engines");
+    println!("cargo:rerun-if-changed=effects");
+    println!("cargo:rerun-if-changed=generated");
+    println!("cargo:rerun-if-env-changed=OPERATOR_UPDATE_GENERATED");
+
+    // Hot reloading compiles engines at runtime with libfaust
+    if env::var_os("CARGO_FEATURE_HOT_RELOAD").is_some() {
+        println!("cargo:rerun-if-env-changed=FAUST_LIB_DIR");
+        if let Ok(lib_dir) = env::var("FAUST_LIB_DIR") {
+            println!("cargo:rustc-link-search=native={}", lib_dir);
+        }
+        println!("cargo:rustc-link-lib=faust");
+    }
 
     let temp = env::var("OUT_DIR")?;
     let out_dir = Path::new(&temp);
-    let engines_dir = Path::new("engines");
 
-    for dsp_file in engines_dir.read_dir()? {
-        if let Err(..) = dsp_file {
-            continue;
-        }
+    let engines = compile_dir(Path::new("engines"), out_dir, Path::new("generated/engines"))?;
+    if engines.is_empty() {
+        return Err("no engines could be compiled, and none are pre-generated in generated/engines".into());
+    }
 
-        let dsp_path = dsp_file.unwrap().path();
-        let dsp_name = dsp_path.file_stem().unwrap();
-        let rs_path = out_dir.join(dsp_name).with_extension("rs");
-        let result = process::Command::new("faust")
-            .arg("-wall")
-            .arg("-light")
-            .args(["-lang", "rust"])
-            .args(["-cn", &get_class_name(dsp_name.to_str().unwrap())])
-            .arg(dsp_path)
-            .args(["-o", rs_path.to_str().unwrap()])
-            .output()?;
+    write_engine_registry(&engines, &out_dir.join("engine_registry.rs"))?;
 
-        assert!(result.status.success());
-    }
+    // Effects are generated into their own directory so that they can share names with engines
+    let effects = compile_dir(Path::new("effects"), &out_dir.join("effects"), Path::new("generated/effects"))?;
+    write_effect_registry(&effects, &out_dir.join("effect_registry.rs"))?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,10 @@
+import("stdfaust.lib");
+
+declare name "Auto-pan";
+
+rate = hslider("rate [unit:Hz]", 1, 0.05, 10, 0.01);
+depth = hslider("depth", 1, 0, 1, 0.01) : si.smoo;
+
+pan = os.osc(rate) * depth;
+
+process = *(min(1, 1 - pan)), *(min(1, 1 + pan));
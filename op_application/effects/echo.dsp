@@ -0,0 +1,9 @@
+import("stdfaust.lib");
+
+declare name "Echo";
+
+time = hslider("time [unit:ms]", 300, 1, 2000, 1) / 1000 : si.smoo;
+feedback = hslider("feedback", 0.4, 0, 0.95, 0.01);
+mix = hslider("mix", 0.3, 0, 1, 0.01) : si.smoo;
+
+process = _ <: *(1 - mix), (ef.echo(2, time, feedback) : *(mix)) :> _;
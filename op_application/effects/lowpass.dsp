@@ -0,0 +1,8 @@
+import("stdfaust.lib");
+
+declare name "Resonant low-pass";
+
+freq = hslider("cutoff [unit:Hz]", 2000, 20, 20000, 1) : si.smoo;
+q = hslider("resonance", 1, 0.5, 10, 0.01) : si.smoo;
+
+process = fi.resonlp(freq, q, 1);
@@ -1,6 +1,13 @@
 import("stdfaust.lib");
 
-process = env * os.osc(440 * 2)
+declare name "Metronome";
+
+// Clicks are notes, so that the metronome can accent downbeats with a different pitch and level
+freq = hslider("freq", 1760, 20, 10000, 1);
+gain = hslider("gain", 1, 0, 1, 0.01);
+gate = button("gate");
+
+process = env * gain * os.osc(freq)
     with {
-        env = button("trigger") : ba.impulsify : en.ar(0.001, 0.002);
+        env = gate : en.ar(0.001, 0.02);
     };
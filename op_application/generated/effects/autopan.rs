@@ -0,0 +1,128 @@
+// Stand-in for faust's output for effects/autopan.dsp, written by hand. It is used when faust is
+// not installed. Building with faust and OPERATOR_UPDATE_GENERATED set replaces it with faust's
+// output.
+//
+// os.osc is computed with sin rather than a table.
+
+pub struct Autopan {
+    fSampleRate: i32,
+    fConst0: F32,
+    fConst1: F32,
+    fHslider0: F32,
+    fHslider1: F32,
+    fRec0: F32,
+    fRec1: F32,
+}
+
+impl FaustDsp for Autopan {
+    type T = F32;
+
+    fn new() -> Autopan {
+        Autopan {
+            fSampleRate: 0,
+            fConst0: 0.0,
+            fConst1: 0.0,
+            fHslider0: 0.0,
+            fHslider1: 0.0,
+            fRec0: 0.0,
+            fRec1: 0.0,
+        }
+    }
+
+    fn metadata(&self, m: &mut dyn Meta) {
+        m.declare("filename", "autopan.dsp");
+        m.declare("name", "Auto-pan");
+    }
+
+    fn get_sample_rate(&self) -> i32 {
+        self.fSampleRate
+    }
+
+    fn get_num_inputs(&self) -> i32 {
+        2
+    }
+
+    fn get_num_outputs(&self) -> i32 {
+        2
+    }
+
+    fn class_init(sample_rate: i32) {}
+
+    fn instance_reset_params(&mut self) {
+        self.fHslider0 = 1.0;
+        self.fHslider1 = 1.0;
+    }
+
+    fn instance_clear(&mut self) {
+        self.fRec0 = 0.0;
+        self.fRec1 = 0.0;
+    }
+
+    fn instance_constants(&mut self, sample_rate: i32) {
+        self.fSampleRate = sample_rate;
+        let fConst = (sample_rate as F32).clamp(1.0, 192000.0);
+
+        // Pole of si.smoo
+        self.fConst0 = 1.0 / fConst;
+        self.fConst1 = F32::exp(-200.0 / fConst);
+    }
+
+    fn instance_init(&mut self, sample_rate: i32) {
+        self.instance_constants(sample_rate);
+        self.instance_reset_params();
+        self.instance_clear();
+    }
+
+    fn init(&mut self, sample_rate: i32) {
+        Autopan::class_init(sample_rate);
+        self.instance_init(sample_rate);
+    }
+
+    fn build_user_interface(&self, ui_interface: &mut dyn UI<Self::T>) {
+        Self::build_user_interface_static(ui_interface);
+    }
+
+    fn build_user_interface_static(ui_interface: &mut dyn UI<Self::T>) {
+        ui_interface.open_vertical_box("Auto-pan");
+        ui_interface.declare(Some(ParamIndex(0)), "unit", "Hz");
+        ui_interface.add_horizontal_slider("rate", ParamIndex(0), 1.0, 0.05, 10.0, 0.01);
+        ui_interface.add_horizontal_slider("depth", ParamIndex(1), 1.0, 0.0, 1.0, 0.01);
+        ui_interface.close_box();
+    }
+
+    fn get_param(&self, param: ParamIndex) -> Option<Self::T> {
+        match param.0 {
+            0 => Some(self.fHslider0),
+            1 => Some(self.fHslider1),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, param: ParamIndex, value: Self::T) {
+        match param.0 {
+            0 => self.fHslider0 = value,
+            1 => self.fHslider1 = value,
+            _ => {}
+        }
+    }
+
+    fn compute(&mut self, count: i32, inputs: &[&[Self::T]], outputs: &mut [&mut [Self::T]]) {
+        let count = count as usize;
+        let (outputs0, outputs1) = outputs.split_at_mut(1);
+        let inputs0 = inputs[0][..count].iter();
+        let inputs1 = inputs[1][..count].iter();
+        let outputs0 = outputs0[0][..count].iter_mut();
+        let outputs1 = outputs1[0][..count].iter_mut();
+        let fSlow0 = self.fConst0 * self.fHslider0;
+        let fSlow1 = (1.0 - self.fConst1) * self.fHslider1;
+
+        for (((input0, input1), output0), output1) in inputs0.zip(inputs1).zip(outputs0).zip(outputs1) {
+            self.fRec1 = fSlow1 + self.fConst1 * self.fRec1;
+            let fTemp0 = F32::sin(std::f32::consts::TAU * self.fRec0) * self.fRec1;
+            self.fRec0 = (self.fRec0 + fSlow0).fract();
+
+            *output0 = *input0 * F32::min(1.0, 1.0 - fTemp0);
+            *output1 = *input1 * F32::min(1.0, 1.0 + fTemp0);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// Stand-in for faust's output for effects/echo.dsp, written by hand. It is used when faust is not
+// installed. Building with faust and OPERATOR_UPDATE_GENERATED set replaces it with faust's output.
+//
+// ef.echo's delay is interpolated linearly rather than crossfaded.
+
+/// Length of the echo's delay line, enough for 2 s at 192 kHz.
+const ECHO_DELAY_LENGTH: usize = 1 << 19;
+
+pub struct Echo {
+    fSampleRate: i32,
+    fConst0: F32,
+    fConst1: F32,
+    fHslider0: F32,
+    fHslider1: F32,
+    fHslider2: F32,
+    fRec0: F32,
+    fRec1: F32,
+    IOTA0: usize,
+    fVec0: Vec<F32>,
+}
+
+impl FaustDsp for Echo {
+    type T = F32;
+
+    fn new() -> Echo {
+        Echo {
+            fSampleRate: 0,
+            fConst0: 0.0,
+            fConst1: 0.0,
+            fHslider0: 0.0,
+            fHslider1: 0.0,
+            fHslider2: 0.0,
+            fRec0: 0.0,
+            fRec1: 0.0,
+            IOTA0: 0,
+            fVec0: vec![0.0; ECHO_DELAY_LENGTH],
+        }
+    }
+
+    fn metadata(&self, m: &mut dyn Meta) {
+        m.declare("filename", "echo.dsp");
+        m.declare("name", "Echo");
+    }
+
+    fn get_sample_rate(&self) -> i32 {
+        self.fSampleRate
+    }
+
+    fn get_num_inputs(&self) -> i32 {
+        1
+    }
+
+    fn get_num_outputs(&self) -> i32 {
+        1
+    }
+
+    fn class_init(sample_rate: i32) {}
+
+    fn instance_reset_params(&mut self) {
+        self.fHslider0 = 300.0;
+        self.fHslider1 = 0.4;
+        self.fHslider2 = 0.3;
+    }
+
+    fn instance_clear(&mut self) {
+        self.fRec0 = 0.0;
+        self.fRec1 = 0.0;
+        self.IOTA0 = 0;
+        self.fVec0.fill(0.0);
+    }
+
+    fn instance_constants(&mut self, sample_rate: i32) {
+        self.fSampleRate = sample_rate;
+        let fConst = (sample_rate as F32).clamp(1.0, 192000.0);
+
+        // Sample rate, and the pole of si.smoo
+        self.fConst0 = fConst;
+        self.fConst1 = F32::exp(-200.0 / fConst);
+    }
+
+    fn instance_init(&mut self, sample_rate: i32) {
+        self.instance_constants(sample_rate);
+        self.instance_reset_params();
+        self.instance_clear();
+    }
+
+    fn init(&mut self, sample_rate: i32) {
+        Echo::class_init(sample_rate);
+        self.instance_init(sample_rate);
+    }
+
+    fn build_user_interface(&self, ui_interface: &mut dyn UI<Self::T>) {
+        Self::build_user_interface_static(ui_interface);
+    }
+
+    fn build_user_interface_static(ui_interface: &mut dyn UI<Self::T>) {
+        ui_interface.open_vertical_box("Echo");
+        ui_interface.declare(Some(ParamIndex(0)), "unit", "ms");
+        ui_interface.add_horizontal_slider("time", ParamIndex(0), 300.0, 1.0, 2000.0, 1.0);
+        ui_interface.add_horizontal_slider("feedback", ParamIndex(1), 0.4, 0.0, 0.95, 0.01);
+        ui_interface.add_horizontal_slider("mix", ParamIndex(2), 0.3, 0.0, 1.0, 0.01);
+        ui_interface.close_box();
+    }
+
+    fn get_param(&self, param: ParamIndex) -> Option<Self::T> {
+        match param.0 {
+            0 => Some(self.fHslider0),
+            1 => Some(self.fHslider1),
+            2 => Some(self.fHslider2),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, param: ParamIndex, value: Self::T) {
+        match param.0 {
+            0 => self.fHslider0 = value,
+            1 => self.fHslider1 = value,
+            2 => self.fHslider2 = value,
+            _ => {}
+        }
+    }
+
+    fn compute(&mut self, count: i32, inputs: &[&[Self::T]], outputs: &mut [&mut [Self::T]]) {
+        let count = count as usize;
+        let inputs0 = inputs[0][..count].iter();
+        let outputs0 = outputs[0][..count].iter_mut();
+        let fSlow0 = (1.0 - self.fConst1) * 0.001 * self.fHslider0;
+        let fSlow1 = self.fHslider1;
+        let fSlow2 = (1.0 - self.fConst1) * self.fHslider2;
+
+        for (input0, output0) in inputs0.zip(outputs0) {
+            self.fRec0 = fSlow0 + self.fConst1 * self.fRec0;
+            self.fRec1 = fSlow2 + self.fConst1 * self.fRec1;
+
+            // Delayed output, read between the two nearest samples
+            let fTemp0 = (self.fConst0 * self.fRec0).clamp(1.0, (ECHO_DELAY_LENGTH - 2) as F32);
+            let iTemp1 = fTemp0 as usize;
+            let fTemp2 = fTemp0 - iTemp1 as F32;
+            let fTemp3 = self.fVec0[(self.IOTA0 + ECHO_DELAY_LENGTH - iTemp1) % ECHO_DELAY_LENGTH];
+            let fTemp4 = self.fVec0[(self.IOTA0 + ECHO_DELAY_LENGTH - iTemp1 - 1) % ECHO_DELAY_LENGTH];
+            let fTemp5 = *input0 + fSlow1 * (fTemp3 + fTemp2 * (fTemp4 - fTemp3));
+
+            self.fVec0[self.IOTA0] = fTemp5;
+            self.IOTA0 = (self.IOTA0 + 1) % ECHO_DELAY_LENGTH;
+
+            *output0 = *input0 * (1.0 - self.fRec1) + fTemp5 * self.fRec1;
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// Stand-in for faust's output for effects/lowpass.dsp, written by hand. It is used when faust is
+// not installed. Building with faust and OPERATOR_UPDATE_GENERATED set replaces it with faust's
+// output.
+//
+// fi.resonlp is the bilinear transform of the same analog prototype, in direct form I.
+
+pub struct Lowpass {
+    fSampleRate: i32,
+    fConst0: F32,
+    fConst1: F32,
+    fHslider0: F32,
+    fHslider1: F32,
+    fRec0: F32,
+    fRec1: F32,
+    fVec0: [F32; 2],
+    fRec2: [F32; 2],
+}
+
+impl FaustDsp for Lowpass {
+    type T = F32;
+
+    fn new() -> Lowpass {
+        Lowpass {
+            fSampleRate: 0,
+            fConst0: 0.0,
+            fConst1: 0.0,
+            fHslider0: 0.0,
+            fHslider1: 0.0,
+            fRec0: 0.0,
+            fRec1: 0.0,
+            fVec0: [0.0; 2],
+            fRec2: [0.0; 2],
+        }
+    }
+
+    fn metadata(&self, m: &mut dyn Meta) {
+        m.declare("filename", "lowpass.dsp");
+        m.declare("name", "Resonant low-pass");
+    }
+
+    fn get_sample_rate(&self) -> i32 {
+        self.fSampleRate
+    }
+
+    fn get_num_inputs(&self) -> i32 {
+        1
+    }
+
+    fn get_num_outputs(&self) -> i32 {
+        1
+    }
+
+    fn class_init(sample_rate: i32) {}
+
+    fn instance_reset_params(&mut self) {
+        self.fHslider0 = 2000.0;
+        self.fHslider1 = 1.0;
+    }
+
+    fn instance_clear(&mut self) {
+        self.fRec0 = 0.0;
+        self.fRec1 = 0.0;
+        self.fVec0 = [0.0; 2];
+        self.fRec2 = [0.0; 2];
+    }
+
+    fn instance_constants(&mut self, sample_rate: i32) {
+        self.fSampleRate = sample_rate;
+        let fConst = (sample_rate as F32).clamp(1.0, 192000.0);
+
+        // Sample rate, and the pole of si.smoo
+        self.fConst0 = fConst;
+        self.fConst1 = F32::exp(-200.0 / fConst);
+    }
+
+    fn instance_init(&mut self, sample_rate: i32) {
+        self.instance_constants(sample_rate);
+        self.instance_reset_params();
+        self.instance_clear();
+    }
+
+    fn init(&mut self, sample_rate: i32) {
+        Lowpass::class_init(sample_rate);
+        self.instance_init(sample_rate);
+    }
+
+    fn build_user_interface(&self, ui_interface: &mut dyn UI<Self::T>) {
+        Self::build_user_interface_static(ui_interface);
+    }
+
+    fn build_user_interface_static(ui_interface: &mut dyn UI<Self::T>) {
+        ui_interface.open_vertical_box("Resonant low-pass");
+        ui_interface.declare(Some(ParamIndex(0)), "unit", "Hz");
+        ui_interface.add_horizontal_slider("cutoff", ParamIndex(0), 2000.0, 20.0, 20000.0, 1.0);
+        ui_interface.add_horizontal_slider("resonance", ParamIndex(1), 1.0, 0.5, 10.0, 0.01);
+        ui_interface.close_box();
+    }
+
+    fn get_param(&self, param: ParamIndex) -> Option<Self::T> {
+        match param.0 {
+            0 => Some(self.fHslider0),
+            1 => Some(self.fHslider1),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, param: ParamIndex, value: Self::T) {
+        match param.0 {
+            0 => self.fHslider0 = value,
+            1 => self.fHslider1 = value,
+            _ => {}
+        }
+    }
+
+    fn compute(&mut self, count: i32, inputs: &[&[Self::T]], outputs: &mut [&mut [Self::T]]) {
+        let count = count as usize;
+        let inputs0 = inputs[0][..count].iter();
+        let outputs0 = outputs[0][..count].iter_mut();
+        let fSlow0 = (1.0 - self.fConst1) * self.fHslider0;
+        let fSlow1 = (1.0 - self.fConst1) * self.fHslider1;
+
+        for (input0, output0) in inputs0.zip(outputs0) {
+            self.fRec0 = fSlow0 + self.fConst1 * self.fRec0;
+            self.fRec1 = fSlow1 + self.fConst1 * self.fRec1;
+
+            let fTemp0 = std::f32::consts::TAU * F32::min(self.fRec0, 0.49 * self.fConst0) / self.fConst0;
+            let (fTemp1, fTemp2) = (F32::sin(fTemp0), F32::cos(fTemp0));
+            let fTemp3 = fTemp1 / (2.0 * self.fRec1);
+            let fTemp4 = 1.0 / (1.0 + fTemp3);
+            let fTemp5 = 0.5 * (1.0 - fTemp2) * fTemp4;
+
+            let fTemp6 = fTemp5 * (*input0 + 2.0 * self.fVec0[0] + self.fVec0[1])
+                + fTemp4 * (2.0 * fTemp2 * self.fRec2[0] - (1.0 - fTemp3) * self.fRec2[1]);
+
+            self.fVec0 = [*input0, self.fVec0[0]];
+            self.fRec2 = [fTemp6, self.fRec2[0]];
+            *output0 = fTemp6;
+        }
+    }
+}
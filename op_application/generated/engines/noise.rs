@@ -0,0 +1,138 @@
+// Stand-in for faust's output for engines/noise.dsp, written by hand. It is used when faust is not
+// installed. Building with faust and OPERATOR_UPDATE_GENERATED set replaces it with faust's output.
+//
+// en.asr ramps linearly. no.noise uses the same generator as Faust's library.
+
+pub struct Noise {
+    fSampleRate: i32,
+    fConst0: F32,
+    fConst1: F32,
+    fConst2: F32,
+    fHslider0: F32,
+    fHslider1: F32,
+    fCheckbox0: F32,
+    fRec0: F32,
+    iRec1: i32,
+    fRec2: F32,
+}
+
+impl FaustDsp for Noise {
+    type T = F32;
+
+    fn new() -> Noise {
+        Noise {
+            fSampleRate: 0,
+            fConst0: 0.0,
+            fConst1: 0.0,
+            fConst2: 0.0,
+            fHslider0: 0.0,
+            fHslider1: 0.0,
+            fCheckbox0: 0.0,
+            fRec0: 0.0,
+            iRec1: 0,
+            fRec2: 0.0,
+        }
+    }
+
+    fn metadata(&self, m: &mut dyn Meta) {
+        m.declare("filename", "noise.dsp");
+        m.declare("name", "Noise");
+    }
+
+    fn get_sample_rate(&self) -> i32 {
+        self.fSampleRate
+    }
+
+    fn get_num_inputs(&self) -> i32 {
+        0
+    }
+
+    fn get_num_outputs(&self) -> i32 {
+        1
+    }
+
+    fn class_init(sample_rate: i32) {}
+
+    fn instance_reset_params(&mut self) {
+        self.fHslider0 = -20.0;
+        self.fHslider1 = 440.0;
+        self.fCheckbox0 = 0.0;
+    }
+
+    fn instance_clear(&mut self) {
+        self.fRec0 = 0.0;
+        self.iRec1 = 0;
+        self.fRec2 = 0.0;
+    }
+
+    fn instance_constants(&mut self, sample_rate: i32) {
+        self.fSampleRate = sample_rate;
+        let fConst = (sample_rate as F32).clamp(1.0, 192000.0);
+
+        // Pole of si.smoo, then the per-sample steps of the attack and release of en.asr
+        self.fConst0 = F32::exp(-200.0 / fConst);
+        self.fConst1 = 1.0 / (0.05 * fConst);
+        self.fConst2 = 1.0 / (0.1 * fConst);
+    }
+
+    fn instance_init(&mut self, sample_rate: i32) {
+        self.instance_constants(sample_rate);
+        self.instance_reset_params();
+        self.instance_clear();
+    }
+
+    fn init(&mut self, sample_rate: i32) {
+        Noise::class_init(sample_rate);
+        self.instance_init(sample_rate);
+    }
+
+    fn build_user_interface(&self, ui_interface: &mut dyn UI<Self::T>) {
+        Self::build_user_interface_static(ui_interface);
+    }
+
+    fn build_user_interface_static(ui_interface: &mut dyn UI<Self::T>) {
+        ui_interface.open_vertical_box("Noise");
+        ui_interface.declare(Some(ParamIndex(0)), "unit", "dB");
+        ui_interface.add_horizontal_slider("volume", ParamIndex(0), -20.0, -96.0, 0.0, 0.1);
+        ui_interface.declare(Some(ParamIndex(1)), "unit", "Hz");
+        ui_interface.add_horizontal_slider("freq", ParamIndex(1), 440.0, 20.0, 24000.0, 1.0);
+        ui_interface.add_check_button("gate", ParamIndex(2));
+        ui_interface.close_box();
+    }
+
+    fn get_param(&self, param: ParamIndex) -> Option<Self::T> {
+        match param.0 {
+            0 => Some(self.fHslider0),
+            1 => Some(self.fHslider1),
+            2 => Some(self.fCheckbox0),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, param: ParamIndex, value: Self::T) {
+        match param.0 {
+            0 => self.fHslider0 = value,
+            1 => self.fHslider1 = value,
+            2 => self.fCheckbox0 = value,
+            _ => {}
+        }
+    }
+
+    fn compute(&mut self, count: i32, inputs: &[&[Self::T]], outputs: &mut [&mut [Self::T]]) {
+        let outputs0 = outputs[0][..count as usize].iter_mut();
+        let fSlow0 = (1.0 - self.fConst0) * F32::powf(10.0, 0.05 * self.fHslider0);
+        let iSlow1 = self.fCheckbox0 > 0.0;
+
+        for output0 in outputs0 {
+            self.fRec0 = fSlow0 + self.fConst0 * self.fRec0;
+            self.iRec1 = self.iRec1.wrapping_mul(1103515245).wrapping_add(12345);
+            self.fRec2 = if iSlow1 {
+                F32::min(1.0, self.fRec2 + self.fConst1)
+            } else {
+                F32::max(0.0, self.fRec2 - self.fConst2)
+            };
+
+            *output0 = (self.iRec1 as F32 / i32::MAX as F32) * self.fRec2 * self.fRec0;
+        }
+    }
+}
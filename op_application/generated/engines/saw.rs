@@ -0,0 +1,199 @@
+// Stand-in for faust's output for engines/saw.dsp, written by hand. It is used when faust is not
+// installed. Building with faust and OPERATOR_UPDATE_GENERATED set replaces it with faust's output.
+//
+// os.sawtooth is antialiased with polyBLEP rather than Faust's polynomial transition regions,
+// os.osc is computed with sin rather than a table, and en.asr ramps linearly.
+
+pub struct Saw {
+    fSampleRate: i32,
+    fConst0: F32,
+    fConst1: F32,
+    fConst2: [F32; 3],
+    fConst3: [F32; 3],
+    fConst4: [F32; 3],
+    fConst5: [F32; 3],
+    fConst6: [F32; 3],
+    fHslider0: F32,
+    fHslider1: F32,
+    fCheckbox0: F32,
+    fRec0: F32,
+    fRec1: F32,
+    fRec2: [F32; 3],
+    fRec3: [F32; 3],
+    fVec0: [F32; 3],
+    fRec4: [F32; 3],
+}
+
+impl FaustDsp for Saw {
+    type T = F32;
+
+    fn new() -> Saw {
+        Saw {
+            fSampleRate: 0,
+            fConst0: 0.0,
+            fConst1: 0.0,
+            fConst2: [0.0; 3],
+            fConst3: [0.0; 3],
+            fConst4: [0.0; 3],
+            fConst5: [0.0; 3],
+            fConst6: [0.0; 3],
+            fHslider0: 0.0,
+            fHslider1: 0.0,
+            fCheckbox0: 0.0,
+            fRec0: 0.0,
+            fRec1: 0.0,
+            fRec2: [0.0; 3],
+            fRec3: [0.0; 3],
+            fVec0: [0.0; 3],
+            fRec4: [0.0; 3],
+        }
+    }
+
+    fn metadata(&self, m: &mut dyn Meta) {
+        m.declare("filename", "saw.dsp");
+        m.declare("name", "Saw");
+    }
+
+    fn get_sample_rate(&self) -> i32 {
+        self.fSampleRate
+    }
+
+    fn get_num_inputs(&self) -> i32 {
+        0
+    }
+
+    fn get_num_outputs(&self) -> i32 {
+        1
+    }
+
+    fn class_init(sample_rate: i32) {}
+
+    fn instance_reset_params(&mut self) {
+        self.fHslider0 = -20.0;
+        self.fHslider1 = 440.0;
+        self.fCheckbox0 = 0.0;
+    }
+
+    fn instance_clear(&mut self) {
+        self.fRec0 = 0.0;
+        self.fRec1 = 0.0;
+        self.fRec2 = [0.0; 3];
+        self.fRec3 = [0.0; 3];
+        self.fVec0 = [0.0; 3];
+        self.fRec4 = [0.0; 3];
+    }
+
+    fn instance_constants(&mut self, sample_rate: i32) {
+        self.fSampleRate = sample_rate;
+        let fConst = (sample_rate as F32).clamp(1.0, 192000.0);
+        self.fConst0 = 1.0 / fConst;
+
+        // Pole of si.smoo
+        self.fConst1 = F32::exp(-200.0 / fConst);
+
+        // Attack, sustain and release of each oscillator's en.asr, and the cutoff of its
+        // fi.lowpass
+        let envelopes = [(0.05, 1.0, 0.1, 10000.0), (0.1, 0.9, 0.4, 3000.0), (0.2, 1.0, 0.5, 600.0)];
+        for (i, (attack, sustain, release, cutoff)) in envelopes.into_iter().enumerate() {
+            let fTemp0 = 1.0 / F32::tan(std::f32::consts::PI * F32::min(cutoff, 0.49 * fConst) / fConst);
+            self.fConst2[i] = sustain / (attack * fConst);
+            self.fConst3[i] = sustain;
+            self.fConst4[i] = sustain / (release * fConst);
+            self.fConst5[i] = 1.0 / (1.0 + fTemp0);
+            self.fConst6[i] = (1.0 - fTemp0) / (1.0 + fTemp0);
+        }
+    }
+
+    fn instance_init(&mut self, sample_rate: i32) {
+        self.instance_constants(sample_rate);
+        self.instance_reset_params();
+        self.instance_clear();
+    }
+
+    fn init(&mut self, sample_rate: i32) {
+        Saw::class_init(sample_rate);
+        self.instance_init(sample_rate);
+    }
+
+    fn build_user_interface(&self, ui_interface: &mut dyn UI<Self::T>) {
+        Self::build_user_interface_static(ui_interface);
+    }
+
+    fn build_user_interface_static(ui_interface: &mut dyn UI<Self::T>) {
+        ui_interface.open_vertical_box("Saw");
+        ui_interface.declare(Some(ParamIndex(0)), "unit", "dB");
+        ui_interface.add_horizontal_slider("volume", ParamIndex(0), -20.0, -96.0, 0.0, 0.1);
+        ui_interface.declare(Some(ParamIndex(1)), "unit", "Hz");
+        ui_interface.add_horizontal_slider("freq", ParamIndex(1), 440.0, 20.0, 24000.0, 1.0);
+        ui_interface.add_check_button("gate", ParamIndex(2));
+        ui_interface.close_box();
+    }
+
+    fn get_param(&self, param: ParamIndex) -> Option<Self::T> {
+        match param.0 {
+            0 => Some(self.fHslider0),
+            1 => Some(self.fHslider1),
+            2 => Some(self.fCheckbox0),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, param: ParamIndex, value: Self::T) {
+        match param.0 {
+            0 => self.fHslider0 = value,
+            1 => self.fHslider1 = value,
+            2 => self.fCheckbox0 = value,
+            _ => {}
+        }
+    }
+
+    fn compute(&mut self, count: i32, inputs: &[&[Self::T]], outputs: &mut [&mut [Self::T]]) {
+        let outputs0 = outputs[0][..count as usize].iter_mut();
+        let fSlow0 = (1.0 - self.fConst1) * F32::powf(10.0, 0.05 * self.fHslider0);
+        let fSlow1 = self.fConst0 * self.fHslider1;
+        let fSlow2 = fSlow1 * F32::powf(2.0, -24.0 / 12.0) * F32::powf(2.0, 1.0 / 1200.0);
+        let iSlow3 = self.fCheckbox0 > 0.0;
+        let fSlow4 = 10.0 * self.fConst0;
+
+        for output0 in outputs0 {
+            self.fRec0 = fSlow0 + self.fConst1 * self.fRec0;
+
+            // Vibrato of the second oscillator
+            let fTemp0 = F32::powf(2.0, (-1.5 - 10.0 * F32::sin(std::f32::consts::TAU * self.fRec1)) / 1200.0);
+            self.fRec1 = (self.fRec1 + fSlow4).fract();
+
+            let fTemp1 = [fSlow1, fSlow1 * fTemp0, fSlow2];
+            let mut fTemp2 = [0.0; 3];
+
+            for i in 0..3 {
+                // Sawtooth from the phase, with polyBLEP smoothing its discontinuity
+                let (fPhase, fDelta) = (self.fRec2[i], F32::min(fTemp1[i], 0.5));
+                let fBlep = if fPhase < fDelta {
+                    let x = fPhase / fDelta;
+                    2.0 * x - x * x - 1.0
+                } else if fPhase > 1.0 - fDelta {
+                    let x = (fPhase - 1.0) / fDelta;
+                    x * x + 2.0 * x + 1.0
+                } else {
+                    0.0
+                };
+
+                let fSaw = 2.0 * fPhase - 1.0 - fBlep;
+                self.fRec2[i] = (fPhase + fDelta).fract();
+
+                self.fRec3[i] = self.fConst5[i] * (fSaw + self.fVec0[i]) - self.fConst6[i] * self.fRec3[i];
+                self.fVec0[i] = fSaw;
+
+                self.fRec4[i] = if iSlow3 {
+                    F32::min(self.fConst3[i], self.fRec4[i] + self.fConst2[i])
+                } else {
+                    F32::max(0.0, self.fRec4[i] - self.fConst4[i])
+                };
+
+                fTemp2[i] = self.fRec4[i] * self.fRec3[i];
+            }
+
+            *output0 = (0.7 * fTemp2[0] + 0.7 * fTemp2[1] + 0.9 * fTemp2[2]) * self.fRec0;
+        }
+    }
+}
@@ -1,12 +1,19 @@
+use std::collections::HashMap;
+
 use midly::MidiMessage;
 
+use op_engine::effect::{Effect, EffectParam};
 use op_engine::generator::Generator;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ParamIndex(pub i32);
 
 pub type F32 = f32;
 
+/// Most inputs or outputs a DSP can have. Their buffers are passed to `compute` in arrays of this
+/// size, so that nothing is allocated on the audio thread.
+pub const MAX_PORTS: usize = 32;
+
 pub trait FaustDsp: Send {
     type T;
 
@@ -55,16 +62,289 @@ pub trait UI<T> {
     fn declare(&mut self, param: Option<ParamIndex>, key: &str, value: &str);
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WidgetKind {
+    Button,
+    CheckButton,
+    VerticalSlider,
+    HorizontalSlider,
+    NumEntry,
+    HorizontalBargraph,
+    VerticalBargraph,
+}
+
+/// A widget declared by a DSP's user interface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamInfo {
+    pub label: String,
+    pub index: ParamIndex,
+    pub kind: WidgetKind,
+    pub init: F32,
+    pub min: F32,
+    pub max: F32,
+    pub step: F32,
+
+    /// Metadata declared on the widget, such as `[unit:dB]`, in declaration order.
+    pub metadata: Vec<(String, String)>,
+}
+
+impl ParamInfo {
+    fn new(label: &str, index: ParamIndex, kind: WidgetKind, init: F32, min: F32, max: F32, step: F32) -> Self {
+        Self {
+            label: label.to_string(),
+            index,
+            kind,
+            init,
+            min,
+            max,
+            step,
+            metadata: Vec::new(),
+        }
+    }
+
+    pub fn meta(&self, key: &str) -> Option<&str> {
+        self.metadata.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn unit(&self) -> Option<&str> {
+        self.meta("unit")
+    }
+
+    /// Whether the parameter can be set. Bargraphs are outputs of the DSP.
+    pub fn is_input(&self) -> bool {
+        !matches!(self.kind, WidgetKind::HorizontalBargraph | WidgetKind::VerticalBargraph)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GroupKind {
+    Tab,
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UiItem {
+    Group {
+        kind: GroupKind,
+        label: String,
+        items: Vec<UiItem>,
+    },
+
+    /// A widget, as an index into `DspUi::params`.
+    Param(usize),
+}
+
+/// The user interface declared by a DSP: its widgets, and how they are laid out in groups.
+#[derive(Debug, Clone, Default)]
+pub struct DspUi {
+    pub params: Vec<ParamInfo>,
+    pub items: Vec<UiItem>,
+}
+
+/// Records every widget of a DSP's user interface.
+#[derive(Default)]
+struct UiCollector {
+    ui: DspUi,
+    metadata: HashMap<ParamIndex, Vec<(String, String)>>,
+
+    // Groups which have been opened but not closed yet
+    open_groups: Vec<(GroupKind, String, Vec<UiItem>)>,
+}
+
+impl UiCollector {
+    fn push_item(&mut self, item: UiItem) {
+        match self.open_groups.last_mut() {
+            Some((_, _, items)) => items.push(item),
+            None => self.ui.items.push(item),
+        }
+    }
+
+    fn open_group(&mut self, kind: GroupKind, label: &str) {
+        self.open_groups.push((kind, label.to_string(), Vec::new()));
+    }
+
+    fn add(&mut self, param: ParamInfo) {
+        self.ui.params.push(param);
+        self.push_item(UiItem::Param(self.ui.params.len() - 1));
+    }
+}
+
+impl UI<F32> for UiCollector {
+    fn open_tab_box(&mut self, label: &str) {
+        self.open_group(GroupKind::Tab, label);
+    }
+
+    fn open_horizontal_box(&mut self, label: &str) {
+        self.open_group(GroupKind::Horizontal, label);
+    }
+
+    fn open_vertical_box(&mut self, label: &str) {
+        self.open_group(GroupKind::Vertical, label);
+    }
+
+    fn close_box(&mut self) {
+        if let Some((kind, label, items)) = self.open_groups.pop() {
+            self.push_item(UiItem::Group { kind, label, items });
+        }
+    }
+
+    fn add_button(&mut self, label: &str, param: ParamIndex) {
+        self.add(ParamInfo::new(label, param, WidgetKind::Button, 0.0, 0.0, 1.0, 1.0));
+    }
+
+    fn add_check_button(&mut self, label: &str, param: ParamIndex) {
+        self.add(ParamInfo::new(label, param, WidgetKind::CheckButton, 0.0, 0.0, 1.0, 1.0));
+    }
+
+    fn add_vertical_slider(&mut self, label: &str, param: ParamIndex, init: F32, min: F32, max: F32, step: F32) {
+        self.add(ParamInfo::new(label, param, WidgetKind::VerticalSlider, init, min, max, step));
+    }
+
+    fn add_horizontal_slider(&mut self, label: &str, param: ParamIndex, init: F32, min: F32, max: F32, step: F32) {
+        self.add(ParamInfo::new(label, param, WidgetKind::HorizontalSlider, init, min, max, step));
+    }
+
+    fn add_num_entry(&mut self, label: &str, param: ParamIndex, init: F32, min: F32, max: F32, step: F32) {
+        self.add(ParamInfo::new(label, param, WidgetKind::NumEntry, init, min, max, step));
+    }
+
+    fn add_horizontal_bargraph(&mut self, label: &str, param: ParamIndex, min: F32, max: F32) {
+        self.add(ParamInfo::new(label, param, WidgetKind::HorizontalBargraph, min, min, max, 0.0));
+    }
+
+    fn add_vertical_bargraph(&mut self, label: &str, param: ParamIndex, min: F32, max: F32) {
+        self.add(ParamInfo::new(label, param, WidgetKind::VerticalBargraph, min, min, max, 0.0));
+    }
+
+    fn declare(&mut self, param: Option<ParamIndex>, key: &str, value: &str) {
+        // Metadata without a parameter belongs to the enclosing box
+        if let Some(param) = param {
+            self.metadata.entry(param).or_default().push((key.to_string(), value.to_string()));
+        }
+    }
+}
+
+/// Walks `dsp`'s user interface.
+pub fn describe_ui(dsp: &dyn FaustDsp<T=F32>) -> DspUi {
+    let mut collector = UiCollector::default();
+    dsp.build_user_interface(&mut collector);
+
+    // Close any groups the DSP left open
+    while !collector.open_groups.is_empty() {
+        collector.close_box();
+    }
+
+    let mut ui = collector.ui;
+    for param in ui.params.iter_mut() {
+        if let Some(metadata) = collector.metadata.remove(&param.index) {
+            param.metadata = metadata;
+        }
+    }
+
+    ui
+}
+
+/// Returns every widget of `dsp`'s user interface, in declaration order.
+pub fn discover_params(dsp: &dyn FaustDsp<T=F32>) -> Vec<ParamInfo> {
+    describe_ui(dsp).params
+}
+
+/// Whether `param` is set from MIDI notes rather than by the user.
+pub fn is_note_param(param: &ParamInfo) -> bool {
+    param.is_input() && note_param_slot(&param.label).is_some()
+}
+
+#[derive(Copy, Clone)]
+enum NoteParam {
+    Freq,
+    Key,
+    Gate,
+    Gain,
+    Velocity,
+}
+
+fn note_param_slot(label: &str) -> Option<NoteParam> {
+    match label.to_lowercase().as_str() {
+        "freq" => Some(NoteParam::Freq),
+        "key" => Some(NoteParam::Key),
+        "gate" => Some(NoteParam::Gate),
+        "gain" => Some(NoteParam::Gain),
+        "vel" | "velocity" => Some(NoteParam::Velocity),
+        _ => None,
+    }
+}
+
+/// Parameters which are set from MIDI notes, found by their conventional labels.
+#[derive(Default)]
+struct NoteParams {
+    /// Frequency of the note in Hz.
+    freq: Option<ParamIndex>,
+
+    /// MIDI note number.
+    key: Option<ParamIndex>,
+
+    /// 1 while the note is held, 0 otherwise.
+    gate: Option<ParamIndex>,
+
+    /// Velocity scaled to 0..1.
+    gain: Option<ParamIndex>,
+
+    /// MIDI velocity, 0..127.
+    velocity: Option<ParamIndex>,
+}
+
+impl NoteParams {
+    fn find(params: &[ParamInfo]) -> Self {
+        let mut notes = NoteParams::default();
+
+        for param in params.iter().filter(|p| p.is_input()) {
+            let slot = match note_param_slot(&param.label) {
+                Some(NoteParam::Freq) => &mut notes.freq,
+                Some(NoteParam::Key) => &mut notes.key,
+                Some(NoteParam::Gate) => &mut notes.gate,
+                Some(NoteParam::Gain) => &mut notes.gain,
+                Some(NoteParam::Velocity) => &mut notes.velocity,
+                None => continue,
+            };
+
+            slot.get_or_insert(param.index);
+        }
+
+        notes
+    }
+}
+
+/// Runs a Faust DSP as a generator. Its inputs are fed silence, and its outputs are averaged.
 pub struct FaustGenerator {
     faust_dsp: Box<dyn FaustDsp<T=F32>>,
+    notes: NoteParams,
     last_note: u8,
+
+    // Fed to every input of the DSP
+    silence: Vec<F32>,
+
+    // Scratch buffers for DSPs with more than one output, which are averaged into the output
+    outputs: Vec<Vec<F32>>,
 }
 
 impl FaustGenerator {
     pub fn new(faust_dsp: Box<dyn FaustDsp<T=F32>>) -> Self {
+        let notes = NoteParams::find(&discover_params(faust_dsp.as_ref()));
+        let num_outputs = faust_dsp.get_num_outputs() as usize;
+
         Self {
             faust_dsp,
+            notes,
             last_note: 0,
+            silence: Vec::new(),
+            outputs: if num_outputs > 1 { vec![Vec::new(); num_outputs] } else { Vec::new() },
+        }
+    }
+
+    fn set(&mut self, param: Option<ParamIndex>, value: F32) {
+        if let Some(param) = param {
+            self.faust_dsp.set_param(param, value);
         }
     }
 }
@@ -74,26 +354,529 @@ fn midi_note_to_hz(note: u8) -> f64 {
 }
 
 impl Generator for FaustGenerator {
-    fn next(&mut self) -> f32 {
-        let input = [0.0; 1];
-        let mut output = [0.0; 1];
-        self.faust_dsp.compute(1, &[&input], &mut [&mut output]);
-        output[0]
+    fn process(&mut self, out: &mut [f32]) {
+        let frames = out.len();
+        for buf in Some(&mut self.silence).into_iter().chain(self.outputs.iter_mut()) {
+            if buf.len() < frames {
+                buf.resize(frames, 0.0);
+            }
+        }
+
+        let num_inputs = self.faust_dsp.get_num_inputs() as usize;
+        let num_outputs = self.faust_dsp.get_num_outputs() as usize;
+        if num_inputs > MAX_PORTS || num_outputs > MAX_PORTS || num_outputs == 0 {
+            out.fill(0.0);
+            return;
+        }
+
+        let inputs: [&[F32]; MAX_PORTS] = [&self.silence[..frames]; MAX_PORTS];
+        if num_outputs == 1 {
+            self.faust_dsp.compute(frames as i32, &inputs[..num_inputs], &mut [out]);
+            return;
+        }
+
+        let mut outputs: [&mut [F32]; MAX_PORTS] = Default::default();
+        for (output, buf) in outputs.iter_mut().zip(self.outputs.iter_mut()) {
+            *output = &mut buf[..frames];
+        }
+
+        self.faust_dsp.compute(frames as i32, &inputs[..num_inputs], &mut outputs[..num_outputs]);
+
+        for (i, sample) in out.iter_mut().enumerate() {
+            *sample = self.outputs.iter().map(|output| output[i]).sum::<F32>() / num_outputs as F32;
+        }
     }
 
     fn handle(&mut self, msg: MidiMessage) {
         match msg {
-            MidiMessage::NoteOn { key, .. } => {
-                self.last_note = key.as_int();
-                self.faust_dsp.set_param(ParamIndex(0), midi_note_to_hz(key.into()) as f32); // freq
-                self.faust_dsp.set_param(ParamIndex(1), 1.0); // gate
+            MidiMessage::NoteOn { key, vel } => {
+                let (key, vel) = (key.as_int(), vel.as_int());
+                self.last_note = key;
+                self.set(self.notes.freq, midi_note_to_hz(key) as F32);
+                self.set(self.notes.key, key as F32);
+                self.set(self.notes.gain, vel as F32 / 127.0);
+                self.set(self.notes.velocity, vel as F32);
+                self.set(self.notes.gate, 1.0);
             }
             MidiMessage::NoteOff { key, .. } => {
                 if self.last_note == key.as_int() {
-                    self.faust_dsp.set_param(ParamIndex(1), 0.0);
+                    self.set(self.notes.gate, 0.0);
                 }
             }
             _ => ()
         }
     }
-}
\ No newline at end of file
+
+    fn set_param(&mut self, param: usize, value: f32) {
+        self.faust_dsp.set_param(ParamIndex(param as i32), value);
+    }
+
+    fn get_param(&self, param: usize) -> Option<f32> {
+        self.faust_dsp.get_param(ParamIndex(param as i32))
+    }
+}
+
+/// Runs a Faust DSP as an insert effect.
+///
+/// DSPs with one input and one output are treated as mono effects and get one instance per
+/// channel. Other DSPs run as a single instance: input `i` is fed channel `i % channels`, and each
+/// channel receives the outputs that map onto it the same way. DSPs with more than [MAX_PORTS]
+/// inputs or outputs pass audio through unchanged.
+pub struct FaustEffect<D: FaustDsp<T=F32>> {
+    sample_rate: u32,
+    instances: Vec<D>,
+    params: Vec<ParamInfo>,
+
+    // Non-interleaved scratch buffers passed to compute
+    inputs: Vec<Vec<F32>>,
+    outputs: Vec<Vec<F32>>,
+}
+
+impl<D: FaustDsp<T=F32> + 'static> FaustEffect<D> {
+    pub fn new(sample_rate: u32) -> Self {
+        let dsp = Self::instance(sample_rate);
+        let params = discover_params(&dsp).into_iter().filter(|p| p.is_input()).collect();
+
+        Self {
+            sample_rate,
+            instances: vec![dsp],
+            params,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn boxed(sample_rate: u32) -> Box<dyn Effect> {
+        Box::new(Self::new(sample_rate))
+    }
+
+    fn instance(sample_rate: u32) -> D {
+        let mut dsp = D::new();
+        dsp.init(sample_rate as i32);
+        dsp
+    }
+
+    fn is_mono(&self) -> bool {
+        let dsp = &self.instances[0];
+        dsp.get_num_inputs() == 1 && dsp.get_num_outputs() == 1
+    }
+
+    /// Creates instances until there is one for each channel. New instances copy the parameters of
+    /// the first one.
+    fn add_instances(&mut self, count: usize) {
+        while self.instances.len() < count {
+            let mut dsp = Self::instance(self.sample_rate);
+
+            for param in &self.params {
+                if let Some(value) = self.instances[0].get_param(param.index) {
+                    dsp.set_param(param.index, value);
+                }
+            }
+
+            self.instances.push(dsp);
+        }
+    }
+
+    fn resize_scratch(&mut self, inputs: usize, outputs: usize, frames: usize) {
+        self.inputs.resize(inputs, Vec::new());
+        self.outputs.resize(outputs, Vec::new());
+
+        for buf in self.inputs.iter_mut().chain(self.outputs.iter_mut()) {
+            if buf.len() < frames {
+                buf.resize(frames, 0.0);
+            }
+        }
+    }
+
+    fn compute(&mut self, instance: usize, frames: usize) {
+        let (num_inputs, num_outputs) = (self.inputs.len(), self.outputs.len());
+        let mut inputs: [&[F32]; MAX_PORTS] = Default::default();
+        let mut outputs: [&mut [F32]; MAX_PORTS] = Default::default();
+
+        for (input, buf) in inputs.iter_mut().zip(self.inputs.iter()) {
+            *input = &buf[..frames];
+        }
+
+        for (output, buf) in outputs.iter_mut().zip(self.outputs.iter_mut()) {
+            *output = &mut buf[..frames];
+        }
+
+        self.instances[instance].compute(frames as i32, &inputs[..num_inputs], &mut outputs[..num_outputs]);
+    }
+}
+
+impl<D: FaustDsp<T=F32> + 'static> Effect for FaustEffect<D> {
+    fn params(&self) -> Vec<EffectParam> {
+        self.params
+            .iter()
+            .map(|p| EffectParam::new(&p.label, p.min, p.max, p.init))
+            .collect()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        let index = match self.params.iter().find(|p| p.label == name) {
+            Some(param) => param.index,
+            None => return,
+        };
+
+        for dsp in self.instances.iter_mut() {
+            dsp.set_param(index, value);
+        }
+    }
+
+    fn process(&mut self, buf: &mut [f32], channels: usize) {
+        let frames = buf.len() / channels;
+        if frames == 0 {
+            return;
+        }
+
+        if self.is_mono() {
+            self.add_instances(channels);
+            self.resize_scratch(1, 1, frames);
+
+            for channel in 0..channels {
+                for (input, frame) in self.inputs[0].iter_mut().zip(buf.chunks_exact(channels)) {
+                    *input = frame[channel];
+                }
+
+                self.compute(channel, frames);
+
+                for (output, frame) in self.outputs[0].iter().zip(buf.chunks_exact_mut(channels)) {
+                    frame[channel] = *output;
+                }
+            }
+
+            return;
+        }
+
+        let num_inputs = self.instances[0].get_num_inputs() as usize;
+        let num_outputs = self.instances[0].get_num_outputs() as usize;
+        if num_inputs > MAX_PORTS || num_outputs > MAX_PORTS {
+            return;
+        }
+
+        self.resize_scratch(num_inputs, num_outputs, frames);
+
+        for (i, input) in self.inputs.iter_mut().enumerate() {
+            for (input, frame) in input.iter_mut().zip(buf.chunks_exact(channels)) {
+                *input = frame[i % channels];
+            }
+        }
+
+        self.compute(0, frames);
+
+        if num_outputs == 0 {
+            return;
+        }
+
+        for (i, frame) in buf.chunks_exact_mut(channels).enumerate() {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = if num_outputs >= channels {
+                    let sources = (channel..num_outputs).step_by(channels);
+                    let count = sources.len();
+                    sources.map(|o| self.outputs[o][i]).sum::<F32>() / count as F32
+                } else {
+                    self.outputs[channel % num_outputs][i]
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Declares the user interface given to it. Output `o` is the value of its first parameter,
+    /// divided by `o + 1`.
+    struct StubDsp {
+        ui: fn(&mut dyn UI<F32>),
+        params: HashMap<ParamIndex, F32>,
+        inputs: usize,
+        outputs: usize,
+    }
+
+    impl StubDsp {
+        fn with_ui(ui: fn(&mut dyn UI<F32>)) -> Self {
+            Self { ui, params: HashMap::new(), inputs: 0, outputs: 1 }
+        }
+    }
+
+    impl FaustDsp for StubDsp {
+        type T = F32;
+
+        fn new() -> Self {
+            Self::with_ui(|_| ())
+        }
+
+        fn metadata(&self, _m: &mut dyn Meta) {}
+        fn get_sample_rate(&self) -> i32 { 44100 }
+        fn get_num_inputs(&self) -> i32 { self.inputs as i32 }
+        fn get_num_outputs(&self) -> i32 { self.outputs as i32 }
+        fn class_init(_sample_rate: i32) {}
+        fn instance_reset_params(&mut self) {}
+        fn instance_clear(&mut self) {}
+        fn instance_constants(&mut self, _sample_rate: i32) {}
+        fn instance_init(&mut self, _sample_rate: i32) {}
+        fn init(&mut self, _sample_rate: i32) {}
+
+        fn build_user_interface(&self, ui_interface: &mut dyn UI<F32>) {
+            (self.ui)(ui_interface);
+        }
+
+        fn build_user_interface_static(_ui_interface: &mut dyn UI<F32>) {}
+
+        fn get_param(&self, param: ParamIndex) -> Option<F32> {
+            self.params.get(&param).copied()
+        }
+
+        fn set_param(&mut self, param: ParamIndex, value: F32) {
+            self.params.insert(param, value);
+        }
+
+        fn compute(&mut self, count: i32, inputs: &[&[F32]], outputs: &mut [&mut [F32]]) {
+            assert_eq!((self.inputs, self.outputs), (inputs.len(), outputs.len()));
+
+            let value = self.get_param(ParamIndex(0)).unwrap_or(0.0);
+            for (o, output) in outputs.iter_mut().enumerate() {
+                output[..count as usize].fill(value / (o + 1) as F32);
+            }
+        }
+    }
+
+    /// Has `INPUTS` inputs and `OUTPUTS` outputs. Output `o` is the sum of the inputs times its
+    /// `gain` parameter, divided by `o + 1`.
+    struct PortStub<const INPUTS: usize, const OUTPUTS: usize> {
+        gain: F32,
+    }
+
+    impl<const INPUTS: usize, const OUTPUTS: usize> FaustDsp for PortStub<INPUTS, OUTPUTS> {
+        type T = F32;
+
+        fn new() -> Self {
+            Self { gain: 1.0 }
+        }
+
+        fn metadata(&self, _m: &mut dyn Meta) {}
+        fn get_sample_rate(&self) -> i32 { 44100 }
+        fn get_num_inputs(&self) -> i32 { INPUTS as i32 }
+        fn get_num_outputs(&self) -> i32 { OUTPUTS as i32 }
+        fn class_init(_sample_rate: i32) {}
+        fn instance_reset_params(&mut self) {}
+        fn instance_clear(&mut self) {}
+        fn instance_constants(&mut self, _sample_rate: i32) {}
+        fn instance_init(&mut self, _sample_rate: i32) {}
+        fn init(&mut self, _sample_rate: i32) {}
+
+        fn build_user_interface(&self, ui_interface: &mut dyn UI<F32>) {
+            Self::build_user_interface_static(ui_interface);
+        }
+
+        fn build_user_interface_static(ui_interface: &mut dyn UI<F32>) {
+            ui_interface.add_horizontal_slider("gain", ParamIndex(0), 1.0, 0.0, 4.0, 0.01);
+        }
+
+        fn get_param(&self, param: ParamIndex) -> Option<F32> {
+            (param == ParamIndex(0)).then_some(self.gain)
+        }
+
+        fn set_param(&mut self, param: ParamIndex, value: F32) {
+            if param == ParamIndex(0) {
+                self.gain = value;
+            }
+        }
+
+        fn compute(&mut self, count: i32, inputs: &[&[F32]], outputs: &mut [&mut [F32]]) {
+            assert_eq!((INPUTS, OUTPUTS), (inputs.len(), outputs.len()));
+
+            for i in 0..count as usize {
+                let sum: F32 = inputs.iter().map(|input| input[i]).sum();
+                for (o, output) in outputs.iter_mut().enumerate() {
+                    output[i] = sum * self.gain / (o + 1) as F32;
+                }
+            }
+        }
+    }
+
+    fn assert_samples_eq(expected: &[f32], actual: &[f32]) {
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(actual) {
+            assert!((e - a).abs() < 1e-6, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    fn note_on(generator: &mut FaustGenerator, key: u8, vel: u8) {
+        generator.handle(MidiMessage::NoteOn { key: key.into(), vel: vel.into() });
+    }
+
+    #[test]
+    fn test_note_params_bind_by_label() {
+        // Like noise.dsp, which declares its volume before its frequency
+        let dsp = StubDsp::with_ui(|ui| {
+            ui.add_horizontal_slider("volume", ParamIndex(0), -6.0, -60.0, 0.0, 0.1);
+            ui.add_num_entry("velocity", ParamIndex(1), 0.0, 0.0, 127.0, 1.0);
+            ui.add_button("Gate", ParamIndex(2));
+            ui.add_horizontal_slider("gain", ParamIndex(3), 0.5, 0.0, 1.0, 0.01);
+            ui.add_horizontal_slider("freq", ParamIndex(4), 440.0, 20.0, 20000.0, 1.0);
+        });
+
+        let mut generator = FaustGenerator::new(Box::new(dsp));
+        note_on(&mut generator, 81, 127);
+        assert_eq!(None, generator.get_param(0), "volume is not a note parameter");
+        assert_eq!(Some(127.0), generator.get_param(1));
+        assert_eq!(Some(1.0), generator.get_param(2));
+        assert_eq!(Some(1.0), generator.get_param(3));
+        assert!((generator.get_param(4).unwrap() - 880.0).abs() < 1e-3);
+
+        generator.handle(MidiMessage::NoteOff { key: 81.into(), vel: 0.into() });
+        assert_eq!(Some(0.0), generator.get_param(2));
+    }
+
+    #[test]
+    fn test_metadata_attaches_to_param() {
+        let dsp = StubDsp::with_ui(|ui| {
+            ui.declare(None, "tooltip", "belongs to the box");
+            ui.open_vertical_box("synth");
+            ui.declare(Some(ParamIndex(1)), "unit", "Hz");
+            ui.declare(Some(ParamIndex(0)), "unit", "dB");
+            ui.declare(Some(ParamIndex(0)), "style", "knob");
+            ui.add_horizontal_slider("volume", ParamIndex(0), 0.0, -60.0, 0.0, 0.1);
+            ui.add_horizontal_slider("cutoff", ParamIndex(1), 1000.0, 20.0, 20000.0, 1.0);
+            ui.add_button("gate", ParamIndex(2));
+            ui.close_box();
+        });
+
+        let params = discover_params(&dsp);
+        assert_eq!(vec!["volume", "cutoff", "gate"], params.iter().map(|p| p.label.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec![("unit".to_string(), "dB".to_string()), ("style".to_string(), "knob".to_string())], params[0].metadata);
+        assert_eq!(Some("Hz"), params[1].unit());
+        assert!(params[2].metadata.is_empty());
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let dsp = StubDsp::with_ui(|ui| {
+            ui.open_vertical_box("synth");
+            ui.open_horizontal_box("envelope");
+            ui.add_horizontal_slider("attack", ParamIndex(0), 0.01, 0.0, 1.0, 0.01);
+            ui.add_horizontal_slider("release", ParamIndex(1), 0.1, 0.0, 1.0, 0.01);
+            ui.close_box();
+            ui.add_horizontal_slider("freq", ParamIndex(2), 440.0, 20.0, 20000.0, 1.0);
+            ui.close_box();
+            ui.add_horizontal_slider("volume", ParamIndex(3), 0.0, -60.0, 0.0, 0.1);
+
+            // Left open
+            ui.open_tab_box("extra");
+            ui.add_check_button("bypass", ParamIndex(4));
+        });
+
+        let ui = describe_ui(&dsp);
+        assert_eq!(vec![
+            UiItem::Group {
+                kind: GroupKind::Vertical,
+                label: "synth".to_string(),
+                items: vec![
+                    UiItem::Group {
+                        kind: GroupKind::Horizontal,
+                        label: "envelope".to_string(),
+                        items: vec![UiItem::Param(0), UiItem::Param(1)],
+                    },
+                    UiItem::Param(2),
+                ],
+            },
+            UiItem::Param(3),
+            UiItem::Group {
+                kind: GroupKind::Tab,
+                label: "extra".to_string(),
+                items: vec![UiItem::Param(4)],
+            },
+        ], ui.items);
+    }
+
+    #[test]
+    fn test_engine_without_note_params() {
+        let dsp = StubDsp::with_ui(|ui| {
+            ui.add_horizontal_slider("level", ParamIndex(0), 0.25, 0.0, 1.0, 0.01);
+
+            // Outputs of the DSP are never set, whatever they are called
+            ui.add_horizontal_bargraph("gate", ParamIndex(1), 0.0, 1.0);
+        });
+
+        let mut generator = FaustGenerator::new(Box::new(dsp));
+        generator.set_param(0, 0.25);
+        note_on(&mut generator, 60, 100);
+        assert_eq!(Some(0.25), generator.get_param(0));
+        assert_eq!(None, generator.get_param(1));
+
+        let mut out = [0.0; 4];
+        generator.process(&mut out);
+        assert_eq!([0.25; 4], out);
+    }
+
+    #[test]
+    fn test_mono_effect_runs_an_instance_per_channel() {
+        let mut effect = FaustEffect::<PortStub<1, 1>>::new(44100);
+        effect.set_param("gain", 2.0);
+
+        let mut buf = [0.1, 0.2, 0.3, 0.4];
+        effect.process(&mut buf, 2);
+        assert_samples_eq(&[0.2, 0.4, 0.6, 0.8], &buf);
+        assert_eq!(2, effect.instances.len());
+
+        // Parameters reach every instance, including the ones created after they were set
+        effect.set_param("gain", 0.5);
+        let mut buf = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        effect.process(&mut buf, 3);
+        assert_samples_eq(&[0.05, 0.1, 0.15, 0.2, 0.25, 0.3], &buf);
+        assert_eq!(3, effect.instances.len());
+    }
+
+    #[test]
+    fn test_effect_outputs_map_onto_channels() {
+        let mut effect = FaustEffect::<PortStub<1, 2>>::new(44100);
+
+        // Outputs go to the channel of the same number, and the input is the first channel
+        let mut buf = [0.5, 0.9];
+        effect.process(&mut buf, 2);
+        assert_samples_eq(&[0.5, 0.25], &buf);
+
+        // Extra outputs are averaged
+        let mut buf = [0.5];
+        effect.process(&mut buf, 1);
+        assert_samples_eq(&[0.375], &buf);
+
+        // Missing outputs repeat
+        let mut buf = [0.5, 0.9, 0.9];
+        effect.process(&mut buf, 3);
+        assert_samples_eq(&[0.5, 0.25, 0.5], &buf);
+    }
+
+    #[test]
+    fn test_effect_inputs_fold_onto_channels() {
+        let mut effect = FaustEffect::<PortStub<2, 1>>::new(44100);
+
+        let mut buf = [0.25];
+        effect.process(&mut buf, 1);
+        assert_samples_eq(&[0.5], &buf);
+
+        let mut buf = [0.25, 0.5, 0.125, 0.0];
+        effect.process(&mut buf, 2);
+        assert_samples_eq(&[0.75, 0.75, 0.125, 0.125], &buf);
+    }
+
+    #[test]
+    fn test_generator_averages_outputs() {
+        let mut dsp = StubDsp::with_ui(|ui| {
+            ui.add_horizontal_slider("level", ParamIndex(0), 0.0, 0.0, 1.0, 0.01);
+        });
+        dsp.inputs = 2;
+        dsp.outputs = 2;
+
+        let mut generator = FaustGenerator::new(Box::new(dsp));
+        generator.set_param(0, 0.5);
+
+        let mut out = [0.0; 4];
+        generator.process(&mut out);
+        assert_eq!([0.375; 4], out);
+    }
+}
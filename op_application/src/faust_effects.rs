@@ -0,0 +1,14 @@
+#![allow(unused_parens)]
+#![allow(non_snake_case)]
+#![allow(non_camel_case_types)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_mut)]
+#![allow(non_upper_case_globals)]
+
+use op_engine::effect::EffectRegistry;
+
+use crate::faust::*;
+
+// Includes every effect in effects/ and defines `register`
+include!(concat!(env!("OUT_DIR"), "/effect_registry.rs"));
@@ -6,7 +6,59 @@
 #![allow(unused_mut)]
 #![allow(non_upper_case_globals)]
 
+use std::fmt::{Display, Formatter};
+
 use crate::faust::*;
 
-include!(concat!(env!("OUT_DIR"), "/sine.rs"));
-include!(concat!(env!("OUT_DIR"), "/saw.rs"));
+pub struct EngineInfo {
+    /// Name of the .dsp file in engines/, without its extension.
+    pub file_name: &'static str,
+
+    /// Name of the generated struct.
+    pub class_name: &'static str,
+
+    /// Name shown to the user, from the engine's `declare name`.
+    pub name: &'static str,
+
+    pub constructor: fn() -> Box<dyn FaustDsp<T=F32>>,
+}
+
+// Includes every engine in engines/ and defines ENGINES
+include!(concat!(env!("OUT_DIR"), "/engine_registry.rs"));
+
+// build.rs fails without any engines, so there is always an Engine(0) to fall back on
+const _: () = assert!(!ENGINES.is_empty(), "there must be at least one engine");
+
+/// An engine in ENGINES.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Engine(usize);
+
+impl Engine {
+    pub fn all() -> Vec<Engine> {
+        (0..ENGINES.len()).map(Engine).collect()
+    }
+
+    pub fn find(class_name: &str) -> Option<Engine> {
+        ENGINES.iter().position(|e| e.class_name == class_name).map(Engine)
+    }
+
+    pub fn info(&self) -> &'static EngineInfo {
+        &ENGINES[self.0]
+    }
+
+    pub fn create(&self) -> Box<dyn FaustDsp<T=F32>> {
+        (self.info().constructor)()
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::find("Sine").unwrap_or(Engine(0))
+    }
+}
+
+impl Display for Engine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.info().name)
+    }
+}
@@ -0,0 +1,477 @@
+//! Recompiles the active engine with libfaust's interpreter backend when its .dsp file changes, so
+//! that engines can be edited while the application is running.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::faust::{F32, FaustDsp, GroupKind, MAX_PORTS, Meta, ParamIndex, UI, WidgetKind};
+use crate::faust_engines::Engine;
+
+/// Bindings to libfaust's C interface to the interpreter backend (`faust/dsp/interpreter-dsp-c.h`).
+#[allow(non_snake_case)]
+mod ffi {
+    use std::ffi::{c_char, c_int, c_void};
+
+    use crate::faust::F32;
+
+    #[repr(C)]
+    pub struct InterpreterDspFactory {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct InterpreterDsp {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct UIGlue {
+        pub ui_interface: *mut c_void,
+        pub open_tab_box: extern "C" fn(*mut c_void, *const c_char),
+        pub open_horizontal_box: extern "C" fn(*mut c_void, *const c_char),
+        pub open_vertical_box: extern "C" fn(*mut c_void, *const c_char),
+        pub close_box: extern "C" fn(*mut c_void),
+        pub add_button: extern "C" fn(*mut c_void, *const c_char, *mut F32),
+        pub add_check_button: extern "C" fn(*mut c_void, *const c_char, *mut F32),
+        pub add_vertical_slider: extern "C" fn(*mut c_void, *const c_char, *mut F32, F32, F32, F32, F32),
+        pub add_horizontal_slider: extern "C" fn(*mut c_void, *const c_char, *mut F32, F32, F32, F32, F32),
+        pub add_num_entry: extern "C" fn(*mut c_void, *const c_char, *mut F32, F32, F32, F32, F32),
+        pub add_horizontal_bargraph: extern "C" fn(*mut c_void, *const c_char, *mut F32, F32, F32),
+        pub add_vertical_bargraph: extern "C" fn(*mut c_void, *const c_char, *mut F32, F32, F32),
+        pub add_soundfile: extern "C" fn(*mut c_void, *const c_char, *const c_char, *mut *mut c_void),
+        pub declare: extern "C" fn(*mut c_void, *mut F32, *const c_char, *const c_char),
+    }
+
+    #[repr(C)]
+    pub struct MetaGlue {
+        pub meta_interface: *mut c_void,
+        pub declare: extern "C" fn(*mut c_void, *const c_char, *const c_char),
+    }
+
+    extern "C" {
+        pub fn createCInterpreterDSPFactoryFromFile(filename: *const c_char, argc: c_int, argv: *const *const c_char, error_msg: *mut c_char) -> *mut InterpreterDspFactory;
+        pub fn deleteCInterpreterDSPFactory(factory: *mut InterpreterDspFactory) -> bool;
+
+        pub fn createCInterpreterDSPInstance(factory: *mut InterpreterDspFactory) -> *mut InterpreterDsp;
+        pub fn deleteCInterpreterDSPInstance(dsp: *mut InterpreterDsp);
+
+        pub fn metadataCInterpreterDSPInstance(dsp: *mut InterpreterDsp, meta: *mut MetaGlue);
+        pub fn getSampleRateCInterpreterDSPInstance(dsp: *mut InterpreterDsp) -> c_int;
+        pub fn getNumInputsCInterpreterDSPInstance(dsp: *mut InterpreterDsp) -> c_int;
+        pub fn getNumOutputsCInterpreterDSPInstance(dsp: *mut InterpreterDsp) -> c_int;
+        pub fn initCInterpreterDSPInstance(dsp: *mut InterpreterDsp, sample_rate: c_int);
+        pub fn instanceInitCInterpreterDSPInstance(dsp: *mut InterpreterDsp, sample_rate: c_int);
+        pub fn instanceConstantsCInterpreterDSPInstance(dsp: *mut InterpreterDsp, sample_rate: c_int);
+        pub fn instanceResetUserInterfaceCInterpreterDSPInstance(dsp: *mut InterpreterDsp);
+        pub fn instanceClearCInterpreterDSPInstance(dsp: *mut InterpreterDsp);
+        pub fn buildUserInterfaceCInterpreterDSPInstance(dsp: *mut InterpreterDsp, ui: *mut UIGlue);
+        pub fn computeCInterpreterDSPInstance(dsp: *mut InterpreterDsp, count: c_int, inputs: *mut *mut F32, outputs: *mut *mut F32);
+    }
+}
+
+/// Size of the buffer libfaust writes compilation errors to.
+const ERROR_MSG_LEN: usize = 4096;
+
+/// A DSP compiled by libfaust, from which instances can be created.
+pub struct InterpretedFactory {
+    factory: *mut ffi::InterpreterDspFactory,
+}
+
+// The factory is only read after it has been compiled
+unsafe impl Send for InterpretedFactory {}
+unsafe impl Sync for InterpretedFactory {}
+
+impl InterpretedFactory {
+    /// Compiles `path`. Libraries are also looked up in `include_dir`. Returns libfaust's error
+    /// message if the file does not compile.
+    pub fn from_file(path: &Path, include_dir: &Path) -> Result<Arc<Self>, String> {
+        let to_c_string = |s: &str| CString::new(s).map_err(|e| e.to_string());
+
+        let path = to_c_string(&path.to_string_lossy())?;
+        let args = [to_c_string("-I")?, to_c_string(&include_dir.to_string_lossy())?];
+        let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let mut error_msg = vec![0 as c_char; ERROR_MSG_LEN];
+
+        let factory = unsafe {
+            ffi::createCInterpreterDSPFactoryFromFile(path.as_ptr(), argv.len() as c_int, argv.as_ptr(), error_msg.as_mut_ptr())
+        };
+
+        if factory.is_null() {
+            let error_msg = unsafe { CStr::from_ptr(error_msg.as_ptr()) };
+            return Err(error_msg.to_string_lossy().trim().to_string());
+        }
+
+        Ok(Arc::new(Self { factory }))
+    }
+
+    pub fn create(self: &Arc<Self>) -> InterpretedDsp {
+        let dsp = unsafe { ffi::createCInterpreterDSPInstance(self.factory) };
+        assert!(!dsp.is_null(), "libfaust failed to create a DSP instance");
+
+        let mut ui = Vec::new();
+        let mut glue = ffi::UIGlue {
+            ui_interface: &mut ui as *mut Vec<UiCall> as *mut c_void,
+            open_tab_box: record_open_tab_box,
+            open_horizontal_box: record_open_horizontal_box,
+            open_vertical_box: record_open_vertical_box,
+            close_box: record_close_box,
+            add_button: record_add_button,
+            add_check_button: record_add_check_button,
+            add_vertical_slider: record_add_vertical_slider,
+            add_horizontal_slider: record_add_horizontal_slider,
+            add_num_entry: record_add_num_entry,
+            add_horizontal_bargraph: record_add_horizontal_bargraph,
+            add_vertical_bargraph: record_add_vertical_bargraph,
+            add_soundfile: record_add_soundfile,
+            declare: record_declare,
+        };
+
+        unsafe { ffi::buildUserInterfaceCInterpreterDSPInstance(dsp, &mut glue) };
+
+        let zones = ui
+            .iter()
+            .filter_map(|call| match call {
+                UiCall::Widget { zone, .. } => Some(*zone),
+                _ => None,
+            })
+            .collect();
+
+        InterpretedDsp {
+            dsp,
+            ui,
+            zones,
+            _factory: self.clone(),
+        }
+    }
+}
+
+impl Drop for InterpretedFactory {
+    fn drop(&mut self) {
+        unsafe { ffi::deleteCInterpreterDSPFactory(self.factory) };
+    }
+}
+
+/// A call made by a DSP while building its user interface, recorded so that it can be replayed
+/// to a `UI` with parameter indices instead of pointers.
+enum UiCall {
+    OpenBox(GroupKind, String),
+    CloseBox,
+    Widget {
+        kind: WidgetKind,
+        label: String,
+        zone: *mut F32,
+        init: F32,
+        min: F32,
+        max: F32,
+        step: F32,
+    },
+    Declare(*mut F32, String, String),
+}
+
+unsafe fn recorder<'a>(ui: *mut c_void) -> &'a mut Vec<UiCall> {
+    &mut *(ui as *mut Vec<UiCall>)
+}
+
+unsafe fn string(s: *const c_char) -> String {
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+unsafe fn widget(kind: WidgetKind, label: *const c_char, zone: *mut F32, init: F32, min: F32, max: F32, step: F32) -> UiCall {
+    UiCall::Widget { kind, label: string(label), zone, init, min, max, step }
+}
+
+extern "C" fn record_open_tab_box(ui: *mut c_void, label: *const c_char) {
+    unsafe { recorder(ui).push(UiCall::OpenBox(GroupKind::Tab, string(label))) }
+}
+
+extern "C" fn record_open_horizontal_box(ui: *mut c_void, label: *const c_char) {
+    unsafe { recorder(ui).push(UiCall::OpenBox(GroupKind::Horizontal, string(label))) }
+}
+
+extern "C" fn record_open_vertical_box(ui: *mut c_void, label: *const c_char) {
+    unsafe { recorder(ui).push(UiCall::OpenBox(GroupKind::Vertical, string(label))) }
+}
+
+extern "C" fn record_close_box(ui: *mut c_void) {
+    unsafe { recorder(ui).push(UiCall::CloseBox) }
+}
+
+extern "C" fn record_add_button(ui: *mut c_void, label: *const c_char, zone: *mut F32) {
+    unsafe { recorder(ui).push(widget(WidgetKind::Button, label, zone, 0.0, 0.0, 1.0, 1.0)) }
+}
+
+extern "C" fn record_add_check_button(ui: *mut c_void, label: *const c_char, zone: *mut F32) {
+    unsafe { recorder(ui).push(widget(WidgetKind::CheckButton, label, zone, 0.0, 0.0, 1.0, 1.0)) }
+}
+
+extern "C" fn record_add_vertical_slider(ui: *mut c_void, label: *const c_char, zone: *mut F32, init: F32, min: F32, max: F32, step: F32) {
+    unsafe { recorder(ui).push(widget(WidgetKind::VerticalSlider, label, zone, init, min, max, step)) }
+}
+
+extern "C" fn record_add_horizontal_slider(ui: *mut c_void, label: *const c_char, zone: *mut F32, init: F32, min: F32, max: F32, step: F32) {
+    unsafe { recorder(ui).push(widget(WidgetKind::HorizontalSlider, label, zone, init, min, max, step)) }
+}
+
+extern "C" fn record_add_num_entry(ui: *mut c_void, label: *const c_char, zone: *mut F32, init: F32, min: F32, max: F32, step: F32) {
+    unsafe { recorder(ui).push(widget(WidgetKind::NumEntry, label, zone, init, min, max, step)) }
+}
+
+extern "C" fn record_add_horizontal_bargraph(ui: *mut c_void, label: *const c_char, zone: *mut F32, min: F32, max: F32) {
+    unsafe { recorder(ui).push(widget(WidgetKind::HorizontalBargraph, label, zone, min, min, max, 0.0)) }
+}
+
+extern "C" fn record_add_vertical_bargraph(ui: *mut c_void, label: *const c_char, zone: *mut F32, min: F32, max: F32) {
+    unsafe { recorder(ui).push(widget(WidgetKind::VerticalBargraph, label, zone, min, min, max, 0.0)) }
+}
+
+// Soundfiles are not supported
+extern "C" fn record_add_soundfile(_ui: *mut c_void, _label: *const c_char, _url: *const c_char, _soundfile: *mut *mut c_void) {}
+
+extern "C" fn record_declare(ui: *mut c_void, zone: *mut F32, key: *const c_char, value: *const c_char) {
+    unsafe { recorder(ui).push(UiCall::Declare(zone, string(key), string(value))) }
+}
+
+extern "C" fn forward_meta_declare(meta: *mut c_void, key: *const c_char, value: *const c_char) {
+    unsafe {
+        let meta = &mut *(meta as *mut &mut dyn Meta);
+        meta.declare(&string(key), &string(value));
+    }
+}
+
+/// An instance of a DSP compiled by libfaust. Parameters are numbered in the order the DSP
+/// declares its widgets.
+pub struct InterpretedDsp {
+    dsp: *mut ffi::InterpreterDsp,
+    ui: Vec<UiCall>,
+    zones: Vec<*mut F32>,
+
+    // Instances must be deleted before their factory
+    _factory: Arc<InterpretedFactory>,
+}
+
+// The zones belong to the instance, which is only used by one thread at a time
+unsafe impl Send for InterpretedDsp {}
+
+impl InterpretedDsp {
+    fn param_index(&self, zone: *mut F32) -> Option<ParamIndex> {
+        self.zones.iter().position(|&z| z == zone).map(|i| ParamIndex(i as i32))
+    }
+}
+
+impl Drop for InterpretedDsp {
+    fn drop(&mut self) {
+        unsafe { ffi::deleteCInterpreterDSPInstance(self.dsp) };
+    }
+}
+
+impl FaustDsp for InterpretedDsp {
+    type T = F32;
+
+    fn new() -> Self {
+        panic!("interpreted DSPs are created by InterpretedFactory::create");
+    }
+
+    fn metadata(&self, m: &mut dyn Meta) {
+        let mut m = m;
+        let mut glue = ffi::MetaGlue {
+            meta_interface: &mut m as *mut &mut dyn Meta as *mut c_void,
+            declare: forward_meta_declare,
+        };
+
+        unsafe { ffi::metadataCInterpreterDSPInstance(self.dsp, &mut glue) };
+    }
+
+    fn get_sample_rate(&self) -> i32 {
+        unsafe { ffi::getSampleRateCInterpreterDSPInstance(self.dsp) }
+    }
+
+    fn get_num_inputs(&self) -> i32 {
+        unsafe { ffi::getNumInputsCInterpreterDSPInstance(self.dsp) }
+    }
+
+    fn get_num_outputs(&self) -> i32 {
+        unsafe { ffi::getNumOutputsCInterpreterDSPInstance(self.dsp) }
+    }
+
+    // The interpreter initializes everything per instance
+    fn class_init(_sample_rate: i32) {}
+
+    fn instance_reset_params(&mut self) {
+        unsafe { ffi::instanceResetUserInterfaceCInterpreterDSPInstance(self.dsp) }
+    }
+
+    fn instance_clear(&mut self) {
+        unsafe { ffi::instanceClearCInterpreterDSPInstance(self.dsp) }
+    }
+
+    fn instance_constants(&mut self, sample_rate: i32) {
+        unsafe { ffi::instanceConstantsCInterpreterDSPInstance(self.dsp, sample_rate) }
+    }
+
+    fn instance_init(&mut self, sample_rate: i32) {
+        unsafe { ffi::instanceInitCInterpreterDSPInstance(self.dsp, sample_rate) }
+    }
+
+    fn init(&mut self, sample_rate: i32) {
+        unsafe { ffi::initCInterpreterDSPInstance(self.dsp, sample_rate) }
+    }
+
+    fn build_user_interface(&self, ui_interface: &mut dyn UI<Self::T>) {
+        let mut widgets = 0;
+
+        for call in &self.ui {
+            match call {
+                UiCall::OpenBox(GroupKind::Tab, label) => ui_interface.open_tab_box(label),
+                UiCall::OpenBox(GroupKind::Horizontal, label) => ui_interface.open_horizontal_box(label),
+                UiCall::OpenBox(GroupKind::Vertical, label) => ui_interface.open_vertical_box(label),
+                UiCall::CloseBox => ui_interface.close_box(),
+
+                UiCall::Widget { kind, label, init, min, max, step, .. } => {
+                    let param = ParamIndex(widgets);
+                    widgets += 1;
+
+                    match kind {
+                        WidgetKind::Button => ui_interface.add_button(label, param),
+                        WidgetKind::CheckButton => ui_interface.add_check_button(label, param),
+                        WidgetKind::VerticalSlider => ui_interface.add_vertical_slider(label, param, *init, *min, *max, *step),
+                        WidgetKind::HorizontalSlider => ui_interface.add_horizontal_slider(label, param, *init, *min, *max, *step),
+                        WidgetKind::NumEntry => ui_interface.add_num_entry(label, param, *init, *min, *max, *step),
+                        WidgetKind::HorizontalBargraph => ui_interface.add_horizontal_bargraph(label, param, *min, *max),
+                        WidgetKind::VerticalBargraph => ui_interface.add_vertical_bargraph(label, param, *min, *max),
+                    }
+                }
+
+                UiCall::Declare(zone, key, value) => ui_interface.declare(self.param_index(*zone), key, value),
+            }
+        }
+    }
+
+    // The interface depends on the compiled DSP, so it is only available from an instance
+    fn build_user_interface_static(_ui_interface: &mut dyn UI<Self::T>) {}
+
+    fn get_param(&self, param: ParamIndex) -> Option<Self::T> {
+        let zone = *self.zones.get(param.0 as usize)?;
+        Some(unsafe { *zone })
+    }
+
+    fn set_param(&mut self, param: ParamIndex, value: Self::T) {
+        if let Some(&zone) = self.zones.get(param.0 as usize) {
+            unsafe { *zone = value };
+        }
+    }
+
+    fn compute(&mut self, count: i32, inputs: &[&[Self::T]], outputs: &mut [&mut [Self::T]]) {
+        // The interpreter reads a pointer for each of its inputs and outputs, so it must not be
+        // given fewer
+        if inputs.len() != self.get_num_inputs() as usize || outputs.len() != self.get_num_outputs() as usize {
+            return;
+        }
+
+        if inputs.len() > MAX_PORTS || outputs.len() > MAX_PORTS {
+            return;
+        }
+
+        let mut input_ptrs = [std::ptr::null_mut(); MAX_PORTS];
+        let mut output_ptrs = [std::ptr::null_mut(); MAX_PORTS];
+
+        // Faust does not write to its inputs
+        for (ptr, input) in input_ptrs.iter_mut().zip(inputs) {
+            *ptr = input.as_ptr() as *mut F32;
+        }
+
+        for (ptr, output) in output_ptrs.iter_mut().zip(outputs.iter_mut()) {
+            *ptr = output.as_mut_ptr();
+        }
+
+        unsafe { ffi::computeCInterpreterDSPInstance(self.dsp, count, input_ptrs.as_mut_ptr(), output_ptrs.as_mut_ptr()) }
+    }
+}
+
+/// Returns when the running executable was built. Engines compiled into it are at least as old.
+fn build_time() -> SystemTime {
+    std::env::current_exe()
+        .and_then(fs::metadata)
+        .and_then(|m| m.modified())
+        .unwrap_or_else(|_| SystemTime::now())
+}
+
+/// Recompiles the active engine when its file in `engines/` is newer than the version that is
+/// playing.
+pub struct HotReload {
+    dir: PathBuf,
+    built: SystemTime,
+
+    // The active engine's file, and its modification time when it was last compiled
+    compiled: Option<(PathBuf, SystemTime)>,
+
+    factory: Option<Arc<InterpretedFactory>>,
+
+    /// The error from the last failed compilation of the active engine, if it has not compiled
+    /// successfully since.
+    pub error: Option<String>,
+}
+
+impl HotReload {
+    pub fn new() -> Self {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("engines");
+
+        Self {
+            dir,
+            built: build_time(),
+            compiled: None,
+            factory: None,
+            error: None,
+        }
+    }
+
+    /// Forgets the recompiled engine, e.g. because another engine was selected. The next poll
+    /// compiles the selected engine if its file was changed since the application was built.
+    pub fn reset(&mut self) {
+        self.compiled = None;
+        self.factory = None;
+        self.error = None;
+    }
+
+    /// Recompiles `engine` if its file is newer than the version that is playing. Returns true if
+    /// a new version of the engine is available from `factory`.
+    pub fn poll(&mut self, engine: Engine) -> bool {
+        let path = self.dir.join(engine.info().file_name).with_extension("dsp");
+        let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return false,
+        };
+
+        let playing = match &self.compiled {
+            Some((compiled, time)) if *compiled == path => *time,
+            _ => self.built,
+        };
+
+        if modified <= playing {
+            return false;
+        }
+
+        // Not retried until the file changes again, even if it fails to compile
+        self.compiled = Some((path.clone(), modified));
+
+        match InterpretedFactory::from_file(&path, &self.dir) {
+            Ok(factory) => {
+                self.factory = Some(factory);
+                self.error = None;
+                true
+            }
+
+            Err(error) => {
+                self.error = Some(error);
+                false
+            }
+        }
+    }
+
+    /// Returns the factory for the latest version of the active engine, if it was recompiled.
+    pub fn factory(&self) -> Option<Arc<InterpretedFactory>> {
+        self.factory.clone()
+    }
+}
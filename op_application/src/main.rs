@@ -1,28 +1,46 @@
 use std::collections::HashSet;
+use std::fs;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use iced::{Alignment, Application, Event, Length, subscription, Theme, time, window};
+use iced::{Alignment, Application, Color, Event, Length, subscription, Theme, time, window};
 use iced::{Command, Element, executor, Settings, Subscription};
 use iced::alignment::{Horizontal, Vertical};
 use iced::keyboard::Event::{KeyPressed, KeyReleased};
 use iced::keyboard::KeyCode;
-use iced::widget::{button, checkbox, column, container, pick_list, row, slider, text};
+use iced::widget::{button, checkbox, column, Column, container, pick_list, row, slider, text};
 
 use op_engine::{Project, Session};
-
-use crate::faust::{FaustDsp, FaustGenerator};
-use crate::view::timeline::{timeline_update, timeline_view, TimelineMessage};
+use op_engine::effect::EffectRegistry;
+use op_engine::history::{Edit, ProjectParam};
+use op_engine::generator::poly::{PolyGenerator, VoiceStealing};
+use op_engine::master::{LimiterMode, MasterMeter};
+use op_engine::snap::Snap;
+use op_engine::time::TimeSignature;
+
+use crate::faust::{describe_ui, DspUi, F32, FaustDsp, FaustGenerator};
+use crate::faust_engines::Engine;
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload::HotReload;
+use crate::recovery::RecoveryDir;
+use crate::view::engine_panel::{engine_panel_update, engine_panel_view, EnginePanel, ParamMessage};
+use crate::view::timeline::{timeline_update, timeline_view, TimelineMessage, TimelineState};
 use crate::virtual_keyboard::VirtualKeyboard;
 
 mod virtual_keyboard;
 mod faust;
 mod faust_engines;
+mod faust_effects;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod recovery;
 mod view;
 
 pub fn main() -> iced::Result {
     OpApplication::run(Settings {
         antialiasing: true,
+        // Closing is handled by the application, which cleans up the autosave first
+        exit_on_close_request: false,
         ..Settings::default()
     })
 }
@@ -36,7 +54,23 @@ struct OpApplication {
     virtual_keyboard: VirtualKeyboard,
     held_keys: HashSet<KeyCode>,
     zoom: f32,
-    current_generator: usize,
+    timeline_state: TimelineState,
+    current_engine: Engine,
+    voice_stealing: VoiceStealing,
+    engine_panel: EnginePanel,
+    meter: MasterMeter,
+
+    /// The directory this instance autosaves to. Autosaving is disabled if it couldn't be created.
+    recovery: Option<RecoveryDir>,
+
+    /// Autosaves of sessions that didn't exit normally, waiting to be restored or discarded.
+    abandoned_autosaves: Vec<PathBuf>,
+
+    /// The last error from saving, loading or exporting, shown until dismissed.
+    error: Option<String>,
+
+    #[cfg(feature = "hot-reload")]
+    hot_reload: HotReload,
 }
 
 #[derive(Debug, Clone)]
@@ -51,18 +85,232 @@ pub enum OpMessage {
     Save,
     Load,
     Export,
+    Autosave,
+    RestoreRecovery,
+    DiscardRecovery,
+    DismissError,
+    PurgeUnusedClips,
     SetZoom(f32),
-    SetGenerator(usize),
+    SetSnap(Snap),
+    SetEngine(Engine),
+    SetVoiceStealing(VoiceStealing),
+    EngineParam(ParamMessage),
+    SetMasterGain(f32),
+    SetLimiter(LimiterMode),
+    SetTempo(f64),
+    SetTimeSignature(TimeSignature),
+    SetMetronome(bool),
+    SetCountIn(u32),
+    EndGesture,
+    Undo,
+    Redo,
+    MeterTick,
+    ResetClip,
+
+    #[cfg(feature = "hot-reload")]
+    HotReloadTick,
 
     Timeline(TimelineMessage),
 }
 
-fn apply_default_generator(session: &mut Session) {
-    let mut sine = faust_engines::Sine::new();
+/// Number of notes a generator can play at once.
+const VOICES: usize = 8;
+
+/// Count-in lengths offered to the user, in bars.
+const COUNT_IN_BARS: [u32; 4] = [0, 1, 2, 4];
+
+/// Time between autosaves.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Returns the autosaves left behind by sessions that didn't exit normally. Their recovery
+/// directories are removed if they crashed before autosaving or recording anything.
+fn find_abandoned_autosaves() -> Vec<PathBuf> {
+    recovery::find_abandoned()
+        .into_iter()
+        .filter(|dir| {
+            if Project::exists(dir) || Project::has_takes(dir) {
+                return true;
+            }
+
+            let _ = fs::remove_dir_all(dir);
+            false
+        })
+        .collect()
+}
+
+/// Sets the session's generator to a polyphonic instance of the engine created by `create`, and
+/// returns the engine's user interface.
+fn apply_generator(session: &mut Session, create: impl Fn() -> Box<dyn FaustDsp<T=F32>>, stealing: VoiceStealing) -> DspUi {
     let sample_rate = session.project.read().unwrap().sample_rate;
-    sine.init(sample_rate as i32);
-    let generator = FaustGenerator::new(Box::new(sine));
-    session.set_generator(Box::new(generator));
+
+    let voices = (0..VOICES)
+        .map(|_| {
+            let mut dsp = create();
+            dsp.init(sample_rate as i32);
+            FaustGenerator::new(dsp)
+        })
+        .collect();
+
+    session.set_generator(Box::new(PolyGenerator::new(voices, stealing)));
+    describe_ui(create().as_ref())
+}
+
+/// Makes the metronome click with the Metronome engine. The built-in click is kept if the engine
+/// is not available.
+fn apply_metronome(session: &Session) {
+    let engine = match Engine::find("Metronome") {
+        Some(engine) => engine,
+        None => return,
+    };
+
+    let sample_rate = session.project.read().unwrap().sample_rate;
+    let mut dsp = engine.create();
+    dsp.init(sample_rate as i32);
+    session.set_metronome_click(Box::new(FaustGenerator::new(dsp)));
+}
+
+fn apply_default_effects(session: &Session) {
+    let mut registry = EffectRegistry::default();
+    faust_effects::register(&mut registry);
+    session.set_effect_registry(registry);
+}
+
+impl OpApplication {
+    /// Returns a function creating instances of the current engine, or of its latest version if
+    /// it was hot-reloaded.
+    fn engine_constructor(&self) -> Box<dyn Fn() -> Box<dyn FaustDsp<T=F32>>> {
+        #[cfg(feature = "hot-reload")]
+        if let Some(factory) = self.hot_reload.factory() {
+            return Box::new(move || Box::new(factory.create()));
+        }
+
+        let engine = self.current_engine;
+        Box::new(move || engine.create())
+    }
+
+    /// Asks whether takes that haven't been saved may be discarded, if there are any. Returns
+    /// whether the current project may be closed.
+    fn confirm_discard_takes(&self) -> bool {
+        if !self.recording && !self.session.has_unsaved_takes() {
+            return true;
+        }
+
+        rfd::MessageDialog::new()
+            .set_level(rfd::MessageLevel::Warning)
+            .set_title("Unsaved recordings")
+            .set_description("Recordings made since the project was last saved will be lost. Continue?")
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show()
+    }
+
+    /// Replaces the session with one for `project`, keeping the current engine and its settings.
+    /// Returns whether the project was opened.
+    fn open_project(&mut self, project: Project, path: Option<PathBuf>) -> bool {
+        if !self.confirm_discard_takes() {
+            return false;
+        }
+
+        // Without an output device, projects are still opened for editing
+        let session = if self.session.has_output() {
+            Session::new_with_project(project)
+        } else {
+            Ok(Session::new_without_output(project))
+        };
+
+        let mut session = match session {
+            Ok(session) => session,
+            Err(e) => {
+                self.error = Some(format!("Failed to open project: {}", e));
+                return false;
+            }
+        };
+
+        apply_generator(&mut session, self.engine_constructor(), self.voice_stealing);
+        apply_default_effects(&session);
+        apply_metronome(&session);
+        self.engine_panel.apply(&session);
+
+        // The old project's unsaved takes are discarded along with it, so that they aren't
+        // recovered into the new one
+        let _ = self.session.set_recording(false, self.armed_track);
+        self.session.discard_takes();
+
+        self.project_path = path;
+        self.session = session;
+        self.update_record_dir();
+        self.playing = false;
+        self.recording = false;
+        self.armed_track = 0;
+        self.timeline_state = TimelineState::default();
+        self.meter = MasterMeter::default();
+        true
+    }
+
+    /// Starts or stops recording. The project is autosaved right after a take finishes, so that it
+    /// can be restored in place.
+    fn set_recording(&mut self, recording: bool, track: usize) -> Command<OpMessage> {
+        let was_recording = self.recording;
+        self.recording = recording;
+
+        match self.session.set_recording(recording, track) {
+            Ok(0) => {}
+            Ok(dropped) => {
+                let sample_rate = self.session.project.read().unwrap().sample_rate;
+                self.error = Some(format!(
+                    "Recording fell behind, {:.2} s are missing from the take",
+                    dropped as f64 / sample_rate as f64
+                ));
+            }
+            Err(e) => {
+                self.recording = false;
+                self.error = Some(format!("Failed to record: {}", e));
+                return Command::none();
+            }
+        }
+
+        if was_recording && !recording {
+            return self.update(OpMessage::Autosave);
+        }
+
+        Command::none()
+    }
+
+    /// Records takes next to the autosave, where they are recovered from after a crash. Without
+    /// autosaving, takes are recorded into the project's directory once it has been saved.
+    fn update_record_dir(&mut self) {
+        let dir = match (&self.recovery, &self.project_path) {
+            (Some(recovery), _) => recovery.path(),
+            (None, Some(path)) => path.as_path(),
+            (None, None) => return,
+        };
+
+        self.session.set_record_dir(Project::recordings_dir(dir));
+    }
+
+    /// Autosaves the session to this instance's recovery directory. Returns whether it was saved.
+    fn autosave(&mut self) -> bool {
+        let recovery = match &self.recovery {
+            Some(recovery) => recovery,
+            None => return false,
+        };
+
+        match self.session.autosave(recovery.path()) {
+            Ok(()) => true,
+            Err(e) => {
+                self.error = Some(format!("Failed to autosave: {}", e));
+                false
+            }
+        }
+    }
+
+    /// Returns why the current engine could not be hot-reloaded, if it failed to compile.
+    fn hot_reload_error(&self) -> Option<&str> {
+        #[cfg(feature = "hot-reload")]
+        return self.hot_reload.error.as_deref();
+
+        #[cfg(not(feature = "hot-reload"))]
+        None
+    }
 }
 
 impl Application for OpApplication {
@@ -72,23 +320,45 @@ impl Application for OpApplication {
     type Flags = ();
 
     fn new(_: Self::Flags) -> (Self, Command<Self::Message>) {
-        let mut session = Session::new_empty().unwrap();
-        apply_default_generator(&mut session);
-
-        (
-            Self {
-                session,
-                project_path: None,
-                playing: false,
-                recording: false,
-                armed_track: 0,
-                virtual_keyboard: VirtualKeyboard::new(),
-                held_keys: HashSet::new(),
-                zoom: 1.0,
-                current_generator: 0,
-            },
-            Command::none()
-        )
+        // Without an output device, the application still starts so that projects can be edited
+        let (mut session, session_error) = match Session::new_empty() {
+            Ok(session) => (session, None),
+            Err(e) => (Session::new_without_output(Project::new()), Some(format!("Audio output is unavailable: {}", e))),
+        };
+
+        let engine_ui = apply_generator(&mut session, || Engine::default().create(), VoiceStealing::default());
+        apply_default_effects(&session);
+        apply_metronome(&session);
+
+        let (recovery, recovery_error) = match RecoveryDir::create() {
+            Ok(recovery) => (Some(recovery), None),
+            Err(e) => (None, Some(format!("Autosave is disabled, its directory could not be created: {}", e))),
+        };
+
+        let mut app = Self {
+            session,
+            project_path: None,
+            playing: false,
+            recording: false,
+            armed_track: 0,
+            virtual_keyboard: VirtualKeyboard::new(),
+            held_keys: HashSet::new(),
+            zoom: 1.0,
+            timeline_state: TimelineState::default(),
+            current_engine: Engine::default(),
+            voice_stealing: VoiceStealing::default(),
+            engine_panel: EnginePanel::new(engine_ui),
+            meter: MasterMeter::default(),
+            recovery,
+            abandoned_autosaves: find_abandoned_autosaves(),
+            error: session_error.or(recovery_error),
+
+            #[cfg(feature = "hot-reload")]
+            hot_reload: HotReload::new(),
+        };
+
+        app.update_record_dir();
+        (app, Command::none())
     }
 
     fn title(&self) -> String {
@@ -99,24 +369,31 @@ impl Application for OpApplication {
         match message {
             OpMessage::Play => {
                 if !self.playing {
-                    self.session.play().unwrap();
-                    self.playing = true;
+                    match self.session.play() {
+                        Ok(()) => self.playing = true,
+                        Err(e) => self.error = Some(format!("Failed to play: {}", e)),
+                    }
                 }
             }
 
             OpMessage::Pause => {
                 if self.playing {
-                    self.session.pause().unwrap();
+                    if let Err(e) = self.session.pause() {
+                        self.error = Some(format!("Failed to pause: {}", e));
+                    }
+
                     self.playing = false;
                 }
             }
 
             OpMessage::Stop => {
-                self.session.pause().unwrap();
+                if let Err(e) = self.session.pause() {
+                    self.error = Some(format!("Failed to stop: {}", e));
+                }
+
                 self.session.seek(0);
-                self.recording = false;
-                self.session.set_recording(false, self.armed_track);
                 self.playing = false;
+                return self.set_recording(false, self.armed_track);
             }
 
             OpMessage::PlaybackTick => {
@@ -124,37 +401,109 @@ impl Application for OpApplication {
             }
 
             OpMessage::SetRecording(recording) => {
-                self.recording = recording;
-                self.session.set_recording(true, self.armed_track);
+                return self.set_recording(recording, self.armed_track);
             }
 
             OpMessage::SetArmedTrack(armed_track) => {
                 if !self.playing {
                     self.armed_track = armed_track;
-                    self.session.set_recording(self.recording, armed_track);
+                    return self.set_recording(self.recording, armed_track);
                 }
             }
 
-            OpMessage::SetGenerator(generator) => {
-                self.current_generator = generator;
+            OpMessage::SetEngine(engine) => {
+                self.current_engine = engine;
 
-                let mut dsp: Box<dyn FaustDsp<T=f32>> = match generator {
-                    0 => Box::new(faust_engines::Sine::new()),
-                    1 => Box::new(faust_engines::Saw::new()),
-                    _ => return Command::none(),
-                };
+                #[cfg(feature = "hot-reload")]
+                self.hot_reload.reset();
 
-                let sample_rate = self.session.project.read().unwrap().sample_rate;
-                dsp.init(sample_rate as i32);
+                let engine_ui = apply_generator(&mut self.session, self.engine_constructor(), self.voice_stealing);
+                self.engine_panel = EnginePanel::new(engine_ui);
+            }
+
+            OpMessage::SetVoiceStealing(stealing) => {
+                self.voice_stealing = stealing;
+                apply_generator(&mut self.session, self.engine_constructor(), self.voice_stealing);
+                self.engine_panel.apply(&self.session);
+            }
+
+            OpMessage::EngineParam(message) => {
+                engine_panel_update(&mut self.engine_panel, &self.session, message);
+            }
 
-                let generator = Box::new(FaustGenerator::new(dsp));
-                self.session.set_generator(generator);
+            OpMessage::SetMasterGain(gain_db) => {
+                let edit = Edit::set_project_param(&self.session.project.read().unwrap(), ProjectParam::MasterGain(gain_db));
+                self.session.edit_gesture(edit);
+            }
+
+            OpMessage::SetLimiter(limiter) => {
+                let edit = Edit::set_project_param(&self.session.project.read().unwrap(), ProjectParam::Limiter(limiter));
+                self.session.edit(edit);
+            }
+
+            OpMessage::SetTempo(tempo) => {
+                let edit = Edit::set_project_param(&self.session.project.read().unwrap(), ProjectParam::Tempo(tempo));
+                self.session.edit_gesture(edit);
+            }
+
+            OpMessage::SetTimeSignature(time_signature) => {
+                let edit = Edit::set_project_param(&self.session.project.read().unwrap(), ProjectParam::TimeSignature(time_signature));
+                self.session.edit(edit);
+            }
+
+            OpMessage::SetMetronome(enabled) => {
+                let edit = Edit::set_project_param(&self.session.project.read().unwrap(), ProjectParam::Metronome(enabled));
+                self.session.edit(edit);
+            }
+
+            OpMessage::SetCountIn(bars) => {
+                let edit = Edit::set_project_param(&self.session.project.read().unwrap(), ProjectParam::CountIn(bars));
+                self.session.edit(edit);
+            }
+
+            OpMessage::EndGesture => {
+                self.session.end_gesture();
+            }
+
+            OpMessage::Undo => {
+                self.session.undo();
+            }
+
+            OpMessage::Redo => {
+                self.session.redo();
+            }
+
+            OpMessage::MeterTick => {
+                self.meter = self.session.take_meter();
+                self.engine_panel.tick(&self.session);
+            }
+
+            OpMessage::ResetClip => {
+                self.session.reset_clip();
+                self.meter.clipped = false;
+            }
+
+            #[cfg(feature = "hot-reload")]
+            OpMessage::HotReloadTick => {
+                if self.hot_reload.poll(self.current_engine) {
+                    let engine_ui = apply_generator(&mut self.session, self.engine_constructor(), self.voice_stealing);
+                    self.engine_panel.reload(engine_ui);
+                    self.engine_panel.apply(&self.session);
+                }
             }
 
             OpMessage::InputEvent(event) => {
                 match event {
                     Event::Keyboard(keyboard_event) => {
                         match keyboard_event {
+                            // Handled before the virtual keyboard, which uses Z to change octave
+                            KeyPressed { key_code: KeyCode::Z, modifiers } if modifiers.command() => {
+                                return self.update(if modifiers.shift() { OpMessage::Redo } else { OpMessage::Undo });
+                            }
+                            KeyPressed { key_code: KeyCode::Delete, .. } => {
+                                return self.update(OpMessage::Timeline(TimelineMessage::DeleteSelection));
+                            }
+
                             KeyPressed { key_code: c, .. } => { self.held_keys.insert(c); }
                             KeyReleased { key_code: c, .. } => { self.held_keys.remove(&c); }
                             _ => {}
@@ -164,7 +513,19 @@ impl Application for OpApplication {
                             self.session.handle(msg);
                         }
                     }
-                    Event::Window(window::Event::CloseRequested) => { return window::close(); }
+                    Event::Window(window::Event::CloseRequested) => {
+                        if !self.confirm_discard_takes() {
+                            return Command::none();
+                        }
+
+                        // Autosaves of other sessions are kept if they weren't restored or
+                        // discarded, so they can be offered again
+                        if let Some(recovery) = self.recovery.take() {
+                            let _ = recovery.remove();
+                        }
+
+                        return window::close();
+                    }
                     _ => {}
                 };
             }
@@ -173,20 +534,27 @@ impl Application for OpApplication {
                 self.zoom = zoom;
             }
 
+            OpMessage::SetSnap(snap) => {
+                self.timeline_state.snap = snap;
+            }
+
             // TODO: Don't block UI to show the file dialog in save/load/export
 
             OpMessage::Save => {
-                let project = self.session.project.read().unwrap();
+                let path = match &self.project_path {
+                    Some(path) => path.clone(),
+                    None => match rfd::FileDialog::new().save_file() {
+                        None => return Command::none(),
+                        Some(path) => path
+                    }
+                };
 
-                if let Some(path) = &self.project_path {
-                    project.save(path).unwrap();
-                } else {
-                    let dialog = rfd::FileDialog::new();
-                    let path = dialog.save_file();
-                    if let Some(path) = path {
-                        project.save(&path).unwrap();
+                match self.session.save(&path) {
+                    Ok(()) => {
                         self.project_path = Some(path);
+                        self.update_record_dir();
                     }
+                    Err(e) => self.error = Some(format!("Failed to save {}: {}", path.display(), e)),
                 }
             }
 
@@ -197,15 +565,12 @@ impl Application for OpApplication {
                     Some(path) => path
                 };
 
-                let project = Project::load(&path).unwrap();
-                let mut session = Session::new_with_project(project).unwrap();
-                apply_default_generator(&mut session);
-
-                self.project_path = Some(path);
-                self.session = session;
-                self.playing = false;
-                self.recording = false;
-                self.armed_track = 0;
+                match Project::load(&path) {
+                    Ok(project) => {
+                        self.open_project(project, Some(path));
+                    }
+                    Err(e) => self.error = Some(format!("Failed to load {}: {}", path.display(), e)),
+                }
             }
 
             OpMessage::Export => {
@@ -216,12 +581,67 @@ impl Application for OpApplication {
                     Some(path) => path
                 };
 
+                let effects = self.session.effect_registry();
                 let project = self.session.project.read().unwrap();
-                project.export_wav(&path).unwrap();
+                if let Err(e) = project.export_wav(&path, effects) {
+                    self.error = Some(format!("Failed to export {}: {}", path.display(), e));
+                }
+            }
+
+            OpMessage::Autosave => {
+                self.autosave();
+            }
+
+            OpMessage::RestoreRecovery => {
+                if self.abandoned_autosaves.is_empty() {
+                    return Command::none();
+                }
+
+                // Restored as an unsaved project, so that saving doesn't overwrite the original
+                // without asking. A session that crashed before its first autosave may only have
+                // left takes.
+                let dir = self.abandoned_autosaves.remove(0);
+                let project = if Project::exists(&dir) { Project::load(&dir) } else { Ok(Project::new()) };
+
+                match project {
+                    Ok(project) => {
+                        // Kept to try again later if the project couldn't be opened
+                        if !self.open_project(project, None) {
+                            self.abandoned_autosaves.insert(0, dir);
+                            return Command::none();
+                        }
+
+                        if let Err(e) = self.session.recover_takes(&dir) {
+                            self.error = Some(format!("Failed to recover recordings: {}", e));
+                            return Command::none();
+                        }
+
+                        // The old autosave is only removed once this session has its own copy
+                        if self.autosave() {
+                            let _ = fs::remove_dir_all(dir);
+                        }
+                    }
+                    Err(e) => self.error = Some(format!("Failed to restore the autosave: {}", e)),
+                }
+            }
+
+            OpMessage::DiscardRecovery => {
+                if !self.abandoned_autosaves.is_empty() {
+                    let _ = fs::remove_dir_all(self.abandoned_autosaves.remove(0));
+                }
+            }
+
+            OpMessage::DismissError => {
+                self.error = None;
+            }
+
+            OpMessage::PurgeUnusedClips => {
+                let edit = Edit::purge_unused_clips(&self.session.project.read().unwrap());
+                self.session.edit(edit);
             }
 
             OpMessage::Timeline(message) => {
-                timeline_update(&mut self.session.project.write().unwrap().timeline, message);
+                timeline_update(&mut self.timeline_state, &mut self.session, message);
             }
         };
 
@@ -231,7 +651,6 @@ impl Application for OpApplication {
     fn view(&self) -> Element<'_, Self::Message> {
         let project = self.session.project.read().unwrap();
         let tracks: Vec<usize> = (0..project.timeline.tracks.len()).collect();
-        let generators: Vec<usize> = vec![0, 1];
 
         let transport_controls = row![
             if !self.playing {
@@ -244,17 +663,45 @@ impl Application for OpApplication {
             pick_list(tracks, Some(self.armed_track), OpMessage::SetArmedTrack)
         ].spacing(4);
 
+        let peak_db = 20.0 * self.meter.peak.max(1e-5).log10();
+        let grid = project.time_grid();
+        let time = self.session.time();
+        let time_display = if self.session.counting_in() {
+            "Count-in".to_string()
+        } else {
+            format!("{}  {:.2} s", grid.samples_to_bbt(time), grid.samples_to_seconds(time))
+        };
+
         let status_display = row![
-            text(format!("{}", self.session.time()))
+            text(time_display)
                 .width(Length::Fill)
                 .horizontal_alignment(Horizontal::Center)
                 .vertical_alignment(Vertical::Center),
-        ];
+            text(format!("{:.1} dB", peak_db))
+                .width(Length::Fixed(70.0))
+                .horizontal_alignment(Horizontal::Right)
+                .vertical_alignment(Vertical::Center),
+            button(if self.meter.clipped { "CLIP" } else { "    " }).on_press(OpMessage::ResetClip),
+        ].spacing(4);
+
+        // Buttons without a message are shown as disabled
+        let mut undo_button = button("Undo");
+        if self.session.can_undo() {
+            undo_button = undo_button.on_press(OpMessage::Undo);
+        }
+
+        let mut redo_button = button("Redo");
+        if self.session.can_redo() {
+            redo_button = redo_button.on_press(OpMessage::Redo);
+        }
 
         let project_controls = container(row![
+            undo_button,
+            redo_button,
             button("Load").on_press(OpMessage::Load),
             button("Save").on_press(OpMessage::Save),
             button("Export").on_press(OpMessage::Export),
+            button("Purge unused clips").on_press(OpMessage::PurgeUnusedClips),
         ].spacing(4)).align_x(Horizontal::Right);
 
         let top_bar = container(row![
@@ -265,28 +712,76 @@ impl Application for OpApplication {
             .padding(8)
             .width(Length::Fill);
 
-        let temp_generator_control = container(row![
-            pick_list(generators, Some(self.current_generator.clone()), OpMessage::SetGenerator),
-        ])
+        let mut generator_controls = column![
+            row![
+                pick_list(Engine::all(), Some(self.current_engine), OpMessage::SetEngine),
+                pick_list(&VoiceStealing::ALL[..], Some(self.voice_stealing), OpMessage::SetVoiceStealing),
+            ].spacing(4),
+            engine_panel_view(&self.engine_panel).map(OpMessage::EngineParam),
+        ].spacing(8);
+
+        if let Some(error) = self.hot_reload_error() {
+            generator_controls = generator_controls.push(text(error.to_string()).style(Color::from_rgb(1.0, 0.4, 0.4)));
+        }
+
+        let temp_generator_control = container(generator_controls)
             .padding(8)
             .width(Length::Fill);
 
+        let effects = self.session.effect_registry();
         let timeline =
-            timeline_view(&project.timeline, &project.clip_database, self.zoom, self.session.time())
+            timeline_view(&project, &effects, &self.timeline_state, self.zoom, time)
                 .map(|m| OpMessage::Timeline(m));
 
         let temp_sliders = container(column![
             container(row![
                 text("Zoom").width(Length::Fixed(100.0)),
-                slider(0.05..=5.0, self.zoom, OpMessage::SetZoom).step(0.01)]),
+                slider(0.05..=5.0, self.zoom, OpMessage::SetZoom).step(0.01),
+                pick_list(&Snap::ALL[..], Some(self.timeline_state.snap), OpMessage::SetSnap),
+                button("Quantize").on_press(OpMessage::Timeline(TimelineMessage::QuantizeSelection)),
+                button("Split").on_press(OpMessage::Timeline(TimelineMessage::SplitSelection)),
+                button("Delete").on_press(OpMessage::Timeline(TimelineMessage::DeleteSelection)),
+            ].spacing(4).align_items(Alignment::Center)),
+            container(row![
+                text("Master").width(Length::Fixed(100.0)),
+                slider(-24.0..=12.0, project.master.gain_db, OpMessage::SetMasterGain).step(0.1).on_release(OpMessage::EndGesture),
+                text(format!("{:.1} dB", project.master.gain_db)).width(Length::Fixed(70.0)),
+                pick_list(&LimiterMode::ALL[..], Some(project.master.limiter), OpMessage::SetLimiter),
+            ].spacing(4).align_items(Alignment::Center)),
+            container(row![
+                text("Tempo").width(Length::Fixed(100.0)),
+                slider(40.0..=240.0, project.tempo, OpMessage::SetTempo).step(1.0).on_release(OpMessage::EndGesture),
+                text(format!("{:.0} BPM", project.tempo)).width(Length::Fixed(70.0)),
+                pick_list(&TimeSignature::COMMON[..], Some(project.time_signature), OpMessage::SetTimeSignature),
+                checkbox("Metronome", project.metronome.enabled, OpMessage::SetMetronome),
+                text("Count-in"),
+                pick_list(&COUNT_IN_BARS[..], Some(project.metronome.count_in_bars), OpMessage::SetCountIn),
+            ].spacing(4).align_items(Alignment::Center)),
         ]);
 
-        column![
-            top_bar,
-            temp_generator_control,
-            timeline,
-            temp_sliders,
-        ].into()
+        let mut content = Column::new();
+
+        if !self.abandoned_autosaves.is_empty() {
+            content = content.push(container(row![
+                text("A previous session didn't exit normally. Restore its autosave?").width(Length::Fill),
+                button("Restore").on_press(OpMessage::RestoreRecovery),
+                button("Discard").on_press(OpMessage::DiscardRecovery),
+            ].spacing(4).align_items(Alignment::Center)).padding(8));
+        }
+
+        if let Some(error) = &self.error {
+            content = content.push(container(row![
+                text(error.clone()).style(Color::from_rgb(1.0, 0.4, 0.4)).width(Length::Fill),
+                button("Dismiss").on_press(OpMessage::DismissError),
+            ].spacing(4).align_items(Alignment::Center)).padding(8));
+        }
+
+        content
+            .push(top_bar)
+            .push(temp_generator_control)
+            .push(timeline)
+            .push(temp_sliders)
+            .into()
     }
 
     fn theme(&self) -> Self::Theme {
@@ -300,7 +795,16 @@ impl Application for OpApplication {
             } else {
                 Subscription::none()
             },
+            time::every(Duration::from_millis(50)).map(|_| OpMessage::MeterTick),
+            if self.recovery.is_some() {
+                time::every(AUTOSAVE_INTERVAL).map(|_| OpMessage::Autosave)
+            } else {
+                Subscription::none()
+            },
             subscription::events().map(OpMessage::InputEvent),
+
+            #[cfg(feature = "hot-reload")]
+            time::every(Duration::from_millis(250)).map(|_| OpMessage::HotReloadTick),
         ])
     }
 }
@@ -0,0 +1,134 @@
+//! Directories that sessions are autosaved to. Every running instance has its own, which it keeps
+//! locked until it exits. A directory that is left behind unlocked belongs to an instance that
+//! crashed, and can be restored.
+
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File in a recovery directory that its instance holds a lock on.
+const LOCK_FILE_NAME: &str = "lock";
+
+/// Returns the directory that every instance's recovery directory is created in.
+fn recovery_root() -> PathBuf {
+    std::env::temp_dir().join("op_application").join("recovery")
+}
+
+/// The recovery directory of this instance. It stays locked until it is removed or dropped.
+/// Dropping it without removing it leaves it behind to be restored, as a crash would.
+pub struct RecoveryDir {
+    path: PathBuf,
+    lock: File,
+}
+
+impl RecoveryDir {
+    /// Creates and locks a new recovery directory for this process.
+    pub fn create() -> io::Result<Self> {
+        Self::create_in(&recovery_root())
+    }
+
+    fn create_in(root: &Path) -> io::Result<Self> {
+        fs::create_dir_all(root)?;
+
+        // Named after the process. A directory left behind by an earlier process with the same PID
+        // is skipped.
+        let mut path = PathBuf::new();
+        for n in 0.. {
+            path = root.join(format!("{}-{}", std::process::id(), n));
+            match fs::create_dir(&path) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let lock = File::create(path.join(LOCK_FILE_NAME))?;
+        lock.try_lock().map_err(io::Error::from)?;
+
+        Ok(Self { path, lock })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Unlocks and removes the directory, when the application exits normally.
+    pub fn remove(self) -> io::Result<()> {
+        let RecoveryDir { path, lock } = self;
+        drop(lock);
+        fs::remove_dir_all(path)
+    }
+}
+
+/// Returns the recovery directories left behind by instances that did not exit normally, most
+/// recently used first.
+pub fn find_abandoned() -> Vec<PathBuf> {
+    find_abandoned_in(&recovery_root())
+}
+
+fn find_abandoned_in(root: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<(SystemTime, PathBuf)> = match root.read_dir() {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_abandoned(path))
+            .map(|path| (modified(&path), path))
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+
+    dirs.sort_by_key(|(modified, _)| Reverse(*modified));
+    dirs.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Whether `dir` is a recovery directory that no running instance holds the lock of. The lock is
+/// taken here only to test it, and released right away.
+fn is_abandoned(dir: &Path) -> bool {
+    match File::open(dir.join(LOCK_FILE_NAME)) {
+        Ok(lock) => lock.try_lock().is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn modified(path: &Path) -> SystemTime {
+    fs::metadata(path).and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("op_application_test_{}", name));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn test_locked_dir_is_not_abandoned() {
+        let root = temp_root("recovery_locked");
+        let first = RecoveryDir::create_in(&root).unwrap();
+        let second = RecoveryDir::create_in(&root).unwrap();
+        assert_ne!(first.path(), second.path(), "every instance should get its own directory");
+        assert!(find_abandoned_in(&root).is_empty());
+
+        first.remove().unwrap();
+        second.remove().unwrap();
+        assert!(find_abandoned_in(&root).is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_dir_is_abandoned() {
+        let root = temp_root("recovery_dropped");
+        let dir = RecoveryDir::create_in(&root).unwrap();
+        let path = dir.path().to_path_buf();
+
+        // Like a crash, which releases the lock but leaves the directory
+        drop(dir);
+        assert_eq!(vec![path], find_abandoned_in(&root));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+
+use iced::{Alignment, Element, Length};
+use iced::widget::{button, checkbox, Column, progress_bar, Row, slider, text, vertical_slider};
+
+use op_engine::Session;
+
+use crate::faust::{DspUi, GroupKind, is_note_param, ParamIndex, ParamInfo, UiItem, WidgetKind};
+
+const LABEL_WIDTH: f32 = 100.0;
+const VALUE_WIDTH: f32 = 80.0;
+
+#[derive(Debug, Clone)]
+pub enum ParamMessage {
+    Set(ParamIndex, f32),
+
+    /// Sets a parameter to 1 until the next tick, for buttons.
+    Trigger(ParamIndex),
+
+    /// Selects the tab at the given position in a tab group, identified by its position among the
+    /// tab groups of the panel.
+    SelectTab(usize, usize),
+}
+
+/// Controls for the parameters of the active engine, laid out as the engine declares them.
+pub struct EnginePanel {
+    ui: DspUi,
+    values: HashMap<ParamIndex, f32>,
+    triggered: HashSet<ParamIndex>,
+    selected_tabs: HashMap<usize, usize>,
+}
+
+impl EnginePanel {
+    pub fn new(ui: DspUi) -> Self {
+        let values = ui.params.iter().map(|p| (p.index, p.init)).collect();
+
+        Self {
+            ui,
+            values,
+            triggered: HashSet::new(),
+            selected_tabs: HashMap::new(),
+        }
+    }
+
+    /// Replaces the interface with that of a new version of the engine. Parameters keep their
+    /// values if the new version has an input with the same label.
+    pub fn reload(&mut self, ui: DspUi) {
+        let mut reloaded = Self::new(ui);
+
+        for param in reloaded.ui.params.iter().filter(|p| p.is_input() && p.kind != WidgetKind::Button) {
+            let old = self.ui.params.iter().find(|old| old.label == param.label && old.is_input());
+            if let Some(old) = old {
+                reloaded.values.insert(param.index, self.value(old).clamp(param.min, param.max));
+            }
+        }
+
+        reloaded.selected_tabs = std::mem::take(&mut self.selected_tabs);
+        *self = reloaded;
+    }
+
+    /// Sends every value shown in the panel to the session's generator, e.g. after it was replaced.
+    pub fn apply(&self, session: &Session) {
+        for param in self.ui.params.iter().filter(|p| p.is_input() && !is_note_param(p)) {
+            session.set_generator_param(param.index.0 as usize, self.value(param));
+        }
+    }
+
+    /// Releases triggered buttons and reads outputs of the generator. Called periodically.
+    pub fn tick(&mut self, session: &Session) {
+        for index in self.triggered.drain() {
+            session.set_generator_param(index.0 as usize, 0.0);
+            self.values.insert(index, 0.0);
+        }
+
+        for param in self.ui.params.iter().filter(|p| !p.is_input()) {
+            if let Some(value) = session.generator_param(param.index.0 as usize) {
+                self.values.insert(param.index, value);
+            }
+        }
+    }
+
+    fn value(&self, param: &ParamInfo) -> f32 {
+        self.values.get(&param.index).copied().unwrap_or(param.init)
+    }
+}
+
+pub fn engine_panel_update(panel: &mut EnginePanel, session: &Session, message: ParamMessage) {
+    match message {
+        ParamMessage::Set(index, value) => {
+            session.set_generator_param(index.0 as usize, value);
+            panel.values.insert(index, value);
+        }
+
+        ParamMessage::Trigger(index) => {
+            session.set_generator_param(index.0 as usize, 1.0);
+            panel.values.insert(index, 1.0);
+            panel.triggered.insert(index);
+        }
+
+        ParamMessage::SelectTab(group, tab) => {
+            panel.selected_tabs.insert(group, tab);
+        }
+    }
+}
+
+/// Formats `value` with as many decimals as `step` needs, followed by the parameter's unit.
+fn format_value(param: &ParamInfo, value: f32) -> String {
+    let decimals = match param.step {
+        step if step <= 0.0 => 2,
+        step if step >= 1.0 => 0,
+        step => (-step.log10()).ceil() as usize,
+    };
+
+    match param.unit() {
+        Some(unit) => format!("{:.*} {}", decimals, value, unit),
+        None => format!("{:.*}", decimals, value),
+    }
+}
+
+fn step(param: &ParamInfo) -> f32 {
+    if param.step > 0.0 { param.step } else { (param.max - param.min) / 100.0 }
+}
+
+fn param_view(panel: &EnginePanel, param: &ParamInfo) -> Element<'static, ParamMessage> {
+    let index = param.index;
+    let value = panel.value(param);
+    let label = text(param.label.clone()).width(Length::Fixed(LABEL_WIDTH));
+    let value_text = text(format_value(param, value)).width(Length::Fixed(VALUE_WIDTH));
+
+    match param.kind {
+        WidgetKind::Button => {
+            button(text(param.label.clone())).on_press(ParamMessage::Trigger(index)).into()
+        }
+
+        WidgetKind::CheckButton => {
+            checkbox(&param.label, value > 0.5, move |checked| {
+                ParamMessage::Set(index, if checked { 1.0 } else { 0.0 })
+            }).into()
+        }
+
+        WidgetKind::HorizontalSlider => {
+            Row::with_children(vec![
+                label.into(),
+                slider(param.min..=param.max, value, move |v| ParamMessage::Set(index, v)).step(step(param)).into(),
+                value_text.into(),
+            ]).spacing(4).align_items(Alignment::Center).into()
+        }
+
+        WidgetKind::VerticalSlider => {
+            Column::with_children(vec![
+                text(param.label.clone()).into(),
+                vertical_slider(param.min..=param.max, value, move |v| ParamMessage::Set(index, v))
+                    .step(step(param))
+                    .height(Length::Fixed(100.0))
+                    .into(),
+                text(format_value(param, value)).into(),
+            ]).spacing(4).align_items(Alignment::Center).into()
+        }
+
+        WidgetKind::NumEntry => {
+            let (min, max, step) = (param.min, param.max, step(param));
+
+            Row::with_children(vec![
+                label.into(),
+                button("-").on_press(ParamMessage::Set(index, (value - step).max(min))).into(),
+                value_text.into(),
+                button("+").on_press(ParamMessage::Set(index, (value + step).min(max))).into(),
+            ]).spacing(4).align_items(Alignment::Center).into()
+        }
+
+        // iced has no vertical progress bar, so both bargraphs are drawn horizontally
+        WidgetKind::HorizontalBargraph | WidgetKind::VerticalBargraph => {
+            Row::with_children(vec![
+                label.into(),
+                progress_bar(param.min..=param.max, value).height(Length::Fixed(12.0)).into(),
+                value_text.into(),
+            ]).spacing(4).align_items(Alignment::Center).into()
+        }
+    }
+}
+
+fn item_label(ui: &DspUi, item: &UiItem) -> String {
+    match item {
+        UiItem::Group { label, .. } => label.clone(),
+        UiItem::Param(i) => ui.params[*i].label.clone(),
+    }
+}
+
+/// Returns the view of `item`, or None if it has nothing to show. `tab_groups` counts the tab
+/// groups seen so far, to identify them.
+fn item_view(panel: &EnginePanel, item: &UiItem, tab_groups: &mut usize) -> Option<Element<'static, ParamMessage>> {
+    let (kind, label, items) = match item {
+        UiItem::Param(i) => {
+            let param = &panel.ui.params[*i];
+            return if is_note_param(param) { None } else { Some(param_view(panel, param)) };
+        }
+        UiItem::Group { kind, label, items } => (*kind, label, items),
+    };
+
+    let content: Element<'static, ParamMessage> = match kind {
+        GroupKind::Tab => {
+            let group = *tab_groups;
+            *tab_groups += 1;
+
+            let selected = panel.selected_tabs.get(&group).copied().unwrap_or(0).min(items.len().saturating_sub(1));
+            let tabs = items.iter().enumerate().map(|(tab, item)| {
+                let tab_button = button(text(item_label(&panel.ui, item)));
+                let tab_button = if tab == selected { tab_button } else { tab_button.on_press(ParamMessage::SelectTab(group, tab)) };
+                tab_button.into()
+            }).collect();
+
+            // Tab groups nested in unselected tabs still need to be counted to keep ids stable
+            let mut pages: Vec<Option<Element<'static, ParamMessage>>> = items
+                .iter()
+                .map(|item| item_view(panel, item, tab_groups))
+                .collect();
+
+            let page = pages.get_mut(selected).and_then(Option::take);
+            let mut children = vec![Row::with_children(tabs).spacing(4).into()];
+            children.extend(page);
+
+            Column::with_children(children).spacing(8).into()
+        }
+
+        GroupKind::Horizontal | GroupKind::Vertical => {
+            let children: Vec<_> = items.iter().filter_map(|item| item_view(panel, item, tab_groups)).collect();
+
+            // Groups which only contain note parameters are hidden along with them
+            if children.is_empty() {
+                return None;
+            }
+
+            if kind == GroupKind::Horizontal {
+                Row::with_children(children).spacing(16).into()
+            } else {
+                Column::with_children(children).spacing(4).into()
+            }
+        }
+    };
+
+    Some(Column::with_children(vec![text(label.clone()).size(14).into(), content]).spacing(4).into())
+}
+
+pub fn engine_panel_view(panel: &EnginePanel) -> Element<'static, ParamMessage> {
+    let mut tab_groups = 0;
+    let children = panel.ui.items
+        .iter()
+        .filter_map(|item| item_view(panel, item, &mut tab_groups))
+        .collect();
+
+    Column::with_children(children).spacing(8).into()
+}
@@ -1 +1,2 @@
+pub mod engine_panel;
 pub mod timeline;
@@ -1,19 +1,28 @@
+use std::collections::HashSet;
+use std::fmt;
 use std::iter;
 
-use iced::{Color, Element, Length, mouse, Point, Rectangle, Theme};
+use iced::{Color, Element, keyboard, Length, mouse, Point, Rectangle, Theme};
 use iced::alignment::Vertical;
 use iced::mouse::Interaction;
-use iced::widget::Canvas;
+use iced::widget::{button, Canvas, checkbox, pick_list, slider};
 use iced::widget::canvas::{Cursor, Event, Fill, Frame, Geometry, LineCap, LineJoin, Path, Program, Stroke, Style};
 use iced_native::event::Status;
 use iced_native::row;
 use iced_native::widget::{column, container, text};
 
-use op_engine::clip_database::{ClipDatabase, ClipId};
-use op_engine::track::ClipInstance;
+use op_engine::clip_database::ClipDatabase;
+use op_engine::effect::{EffectRegistry, EffectSettings};
+use op_engine::history::{Edit, TrackParam};
+use op_engine::{Project, Session};
+use op_engine::snap::Snap;
+use op_engine::time::TimeGrid;
+use op_engine::track::{ClipInstance, ClipInstanceId};
 
 const BASE_SAMPLES_PER_PIXEL: f32 = 300.0;
-const BASE_RULER_SPACING_SAMPLES: f32 = 22050.0;
+
+/// Ruler lines closer together than this are thinned out.
+const MIN_RULER_SPACING_PIXELS: f32 = 8.0;
 
 fn samples_to_pixels(samples: i32, zoom: f32) -> f32 {
     let samples_per_pixel = BASE_SAMPLES_PER_PIXEL * zoom;
@@ -26,7 +35,8 @@ fn pixels_to_samples(pixels: f32, zoom: f32) -> i32 {
 }
 
 struct ClipLayout {
-    clip_id: ClipId,
+    id: ClipInstanceId,
+    time: op_engine::Time,
     waveform: Vec<f32>,
 
     x: f32,
@@ -39,8 +49,9 @@ impl ClipLayout {
         let clip = clip_db.get(clip_instance.clip_id).expect("TODO: Missing clip UI");
 
         Self {
-            clip_id: clip_instance.clip_id,
-            waveform: clip.data.chunks(pixels_to_samples(1.0, zoom) as usize)
+            id: clip_instance.id,
+            time: clip_instance.time,
+            waveform: clip.data.chunks(pixels_to_samples(1.0, zoom) as usize * clip.channels)
                 .map(|chunk| {
                     chunk.iter().map(|s| s.abs()).sum::<f32>() / (chunk.len() as f32)
                 })
@@ -65,7 +76,7 @@ impl ClipLayout {
         1.0 * (1.0 - y.abs()) * (height - 12.0)
     }
 
-    pub fn draw(&self, bounds: &Rectangle, hovered: bool, offset: i32) -> impl Iterator<Item=Geometry> {
+    pub fn draw(&self, bounds: &Rectangle, hovered: bool, selected: bool, offset: i32) -> impl Iterator<Item=Geometry> {
         let mut frame = Frame::new(bounds.size());
         if self.waveform.len() > 0 {
             let mut point = Point::new(self.x + samples_to_pixels(offset, self.zoom), Self::waveform_y(&self.waveform[0], bounds.height));
@@ -84,7 +95,7 @@ impl ClipLayout {
 
             frame.stroke(&path, Stroke::default()
                 .with_width(if hovered { 4.0 } else { 2.0 })
-                .with_color(Color::WHITE)
+                .with_color(if selected { Color::from_rgb(0.4, 0.7, 1.0) } else { Color::WHITE })
                 .with_line_cap(LineCap::Square)
                 .with_line_join(LineJoin::Bevel));
         }
@@ -94,28 +105,93 @@ impl ClipLayout {
 }
 
 pub struct TrackProgram {
+    grid: TimeGrid,
+    snap: Snap,
     zoom: f32,
     start_time: op_engine::Time,
     current_time: op_engine::Time,
     clip_layouts: Vec<ClipLayout>,
+    selected_clips: Vec<ClipInstanceId>,
 }
 
 #[derive(Default)]
 pub struct TrackProgramState {
-    hovered_clip: Option<ClipId>,
-    dragging_clip: Option<ClipId>,
+    hovered_clip: Option<ClipInstanceId>,
+    dragging_clip: Option<ClipInstanceId>,
     drag_origin: i32,
     drag_current: i32,
+    modifiers: keyboard::Modifiers,
 }
 
 #[derive(Debug, Clone)]
 pub enum TrackMessage {
-    MoveClip { clip_id: ClipId, delta_samples: i32 },
+    /// Moves a clip by the distance it was dragged. If `snap` is set, the clip's start snaps to
+    /// the grid.
+    MoveClip { id: ClipInstanceId, delta_samples: i32, snap: bool },
+
+    /// Selects a clip, adding it to the selection if `extend` is set.
+    SelectClip { id: ClipInstanceId, extend: bool },
+    ClearSelection,
+
+    /// Ends dragging a slider, so that the next change is undone separately.
+    SliderReleased,
+
+    SetGain(f32),
+    SetPan(f32),
+    SetMuted(bool),
+    SetSoloed(bool),
+
+    /// Adds an insert effect at the end of the track's chain.
+    AddEffect(EffectChoice),
+    RemoveEffect(usize),
+}
+
+/// An effect from the registry, as it is offered for inserting on a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectChoice {
+    kind: String,
+    name: String,
+}
+
+impl fmt::Display for EffectChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Lists a track's insert effects, each with a button removing it, followed by a list of effects
+/// to add.
+fn effects_view(track: &op_engine::Track, registry: &EffectRegistry) -> Element<'static, TrackMessage> {
+    let inserts = track.effects
+        .iter()
+        .enumerate()
+        .map(|(i, effect)| -> Element<'static, TrackMessage> {
+            let name = registry.get(&effect.kind).map_or(effect.kind.as_str(), |e| e.name.as_str());
+            row![
+                text(name).width(Length::Fill),
+                button("x").on_press(TrackMessage::RemoveEffect(i)),
+            ]
+                .spacing(4)
+                .into()
+        });
+
+    let choices: Vec<EffectChoice> = registry
+        .iter()
+        .map(|e| EffectChoice { kind: e.kind.clone(), name: e.name.clone() })
+        .collect();
+    let add = pick_list(choices, None, TrackMessage::AddEffect).placeholder("Insert");
+
+    column(inserts.chain(iter::once(add.into())).collect())
+        .spacing(4)
+        .into()
 }
 
 impl TrackProgram {
-    pub fn new(track: &op_engine::Track, clip_db: &ClipDatabase, zoom: f32, current_time: op_engine::Time) -> Self {
+    pub fn new(track: &op_engine::Track, clip_db: &ClipDatabase, grid: TimeGrid, snap: Snap, zoom: f32, current_time: op_engine::Time, selected_clips: Vec<ClipInstanceId>) -> Self {
         Self {
+            grid,
+            snap,
+            selected_clips,
             zoom,
             current_time,
             start_time: 0,
@@ -137,30 +213,63 @@ impl TrackProgram {
         iter::once(background)
     }
 
+    /// Draws a line on every beat, with brighter lines on bars. Beats are skipped when zoomed out
+    /// too far to tell them apart, and then bars as well.
     fn draw_ruler(&self, bounds: &Rectangle) -> impl Iterator<Item=Geometry> {
-        let marks = 20;
+        let beats_per_bar = self.grid.beats_per_bar() as u64;
+        let beat_width = samples_to_pixels(self.grid.samples_per_beat() as i32, self.zoom);
+
+        let mut step = 1;
+        if beat_width < MIN_RULER_SPACING_PIXELS {
+            step = beats_per_bar;
+        }
+
+        while beat_width * (step as f32) < MIN_RULER_SPACING_PIXELS {
+            step *= 2;
+        }
 
-        let first_mark_number = self.start_time / BASE_RULER_SPACING_SAMPLES as usize;
-        let first_mark_time = first_mark_number * BASE_RULER_SPACING_SAMPLES as usize;
+        let first_beat = self.grid.samples_to_beats(self.start_time) as u64 / step * step;
 
-        let path = Path::new(|builder| {
-            for i in 0..marks {
-                let time = first_mark_time + i * BASE_RULER_SPACING_SAMPLES as usize;
-                let x = samples_to_pixels(time as i32, self.zoom);
-                builder.move_to(Point::new(x, 0.0));
-                builder.line_to(Point::new(x, bounds.height));
+        // Pairs of x position and beat within the bar
+        let lines: Vec<(f32, u64)> = (first_beat..)
+            .step_by(step as usize)
+            .map(|beat| {
+                let time = self.grid.beats_to_samples(beat as f64) as i32 - self.start_time as i32;
+                (samples_to_pixels(time, self.zoom), beat % beats_per_bar)
+            })
+            .take_while(|&(x, _)| x <= bounds.width)
+            .collect();
+
+        let path = |bars: bool| Path::new(|builder| {
+            for &(x, beat_in_bar) in lines.iter() {
+                if (beat_in_bar == 0) == bars {
+                    builder.move_to(Point::new(x, 0.0));
+                    builder.line_to(Point::new(x, bounds.height));
+                }
             }
         });
 
         let mut frame = Frame::new(bounds.size());
-        frame.stroke(&path, Stroke::default()
+        frame.stroke(&path(false), Stroke::default()
             .with_width(1.0)
             .with_color(Color::from_rgb(0.22, 0.22, 0.22)));
+        frame.stroke(&path(true), Stroke::default()
+            .with_width(1.0)
+            .with_color(Color::from_rgb(0.35, 0.35, 0.35)));
 
         let background = frame.into_geometry();
         iter::once(background)
     }
 
+    /// Returns how far a clip at `time` moves when dragged by `delta` samples.
+    fn drag_offset(&self, state: &TrackProgramState, time: op_engine::Time, delta: i32) -> i32 {
+        if state.modifiers.alt() {
+            return delta;
+        }
+
+        self.snap.snap_move(&self.grid, time, delta as i64) as i32 - time as i32
+    }
+
     fn draw_playhead(&self, bounds: &Rectangle) -> impl Iterator<Item=Geometry> {
         let playhead_relative_x = self.current_time - self.start_time;
         let x = samples_to_pixels(playhead_relative_x as i32, self.zoom);
@@ -200,30 +309,42 @@ impl Program<TrackMessage> for TrackProgram {
                 let clip_bounds = c.clip_bounds(&bounds);
                 cursor.is_over(&clip_bounds)
             })
-            .map(|c| c.clip_id);
+            .map(|c| c.id);
+
+        if let Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) = event {
+            state.modifiers = modifiers;
+        }
 
         if let Event::Mouse(mouse::Event::CursorMoved { position, .. }) = event {
             state.drag_current = pixels_to_samples(position.x, self.zoom);
         }
 
         if let Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) = event {
-            if let Some(clip_id) = state.dragging_clip {
-                println!("Released clip {:?}, change of {:?} samples", clip_id, state.drag_current - state.drag_origin);
+            if let Some(id) = state.dragging_clip {
+                println!("Released clip {:?}, change of {:?} samples", id, state.drag_current - state.drag_origin);
                 state.dragging_clip = None;
-                return (Status::Captured, Some(TrackMessage::MoveClip { clip_id, delta_samples: state.drag_current - state.drag_origin }));
+                let delta_samples = state.drag_current - state.drag_origin;
+                let snap = !state.modifiers.alt();
+                return (Status::Captured, Some(TrackMessage::MoveClip { id, delta_samples, snap }));
             }
         }
 
         if let Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) = event {
-            if let Some(clip_id) = state.hovered_clip {
-                println!("Pressed clip {:?}", clip_id);
-                state.dragging_clip = Some(clip_id);
+            if let Some(id) = state.hovered_clip {
+                println!("Pressed clip {:?}", id);
+                state.dragging_clip = Some(id);
 
                 if let Some(cursor_pos) = cursor.position() {
                     state.drag_origin = pixels_to_samples(cursor_pos.x, self.zoom);
+                    state.drag_current = state.drag_origin;
                 }
 
-                return (Status::Captured, None);
+                let extend = state.modifiers.shift() || state.modifiers.control();
+                return (Status::Captured, Some(TrackMessage::SelectClip { id, extend }));
+            }
+
+            if cursor.is_over(&bounds) {
+                return (Status::Captured, Some(TrackMessage::ClearSelection));
             }
         }
 
@@ -235,11 +356,12 @@ impl Program<TrackMessage> for TrackProgram {
             .chain(self.draw_ruler(&bounds))
             .chain(self.draw_playhead(&bounds))
             .chain(self.clip_layouts.iter().flat_map(|c| {
-                let is_dragging = Some(c.clip_id) == state.dragging_clip;
-                let is_highlighted = is_dragging || (state.dragging_clip.is_none() && Some(c.clip_id) == state.hovered_clip);
-                let offset = if is_dragging { state.drag_current - state.drag_origin } else { 0 };
+                let is_dragging = Some(c.id) == state.dragging_clip;
+                let is_highlighted = is_dragging || (state.dragging_clip.is_none() && Some(c.id) == state.hovered_clip);
+                let is_selected = self.selected_clips.contains(&c.id);
+                let offset = if is_dragging { self.drag_offset(state, c.time, state.drag_current - state.drag_origin) } else { 0 };
 
-                c.draw(&bounds, is_highlighted, offset)
+                c.draw(&bounds, is_highlighted, is_selected, offset)
             }))
             .collect()
     }
@@ -256,13 +378,26 @@ impl Program<TrackMessage> for TrackProgram {
     }
 }
 
-fn track_view(number: usize, track: &op_engine::Track, clip_db: &ClipDatabase, zoom: f32, current_time: usize) -> Element<'static, TrackMessage> {
-    let program = TrackProgram::new(track, clip_db, zoom, current_time);
+fn track_view(number: usize, track: &op_engine::Track, project: &Project, effects: &EffectRegistry, state: &TimelineState, zoom: f32, current_time: usize) -> Element<'static, TrackMessage> {
+    let selected_clips = track.iter_clips().map(|c| c.id).filter(|id| state.selection.contains(id)).collect();
+    let program = TrackProgram::new(track, &project.clip_database, project.time_grid(), state.snap, zoom, current_time, selected_clips);
     let clip_area = Canvas::new(program).width(Length::Fill);
 
-    let track_header = text(format!("{}", number))
-        .height(Length::Fill)
-        .vertical_alignment(Vertical::Center);
+    let track_header = iced_native::column![
+        text(format!("{}", number))
+            .vertical_alignment(Vertical::Center),
+        row![
+            checkbox("M", track.muted, TrackMessage::SetMuted),
+            checkbox("S", track.soloed, TrackMessage::SetSoloed),
+        ].spacing(8),
+        text(format!("{:.1} dB", track.gain_db)),
+        slider(-48.0..=12.0, track.gain_db, TrackMessage::SetGain).step(0.1).on_release(TrackMessage::SliderReleased),
+        text("Pan"),
+        slider(-1.0..=1.0, track.pan, TrackMessage::SetPan).step(0.01).on_release(TrackMessage::SliderReleased),
+        effects_view(track, effects),
+    ]
+        .spacing(4)
+        .width(Length::Fixed(100.0));
 
     row![track_header, clip_area]
         .padding(20.0)
@@ -271,18 +406,34 @@ fn track_view(number: usize, track: &op_engine::Track, clip_db: &ClipDatabase, z
         .into()
 }
 
+/// Editing state of the timeline which is not part of the project.
+#[derive(Default)]
+pub struct TimelineState {
+    pub snap: Snap,
+
+    selection: HashSet<ClipInstanceId>,
+}
+
 #[derive(Debug, Clone)]
 pub enum TimelineMessage {
     Track(usize, TrackMessage),
+
+    /// Moves every selected clip to the nearest grid line.
+    QuantizeSelection,
+
+    DeleteSelection,
+
+    /// Splits every selected clip at the playhead.
+    SplitSelection,
 }
 
-pub fn timeline_view(timeline: &op_engine::Timeline, clip_db: &ClipDatabase, zoom: f32, current_time: usize) -> Element<'static, TimelineMessage> {
+pub fn timeline_view(project: &Project, effects: &EffectRegistry, state: &TimelineState, zoom: f32, current_time: usize) -> Element<'static, TimelineMessage> {
     container(
-        column(timeline.tracks
+        column(project.timeline.tracks
             .iter()
             .enumerate()
             .map(|(i, track)| {
-                track_view(i, track, clip_db, zoom, current_time).map(move |m| TimelineMessage::Track(i, m))
+                track_view(i, track, project, effects, state, zoom, current_time).map(move |m| TimelineMessage::Track(i, m))
             })
             .collect())
     )
@@ -292,21 +443,80 @@ pub fn timeline_view(timeline: &op_engine::Timeline, clip_db: &ClipDatabase, zoo
         .into()
 }
 
-pub fn track_update(track: &mut op_engine::Track, message: TrackMessage) {
-    match message {
-        TrackMessage::MoveClip { clip_id, delta_samples, .. } => {
-            if let Some(mut instance) = track.get_clip_mut(clip_id) {
-                instance.time = (instance.time as i32 + delta_samples) as usize;
+pub fn track_update(session: &mut Session, track: usize, snap: Snap, message: TrackMessage) {
+    let (edit, gesture) = {
+        let project = session.project.read().unwrap();
+        let param = |param| Some(Edit::set_track_param(&project.timeline, track, param));
+
+        match message {
+            TrackMessage::MoveClip { id, delta_samples, snap: snapped } => {
+                let snap = if snapped { snap } else { Snap::Off };
+                (Edit::move_clip(&project.timeline, id, delta_samples as i64, snap, &project.time_grid()), false)
+            }
+            TrackMessage::SelectClip { .. } | TrackMessage::ClearSelection => (None, false),
+            TrackMessage::SliderReleased => (None, false),
+            TrackMessage::SetGain(gain_db) => (param(TrackParam::Gain(gain_db)), true),
+            TrackMessage::SetPan(pan) => (param(TrackParam::Pan(pan)), true),
+            TrackMessage::SetMuted(muted) => (param(TrackParam::Muted(muted)), false),
+            TrackMessage::SetSoloed(soloed) => (param(TrackParam::Soloed(soloed)), false),
+            TrackMessage::AddEffect(choice) => {
+                (Some(Edit::AddEffect { track, effect: EffectSettings::new(&choice.kind) }), false)
             }
+            TrackMessage::RemoveEffect(index) => (Edit::remove_effect(&project.timeline, track, index), false),
         }
+    };
+
+    match edit {
+        Some(edit) if gesture => session.edit_gesture(edit),
+        Some(edit) => session.edit(edit),
+        None => (),
     }
 }
 
-pub fn timeline_update(timeline: &mut op_engine::Timeline, message: TimelineMessage) {
+pub fn timeline_update(state: &mut TimelineState, session: &mut Session, message: TimelineMessage) {
+    let selection: Vec<ClipInstanceId> = state.selection.iter().copied().collect();
+
     match message {
+        TimelineMessage::Track(_, TrackMessage::SelectClip { id, extend }) => {
+            if !extend {
+                state.selection.clear();
+            }
+
+            state.selection.insert(id);
+        }
+
+        TimelineMessage::Track(_, TrackMessage::ClearSelection) => {
+            state.selection.clear();
+        }
+
+        TimelineMessage::Track(_, TrackMessage::SliderReleased) => {
+            session.end_gesture();
+        }
+
         TimelineMessage::Track(track_number, message) => {
-            let track = &mut timeline.tracks[track_number];
-            track_update(track, message);
+            track_update(session, track_number, state.snap, message);
+        }
+
+        TimelineMessage::QuantizeSelection => {
+            let edit = {
+                let project = session.project.read().unwrap();
+                Edit::quantize(&project.timeline, &selection, state.snap, &project.time_grid())
+            };
+
+            session.edit(edit);
+        }
+
+        TimelineMessage::DeleteSelection => {
+            let edit = Edit::remove_clips(&session.project.read().unwrap().timeline, &selection);
+            session.edit(edit);
+            state.selection.clear();
+        }
+
+        TimelineMessage::SplitSelection => {
+            let time = session.time();
+            let edit = Edit::split_clips(&session.project.read().unwrap(), &selection, time);
+            session.edit(edit);
+            state.selection.clear();
         }
     }
 }
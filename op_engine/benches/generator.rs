@@ -0,0 +1,46 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main, Throughput};
+use midly::MidiMessage;
+
+use op_engine::generator::Generator;
+use op_engine::generator::sine::SineGenerator;
+
+const BLOCK_SIZES: [usize; 3] = [64, 512, 4096];
+
+fn generator() -> Box<dyn Generator> {
+    let mut generator: Box<dyn Generator> = Box::new(SineGenerator::new(44100));
+    generator.handle(MidiMessage::NoteOn { key: 69.into(), vel: 100.into() });
+    generator
+}
+
+/// Compares filling a block one sample at a time through `next` with filling it in one call to
+/// `process`. Both go through a `Box<dyn Generator>`, as in the player.
+fn bench_generator(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sine");
+
+    for block_size in BLOCK_SIZES {
+        group.throughput(Throughput::Elements(block_size as u64));
+
+        group.bench_with_input(BenchmarkId::new("next", block_size), &block_size, |b, &block_size| {
+            let mut generator = generator();
+            let mut buf = vec![0.0; block_size];
+
+            b.iter(|| {
+                for sample in buf.iter_mut() {
+                    *sample = generator.next();
+                }
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("process", block_size), &block_size, |b, &block_size| {
+            let mut generator = generator();
+            let mut buf = vec![0.0; block_size];
+
+            b.iter(|| generator.process(&mut buf));
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_generator);
+criterion_main!(benches);
@@ -1,10 +1,25 @@
-use hound::SampleFormat;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavReader};
 
 use crate::clip::ClipError::ClipReadError;
+use crate::resample::resample_interleaved;
+
+fn default_channels() -> usize {
+    1
+}
 
+/// A Clip is a piece of audio. Multi-channel audio is stored interleaved, i.e. `data` contains
+/// `channels` samples for every frame.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Clip {
     pub data: Vec<f32>,
+
+    #[serde(default = "default_channels")]
+    pub channels: usize,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -14,37 +29,409 @@ pub enum ClipError {
         source: hound::Error,
     },
 
-    #[error("unsupported sample format: {bits_per_sample}")]
+    #[error("failed to write audio file: {source}")]
+    ClipWriteError {
+        source: hound::Error,
+    },
+
+    #[error("unsupported sample format: {bits_per_sample}-bit {encoding}")]
     UnsupportedSampleFormat {
-        bits_per_sample: u16
+        bits_per_sample: u16,
+        encoding: &'static str,
     },
+
+    #[error("channel {channel} does not exist in audio file with {channels} channels")]
+    ChannelOutOfRange {
+        channel: u16,
+        channels: u16,
+    },
+
+    #[error("recording stopped unexpectedly, the take was kept in {}", path.display())]
+    RecordingAborted {
+        path: PathBuf,
+    },
+}
+
+/// Determines how audio files with more than one channel are converted when loaded into a clip.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelPolicy {
+    /// Keep every channel of the file.
+    #[default]
+    Keep,
+
+    /// Average all channels together into a mono clip.
+    Downmix,
+
+    /// Keep only the channel with the given (zero-based) index and discard the rest.
+    Channel(u16),
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+fn is_supported(format_tag: u16, bits_per_sample: u16) -> bool {
+    match format_tag {
+        WAVE_FORMAT_PCM => (1..=32).contains(&bits_per_sample),
+        WAVE_FORMAT_IEEE_FLOAT => bits_per_sample == 32,
+        _ => false,
+    }
+}
+
+fn encoding_name(format_tag: u16) -> &'static str {
+    match format_tag {
+        WAVE_FORMAT_PCM => "integer PCM",
+        WAVE_FORMAT_IEEE_FLOAT => "float",
+        0x0002 => "ADPCM",
+        0x0006 => "A-law",
+        0x0007 => "mu-law",
+        _ => "compressed",
+    }
+}
+
+/// Reads the format tag and bit depth from the `fmt ` chunk of a WAV file. This is only used to
+/// describe files that hound refuses to open, so it does no validation beyond what is needed to
+/// find the chunk.
+fn read_wav_format(path: &String) -> io::Result<Option<(u16, u16)>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    loop {
+        let mut chunk_header = [0u8; 8];
+        if file.read_exact(&mut chunk_header).is_err() {
+            return Ok(None);
+        }
+
+        let chunk_len = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+
+        if &chunk_header[0..4] != b"fmt " {
+            file.seek(SeekFrom::Current(chunk_len as i64 + (chunk_len % 2) as i64))?;
+            continue;
+        }
+
+        let mut fmt = vec![0u8; chunk_len as usize];
+        file.read_exact(&mut fmt)?;
+
+        if fmt.len() < 16 {
+            return Ok(None);
+        }
+
+        let mut format_tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+        let bits_per_sample = u16::from_le_bytes([fmt[14], fmt[15]]);
+
+        // The actual format of an extensible file is in the first two bytes of the sub-format GUID
+        if format_tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+            format_tag = u16::from_le_bytes([fmt[24], fmt[25]]);
+        }
+
+        return Ok(Some((format_tag, bits_per_sample)));
+    }
+}
+
+/// Reads all samples from a WAV file as interleaved floats in [-1, 1].
+fn read_samples<R: Read>(reader: &mut WavReader<R>) -> Result<Vec<f32>, ClipError> {
+    let spec = reader.spec();
+
+    match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => {
+            reader.samples::<f32>()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ClipReadError { source: e })
+        }
+
+        (SampleFormat::Int, 1..=32) => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ClipReadError { source: e })
+        }
+
+        (sample_format, bits_per_sample) => Err(ClipError::UnsupportedSampleFormat {
+            bits_per_sample,
+            encoding: match sample_format {
+                SampleFormat::Float => encoding_name(WAVE_FORMAT_IEEE_FLOAT),
+                SampleFormat::Int => encoding_name(WAVE_FORMAT_PCM),
+            },
+        }),
+    }
+}
+
+/// Converts interleaved samples with `channels` channels according to `policy`. Returns the
+/// converted samples and their channel count.
+fn apply_channel_policy(samples: Vec<f32>, channels: u16, policy: ChannelPolicy) -> Result<(Vec<f32>, usize), ClipError> {
+    if channels == 1 {
+        return Ok((samples, 1));
+    }
+
+    let frames = samples.chunks_exact(channels as usize);
+
+    match policy {
+        ChannelPolicy::Keep => Ok((samples, channels as usize)),
+
+        ChannelPolicy::Downmix => {
+            Ok((frames.map(|frame| frame.iter().sum::<f32>() / channels as f32).collect(), 1))
+        }
+
+        ChannelPolicy::Channel(channel) if channel < channels => {
+            Ok((frames.map(|frame| frame[channel as usize]).collect(), 1))
+        }
+
+        ChannelPolicy::Channel(channel) => Err(ClipError::ChannelOutOfRange { channel, channels }),
+    }
 }
 
 impl Clip {
+    /// Creates a mono clip.
     pub fn new(data: Vec<f32>) -> Self {
-        Self { data }
+        Self::new_interleaved(data, 1)
+    }
+
+    /// Creates a clip from interleaved samples with the given number of channels.
+    pub fn new_interleaved(data: Vec<f32>, channels: usize) -> Self {
+        debug_assert!(channels > 0);
+        debug_assert_eq!(data.len() % channels, 0);
+        Self { data, channels }
     }
 
-    pub fn load_wav(sample_rate: u32, path: &String) -> Result<Self, ClipError> {
-        let mut reader = hound::WavReader::open(path).map_err(|e| ClipReadError { source: e })?;
+    /// Loads a WAV file, converting it to `sample_rate` and combining its channels according to
+    /// `channel_policy`. 8, 16, 24 and 32-bit integer and 32-bit float files are supported.
+    pub fn load_wav(sample_rate: u32, path: &String, channel_policy: ChannelPolicy) -> Result<Self, ClipError> {
+        let mut reader = match WavReader::open(path) {
+            Ok(reader) => reader,
+            Err(e @ (hound::Error::Unsupported | hound::Error::FormatError(_))) => {
+                return Err(match read_wav_format(path) {
+                    Ok(Some((format_tag, bits_per_sample))) if !is_supported(format_tag, bits_per_sample) => ClipError::UnsupportedSampleFormat {
+                        bits_per_sample,
+                        encoding: encoding_name(format_tag),
+                    },
+                    _ => ClipReadError { source: e },
+                });
+            }
+            Err(e) => return Err(ClipReadError { source: e }),
+        };
+
         let spec = reader.spec();
+        let samples = read_samples(&mut reader)?;
+        let (samples, channels) = apply_channel_policy(samples, spec.channels, channel_policy)?;
+        let samples = resample_interleaved(&samples, channels, spec.sample_rate, sample_rate);
+
+        Ok(Clip::new_interleaved(samples, channels))
+    }
+
+    /// Writes this clip to a 32-bit float WAV file, which stores every sample exactly.
+    pub fn save_wav(&self, sample_rate: u32, path: &Path) -> Result<(), ClipError> {
+        let spec = hound::WavSpec {
+            channels: self.channels as u16,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
 
-        // TODO: Resample
-        debug_assert_eq!(spec.sample_rate, sample_rate);
-        debug_assert_eq!(spec.sample_format, SampleFormat::Int);
+        let write = || {
+            let mut writer = hound::WavWriter::create(path, spec)?;
+            for &sample in &self.data {
+                writer.write_sample(sample)?;
+            }
 
-        let samples = reader.samples::<i32>()
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| ClipReadError { source: e })?
-            .into_iter()
-            .map(|s| { s as f32 / ((1 << spec.bits_per_sample) / 2 - 1) as f32 })
-            .step_by(spec.channels as usize)
-            .collect::<Vec<_>>();
+            writer.finalize()
+        };
 
-        Ok(Clip::new(samples))
+        write().map_err(|e| ClipError::ClipWriteError { source: e })
     }
 
+    /// Returns the length of this clip in frames.
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.data.len() / self.channels
+    }
+
+    /// Returns the samples of frame `i`, one per channel.
+    pub fn frame(&self, i: usize) -> &[f32] {
+        &self.data[i * self.channels..(i + 1) * self.channels]
+    }
+
+    /// Splits this clip into the frames before `frame` and the frames from `frame` on.
+    pub fn split_at(&self, frame: usize) -> (Clip, Clip) {
+        let (before, after) = self.data.split_at(frame.min(self.len()) * self.channels);
+        (Clip::new_interleaved(before.to_vec(), self.channels), Clip::new_interleaved(after.to_vec(), self.channels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::path::PathBuf;
+
+    use hound::WavSpec;
+
+    use super::*;
+
+    fn temp_wav_path(name: &str) -> String {
+        let path: PathBuf = std::env::temp_dir().join(format!("op_engine_test_{}.wav", name));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn spec(channels: u16, bits_per_sample: u16, sample_format: SampleFormat) -> WavSpec {
+        WavSpec {
+            channels,
+            sample_rate: 44100,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+
+    fn write_int_wav(name: &str, spec: WavSpec, samples: &[i32]) -> String {
+        let path = temp_wav_path(name);
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for &s in samples {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    fn assert_samples_eq(expected: &[f32], actual: &[f32]) {
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(actual) {
+            assert!((e - a).abs() < 1e-6, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_load_wav_resamples_to_project_rate() {
+        let path = temp_wav_path("load_wav_resamples");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..48000 {
+            let s = (2.0 * PI * 1000.0 * i as f32 / 48000.0).sin() * 0.5;
+            writer.write_sample((s * i16::MAX as f32) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let clip = Clip::load_wav(44100, &path, ChannelPolicy::Downmix).unwrap();
+        assert_eq!(clip.len(), 44100);
+
+        // One second of a 1 kHz tone should still contain 1000 rising zero crossings
+        let crossings = clip.data.windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        assert!((999..=1000).contains(&crossings), "got {} crossings", crossings);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_wav_int_bit_depths() {
+        for bits in [8u16, 16, 24, 32] {
+            let full_scale = 1i64 << (bits - 1);
+            let path = write_int_wav(
+                &format!("load_wav_int_{}", bits),
+                spec(1, bits, SampleFormat::Int),
+                &[0, (full_scale / 2) as i32, (-full_scale / 2) as i32, (-full_scale) as i32],
+            );
+
+            let clip = Clip::load_wav(44100, &path, ChannelPolicy::Downmix).unwrap();
+            assert_samples_eq(&[0.0, 0.5, -0.5, -1.0], &clip.data);
+
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_save_wav_round_trip() {
+        let path = temp_wav_path("save_wav_round_trip");
+        let clip = Clip::new_interleaved(vec![0.1, -0.2, 1.5, 1e-7, -1.0, 0.3333333], 2);
+        clip.save_wav(48000, Path::new(&path)).unwrap();
+
+        let loaded = Clip::load_wav(48000, &path, ChannelPolicy::Keep).unwrap();
+        assert_eq!(2, loaded.channels);
+        assert_eq!(clip.data, loaded.data, "samples should be stored exactly");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_wav_float() {
+        let path = temp_wav_path("load_wav_float");
+        let mut writer = hound::WavWriter::create(&path, spec(1, 32, SampleFormat::Float)).unwrap();
+        for s in [0.0f32, 0.25, -0.75, 1.0] {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let clip = Clip::load_wav(44100, &path, ChannelPolicy::Downmix).unwrap();
+        assert_samples_eq(&[0.0, 0.25, -0.75, 1.0], &clip.data);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_wav_channel_policy() {
+        let path = write_int_wav(
+            "load_wav_channel_policy",
+            spec(2, 16, SampleFormat::Int),
+            &[16384, 0, -16384, 16384],
+        );
+
+        let kept = Clip::load_wav(44100, &path, ChannelPolicy::Keep).unwrap();
+        assert_eq!(2, kept.channels);
+        assert_eq!(2, kept.len());
+        assert_samples_eq(&[0.5, 0.0, -0.5, 0.5], &kept.data);
+        assert_samples_eq(&[-0.5, 0.5], kept.frame(1));
+
+        let downmixed = Clip::load_wav(44100, &path, ChannelPolicy::Downmix).unwrap();
+        assert_eq!(1, downmixed.channels);
+        assert_samples_eq(&[0.25, 0.0], &downmixed.data);
+
+        let left = Clip::load_wav(44100, &path, ChannelPolicy::Channel(0)).unwrap();
+        assert_samples_eq(&[0.5, -0.5], &left.data);
+
+        let right = Clip::load_wav(44100, &path, ChannelPolicy::Channel(1)).unwrap();
+        assert_samples_eq(&[0.0, 0.5], &right.data);
+
+        let result = Clip::load_wav(44100, &path, ChannelPolicy::Channel(2));
+        assert!(matches!(result, Err(ClipError::ChannelOutOfRange { channel: 2, channels: 2 })));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_wav_unsupported_format() {
+        // hound cannot read 64-bit float files, so build one by hand
+        let mut bytes = Vec::new();
+        let data = [0u8; 16];
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(4 + 8 + 16 + 8 + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // channels
+        bytes.extend_from_slice(&44100u32.to_le_bytes()); // sample rate
+        bytes.extend_from_slice(&(44100u32 * 8).to_le_bytes()); // byte rate
+        bytes.extend_from_slice(&8u16.to_le_bytes()); // block align
+        bytes.extend_from_slice(&64u16.to_le_bytes()); // bits per sample
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&data);
+
+        let path = temp_wav_path("load_wav_unsupported_format");
+        std::fs::write(&path, bytes).unwrap();
+
+        let result = Clip::load_wav(44100, &path, ChannelPolicy::Downmix);
+        assert!(matches!(result, Err(ClipError::UnsupportedSampleFormat { bits_per_sample: 64, encoding: "float" })));
+
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -1,15 +1,110 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use serde::{Deserialize, Serialize};
 
-use crate::Clip;
+use crate::{ChannelPolicy, Clip, Timeline};
+use crate::project::ProjectError;
 
-#[derive(Debug, Eq, Hash, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, Hash, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize, Deserialize)]
 pub struct ClipId(usize);
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default)]
 pub struct ClipDatabase {
     clips: HashMap<ClipId, Clip>,
+
+    // Hash of each clip's audio, which names its file. Clips don't change once they are added, so
+    // this is only computed once per ID. Hashes are kept when their clip is removed, in case it is
+    // restored.
+    hashes: HashMap<ClipId, String>,
+
+    // ID of the next clip. IDs are never reused, so that removing a clip can't make a later one
+    // take its place. Atomic so that edits can reserve IDs while the project is only locked for
+    // reading.
+    next_id: AtomicUsize,
+}
+
+/// A clip as it is stored in a project file.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredClip {
+    /// Audio in a WAV file in the project's audio directory, named after the clip's ID and the
+    /// hash of its samples.
+    File { hash: String },
+
+    /// Audio written into the project file itself, as in version 0 projects saved before clips had
+    /// files. These are written to files the next time the project is saved.
+    Inline(Clip),
+}
+
+/// The clip database as it is stored in a project file.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct StoredClipDatabase {
+    clips: HashMap<ClipId, StoredClip>,
+
+    next_id: usize,
+}
+
+impl StoredClipDatabase {
+    /// Removes the files in `dir` that don't belong to any of the stored clips, such as those of
+    /// deleted clips. This should only be done once the project file refers to the new files.
+    pub(crate) fn remove_unused_files(&self, dir: &Path) -> Result<(), ProjectError> {
+        let used: HashSet<String> = self.clips.iter()
+            .filter_map(|(&id, clip)| match clip {
+                StoredClip::File { hash } => Some(clip_file_name(id, hash)),
+                StoredClip::Inline(_) => None,
+            })
+            .collect();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_clip_file = path.extension() == Some(OsStr::new("wav"));
+            let is_used = path.file_name().and_then(OsStr::to_str).is_some_and(|name| used.contains(name));
+
+            if is_clip_file && !is_used {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the FNV-1a hash of a clip's channel count and samples, as hex. Unlike std's hashers,
+/// the result is the same in every build, so it can be saved.
+fn hash_clip(clip: &Clip) -> String {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let hash = (clip.channels as u32).to_le_bytes()
+        .into_iter()
+        .chain(clip.data.iter().flat_map(|s| s.to_le_bytes()))
+        .fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME));
+
+    format!("{:016x}", hash)
+}
+
+fn clip_file_name(id: ClipId, hash: &str) -> String {
+    format!("{}-{}.wav", id.0, hash)
+}
+
+/// Writes a clip with the given hash to a WAV file in `dir`, unless its file already exists.
+fn write_clip_file(id: ClipId, clip: &Clip, hash: &str, dir: &Path, sample_rate: u32) -> Result<(), ProjectError> {
+    let path = dir.join(clip_file_name(id, hash));
+
+    if !path.exists() {
+        // Written under another name first, so that an interrupted save can't leave a partial
+        // file that looks complete
+        let temp_path = path.with_extension("wav.tmp");
+        clip.save_wav(sample_rate, &temp_path)
+            .map_err(|e| ProjectError::SaveClipError { path: temp_path.display().to_string(), source: e })?;
+        fs::rename(&temp_path, &path)?;
+    }
+
+    Ok(())
 }
 
 impl ClipDatabase {
@@ -18,12 +113,263 @@ impl ClipDatabase {
     }
 
     pub fn add(&mut self, clip: Clip) -> ClipId {
-        let id = ClipId(self.clips.len());
+        let id = self.reserve_id();
+        self.hashes.insert(id, hash_clip(&clip));
         self.clips.insert(id, clip);
         id
     }
 
+    /// Returns an ID that no clip has had before, for a clip that is added later with
+    /// [ClipDatabase::restore].
+    pub(crate) fn reserve_id(&self) -> ClipId {
+        ClipId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Puts back a clip that was removed, or adds one under a reserved ID.
+    pub(crate) fn restore(&mut self, id: ClipId, clip: Clip) {
+        debug_assert!(id.0 < self.next_id.load(Ordering::Relaxed));
+        self.hashes.entry(id).or_insert_with(|| hash_clip(&clip));
+        self.clips.insert(id, clip);
+    }
+
+    pub fn remove(&mut self, id: ClipId) -> Option<Clip> {
+        self.clips.remove(&id)
+    }
+
     pub fn get(&self, id: ClipId) -> Option<&Clip> {
         self.clips.get(&id)
     }
+
+    /// Whether a clip with the same audio as `clip` is in the database. Only clips with the same
+    /// hash are compared sample by sample.
+    pub(crate) fn contains(&self, clip: &Clip) -> bool {
+        let hash = hash_clip(clip);
+        self.clips.iter().any(|(id, c)| {
+            self.hashes.get(id) == Some(&hash) && c.channels == clip.channels && c.data == clip.data
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.clips.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clips.is_empty()
+    }
+
+    /// Returns the clips that aren't instantiated anywhere on `timeline`, in the order they were
+    /// added.
+    pub fn unused(&self, timeline: &Timeline) -> Vec<ClipId> {
+        let used: HashSet<ClipId> = timeline.iter_clips().map(|(_, c)| c.clip_id).collect();
+
+        let mut unused: Vec<ClipId> = self.clips.keys()
+            .filter(|id| !used.contains(id))
+            .copied()
+            .collect();
+
+        unused.sort();
+        unused
+    }
+
+    /// Writes each clip to a WAV file in `dir` and returns the database as it is stored in the
+    /// project file. A file's name identifies its contents, so clips that already have a file are
+    /// not written again. Their hashes are not computed again either.
+    pub(crate) fn save(&self, dir: &Path, sample_rate: u32) -> Result<StoredClipDatabase, ProjectError> {
+        fs::create_dir_all(dir)?;
+
+        let mut clips = HashMap::new();
+
+        for (&id, clip) in &self.clips {
+            let hash = self.hashes.get(&id).cloned().unwrap_or_else(|| hash_clip(clip));
+            write_clip_file(id, clip, &hash, dir, sample_rate)?;
+            clips.insert(id, StoredClip::File { hash });
+        }
+
+        Ok(StoredClipDatabase { clips, next_id: self.next_id.load(Ordering::Relaxed) })
+    }
+
+    /// Reads the clips of a stored database from the WAV files in `dir`.
+    pub(crate) fn load(stored: StoredClipDatabase, dir: &Path, sample_rate: u32) -> Result<Self, ProjectError> {
+        let mut clips = HashMap::new();
+        let mut hashes = HashMap::new();
+
+        for (id, stored_clip) in stored.clips {
+            let (clip, hash) = match stored_clip {
+                StoredClip::File { hash } => {
+                    let path = dir.join(clip_file_name(id, &hash)).display().to_string();
+                    let clip = Clip::load_wav(sample_rate, &path, ChannelPolicy::Keep)
+                        .map_err(|e| ProjectError::LoadClipError { path, source: e })?;
+                    (clip, hash)
+                }
+
+                StoredClip::Inline(clip) => {
+                    let hash = hash_clip(&clip);
+                    (clip, hash)
+                }
+            };
+
+            clips.insert(id, clip);
+            hashes.insert(id, hash);
+        }
+
+        Ok(Self {
+            clips,
+            hashes,
+            next_id: AtomicUsize::new(stored.next_id),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_ids_are_not_reused() {
+        let mut db = ClipDatabase::new();
+        let first = db.add(Clip::new(vec![1.0]));
+        let second = db.add(Clip::new(vec![2.0]));
+
+        assert_eq!(1.0, db.remove(first).unwrap().data[0]);
+        assert!(db.get(first).is_none());
+
+        let third = db.add(Clip::new(vec![3.0]));
+        assert_ne!(second, third);
+        assert_eq!(2.0, db.get(second).unwrap().data[0], "adding a clip should not replace another");
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("op_engine_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    fn save_and_load(db: &ClipDatabase, dir: &Path) -> ClipDatabase {
+        let stored = db.save(dir, 44100).unwrap();
+        let stored = serde_json::from_str(&serde_json::to_string(&stored).unwrap()).unwrap();
+        ClipDatabase::load(stored, dir, 44100).unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = temp_dir("clip_database_save_and_load");
+        let mut db = ClipDatabase::new();
+        let mono = db.add(Clip::new(vec![0.1, 0.2, 0.3]));
+        let stereo = db.add(Clip::new_interleaved(vec![0.5, -0.5, 1.0 / 3.0, 1e-9], 2));
+
+        let loaded = save_and_load(&db, &dir);
+        assert_eq!(2, loaded.len());
+        assert_eq!(db.get(mono).unwrap().data, loaded.get(mono).unwrap().data);
+        assert_eq!(db.get(stereo).unwrap().data, loaded.get(stereo).unwrap().data);
+        assert_eq!(2, loaded.get(stereo).unwrap().channels);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_skips_unchanged_and_removes_unused_files() {
+        let dir = temp_dir("clip_database_save_files");
+        let mut db = ClipDatabase::new();
+        let first = db.add(Clip::new(vec![1.0]));
+        let second = db.add(Clip::new(vec![2.0]));
+        db.save(&dir, 44100).unwrap();
+
+        let files = file_names(&dir);
+        assert_eq!(2, files.len());
+
+        // Overwrite a file to tell whether it gets written again
+        let unchanged = dir.join(&files[1]);
+        assert!(files[1].starts_with(&format!("{}-", second.0)));
+        fs::write(&unchanged, "unchanged").unwrap();
+
+        db.remove(first);
+        let stored = db.save(&dir, 44100).unwrap();
+        assert_eq!(files, file_names(&dir), "files should be kept until the project file is written");
+
+        stored.remove_unused_files(&dir).unwrap();
+        assert_eq!(vec![files[1].clone()], file_names(&dir));
+        assert_eq!("unchanged", fs::read_to_string(unchanged).unwrap());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_loaded_hashes_are_not_computed_again() {
+        let dir = temp_dir("clip_database_loaded_hashes");
+        let mut db = ClipDatabase::new();
+        let id = db.add(Clip::new(vec![1.0]));
+        db.save(&dir, 44100).unwrap();
+
+        // Give the file a hash that the audio doesn't have, which only a stored hash can know
+        let files = file_names(&dir);
+        fs::rename(dir.join(&files[0]), dir.join(clip_file_name(id, "stored"))).unwrap();
+        let mut stored = StoredClipDatabase::default();
+        stored.clips.insert(id, StoredClip::File { hash: "stored".to_string() });
+        stored.next_id = 1;
+
+        let loaded = ClipDatabase::load(stored, &dir, 44100).unwrap();
+        loaded.save(&dir, 44100).unwrap();
+        assert_eq!(vec![clip_file_name(id, "stored")], file_names(&dir));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_contains() {
+        let mut db = ClipDatabase::new();
+        let id = db.add(Clip::new(vec![1.0, 2.0]));
+
+        assert!(db.contains(&Clip::new(vec![1.0, 2.0])));
+        assert!(!db.contains(&Clip::new(vec![2.0, 1.0])));
+        assert!(!db.contains(&Clip { data: vec![1.0, 2.0], channels: 2 }));
+
+        db.remove(id);
+        assert!(!db.contains(&Clip::new(vec![1.0, 2.0])), "removed clips should not be found by their hash");
+    }
+
+    #[test]
+    fn test_next_id_survives_save_and_load() {
+        let dir = temp_dir("clip_database_next_id");
+        let mut db = ClipDatabase::new();
+        let first = db.add(Clip::new(vec![1.0]));
+        db.add(Clip::new(vec![2.0]));
+        db.remove(first);
+
+        let mut loaded = save_and_load(&db, &dir);
+        assert_eq!(ClipId(2), loaded.add(Clip::new(vec![3.0])));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_inline_clips() {
+        let stored = serde_json::from_str(r#"{"clips": {"0": {"data": [1.0]}, "3": {"data": [2.0, 3.0], "channels": 2}}, "next_id": 4}"#).unwrap();
+        let db = ClipDatabase::load(stored, Path::new("unused"), 44100).unwrap();
+        assert_eq!(vec![1.0], db.get(ClipId(0)).unwrap().data);
+        assert_eq!(vec![2.0, 3.0], db.get(ClipId(3)).unwrap().data);
+        assert_eq!(2, db.get(ClipId(3)).unwrap().channels);
+    }
+
+    #[test]
+    fn test_unused() {
+        let mut db = ClipDatabase::new();
+        let mut timeline = Timeline::new();
+
+        let clips: Vec<ClipId> = (0..4).map(|i| db.add(Clip::new(vec![i as f32]))).collect();
+        timeline.instantiate_clip(0, clips[1], 0);
+        timeline.instantiate_clip(3, clips[1], 10);
+        timeline.instantiate_clip(2, clips[2], 0);
+
+        assert_eq!(vec![clips[0], clips[3]], db.unused(&timeline));
+    }
 }
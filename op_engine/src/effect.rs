@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+pub mod biquad;
+pub mod delay;
+pub mod gain;
+pub mod reverb;
+
+/// Level below which a decaying tail is considered silent (-60 dB).
+const SILENCE: f32 = 0.001;
+
+/// Returns how many times a signal is fed back with gain `feedback` before it falls below
+/// [SILENCE].
+pub(crate) fn repeats_until_silent(feedback: f32) -> usize {
+    if feedback <= 0.0 {
+        return 1;
+    }
+
+    1 + (SILENCE.ln() / feedback.min(0.999).ln()).ceil() as usize
+}
+
+/// Describes a parameter of an effect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectParam {
+    pub name: String,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+}
+
+impl EffectParam {
+    pub fn new(name: &str, min: f32, max: f32, default: f32) -> Self {
+        Self { name: name.to_string(), min, max, default }
+    }
+}
+
+/// An Effect processes blocks of audio in place. Effects are configured through named parameters
+/// so that they can be described and saved without knowing their concrete type.
+pub trait Effect: Send {
+    fn params(&self) -> Vec<EffectParam>;
+    fn set_param(&mut self, name: &str, value: f32);
+
+    /// Processes `buf`, which holds interleaved frames with `channels` channels. The channel count
+    /// may change between calls.
+    fn process(&mut self, buf: &mut [f32], channels: usize);
+
+    /// Returns how many frames of output the effect keeps producing after its input falls silent.
+    /// Offline renders are extended by this much so that the tail isn't cut off.
+    fn tail(&self) -> usize {
+        0
+    }
+}
+
+/// Persistent description of an effect in a chain. Parameters which are not set use the effect's
+/// default value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectSettings {
+    pub kind: String,
+
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+
+    #[serde(default)]
+    pub bypass: bool,
+}
+
+impl EffectSettings {
+    pub fn new(kind: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            params: BTreeMap::new(),
+            bypass: false,
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: f32) -> Self {
+        self.params.insert(name.to_string(), value);
+        self
+    }
+}
+
+pub type EffectConstructor = fn(sample_rate: u32) -> Box<dyn Effect>;
+
+#[derive(Clone)]
+pub struct EffectInfo {
+    /// Identifier saved in EffectSettings.
+    pub kind: String,
+
+    /// Name shown to the user.
+    pub name: String,
+
+    pub constructor: EffectConstructor,
+}
+
+/// Maps effect kinds to constructors. The default registry contains the built-in effects, and
+/// more can be registered by the application.
+#[derive(Clone)]
+pub struct EffectRegistry {
+    effects: Vec<EffectInfo>,
+}
+
+impl EffectRegistry {
+    /// Creates a registry without any effects.
+    pub fn new() -> Self {
+        Self { effects: Vec::new() }
+    }
+
+    /// Adds an effect, replacing any existing effect of the same kind.
+    pub fn register(&mut self, kind: &str, name: &str, constructor: EffectConstructor) {
+        self.effects.retain(|e| e.kind != kind);
+        self.effects.push(EffectInfo {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            constructor,
+        });
+    }
+
+    pub fn get(&self, kind: &str) -> Option<&EffectInfo> {
+        self.effects.iter().find(|e| e.kind == kind)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&EffectInfo> {
+        self.effects.iter()
+    }
+
+    pub fn build(&self, kind: &str, sample_rate: u32) -> Option<Box<dyn Effect>> {
+        self.get(kind).map(|e| (e.constructor)(sample_rate))
+    }
+}
+
+impl Default for EffectRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(gain::KIND, "Gain", gain::Gain::boxed);
+        registry.register(biquad::KIND, "EQ", biquad::Biquad::boxed);
+        registry.register(delay::KIND, "Delay", delay::Delay::boxed);
+        registry.register(reverb::KIND, "Reverb", reverb::Reverb::boxed);
+        registry
+    }
+}
+
+struct EffectSlot {
+    kind: String,
+    params: BTreeMap<String, f32>,
+
+    // None if the kind is not in the registry, in which case the slot passes audio through
+    effect: Option<Box<dyn Effect>>,
+}
+
+impl EffectSlot {
+    fn new(settings: &EffectSettings, registry: &EffectRegistry, sample_rate: u32) -> Self {
+        let mut effect = registry.build(&settings.kind, sample_rate);
+
+        if let Some(effect) = &mut effect {
+            for (name, value) in &settings.params {
+                effect.set_param(name, *value);
+            }
+        }
+
+        Self {
+            kind: settings.kind.clone(),
+            params: settings.params.clone(),
+            effect,
+        }
+    }
+
+    fn update(&mut self, settings: &EffectSettings) {
+        if self.params == settings.params {
+            return;
+        }
+
+        if let Some(effect) = &mut self.effect {
+            for (name, value) in &settings.params {
+                if self.params.get(name) != Some(value) {
+                    effect.set_param(name, *value);
+                }
+            }
+
+            // Parameters that are no longer set go back to their defaults
+            if self.params.keys().any(|name| !settings.params.contains_key(name)) {
+                for param in effect.params() {
+                    if self.params.contains_key(&param.name) && !settings.params.contains_key(&param.name) {
+                        effect.set_param(&param.name, param.default);
+                    }
+                }
+            }
+        }
+
+        self.params.clone_from(&settings.params);
+    }
+}
+
+/// Runtime state for a chain of effects. The chain follows a list of EffectSettings: effects are
+/// created when they are added to the list and keep their state (e.g. delay lines) while only
+/// their parameters change.
+pub struct EffectChain {
+    sample_rate: u32,
+    slots: Vec<EffectSlot>,
+}
+
+impl EffectChain {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            slots: Vec::new(),
+        }
+    }
+
+    fn sync(&mut self, settings: &[EffectSettings], registry: &EffectRegistry) {
+        self.slots.truncate(settings.len());
+
+        for (i, settings) in settings.iter().enumerate() {
+            match self.slots.get_mut(i) {
+                Some(slot) if slot.kind == settings.kind => slot.update(settings),
+                Some(slot) => *slot = EffectSlot::new(settings, registry, self.sample_rate),
+                None => self.slots.push(EffectSlot::new(settings, registry, self.sample_rate)),
+            }
+        }
+    }
+
+    /// Processes `buf` (interleaved, with `channels` channels) through the effects described by
+    /// `settings`, in order.
+    pub fn process(&mut self, settings: &[EffectSettings], registry: &EffectRegistry, buf: &mut [f32], channels: usize) {
+        self.sync(settings, registry);
+
+        for (slot, settings) in self.slots.iter_mut().zip(settings) {
+            if settings.bypass {
+                continue;
+            }
+
+            if let Some(effect) = &mut slot.effect {
+                effect.process(buf, channels);
+            }
+        }
+    }
+
+    /// Returns how many frames the chain keeps producing output for after its input falls silent,
+    /// as of the last call to [EffectChain::process] with `settings`.
+    pub fn tail(&self, settings: &[EffectSettings]) -> usize {
+        self.slots.iter()
+            .zip(settings)
+            .filter(|(_, settings)| !settings.bypass)
+            .filter_map(|(slot, _)| slot.effect.as_ref())
+            .map(|effect| effect.tail())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds the number of times it has processed a block to every sample.
+    struct Counter {
+        count: f32,
+        step: f32,
+    }
+
+    impl Effect for Counter {
+        fn params(&self) -> Vec<EffectParam> {
+            vec![EffectParam::new("step", 0.0, 10.0, 1.0)]
+        }
+
+        fn set_param(&mut self, name: &str, value: f32) {
+            if name == "step" {
+                self.step = value;
+            }
+        }
+
+        fn process(&mut self, buf: &mut [f32], _channels: usize) {
+            self.count += self.step;
+            buf.iter_mut().for_each(|s| *s += self.count);
+        }
+    }
+
+    fn counter(_sample_rate: u32) -> Box<dyn Effect> {
+        Box::new(Counter { count: 0.0, step: 1.0 })
+    }
+
+    fn registry() -> EffectRegistry {
+        let mut registry = EffectRegistry::default();
+        registry.register("counter", "Counter", counter);
+        registry
+    }
+
+    #[test]
+    fn test_chain_keeps_state_across_param_changes() {
+        let registry = registry();
+        let mut chain = EffectChain::new(44100);
+        let mut settings = vec![EffectSettings::new("counter")];
+
+        let mut buf = [0.0];
+        chain.process(&settings, &registry, &mut buf, 1);
+        assert_eq!([1.0], buf);
+
+        settings[0].params.insert("step".to_string(), 2.0);
+        let mut buf = [0.0];
+        chain.process(&settings, &registry, &mut buf, 1);
+        assert_eq!([3.0], buf, "changing a parameter should not recreate the effect");
+
+        settings[0].params.remove("step");
+        let mut buf = [0.0];
+        chain.process(&settings, &registry, &mut buf, 1);
+        assert_eq!([4.0], buf, "a removed parameter should go back to its default");
+
+        settings[0] = EffectSettings::new("gain");
+        settings.push(EffectSettings::new("counter"));
+        let mut buf = [0.0];
+        chain.process(&settings, &registry, &mut buf, 1);
+        assert_eq!([1.0], buf, "changing the kind of an effect should recreate it");
+    }
+
+    #[test]
+    fn test_chain_order_and_bypass() {
+        let registry = registry();
+        let mut chain = EffectChain::new(44100);
+        let mut settings = vec![
+            EffectSettings::new("counter"),
+            EffectSettings::new(gain::KIND).with_param("gain", -6.0206),
+        ];
+
+        let mut buf = [1.0];
+        chain.process(&settings, &registry, &mut buf, 1);
+        assert!((buf[0] - 1.0).abs() < 1e-4, "gain should be applied after the counter");
+
+        settings[1].bypass = true;
+        let mut buf = [1.0];
+        chain.process(&settings, &registry, &mut buf, 1);
+        assert_eq!([3.0], buf);
+    }
+
+    #[test]
+    fn test_chain_tail() {
+        let registry = registry();
+        let mut chain = EffectChain::new(1000);
+        let mut settings = vec![
+            EffectSettings::new(delay::KIND).with_param("time", 10.0).with_param("feedback", 0.0),
+            EffectSettings::new(delay::KIND).with_param("time", 20.0).with_param("feedback", 0.0),
+            EffectSettings::new(gain::KIND),
+        ];
+
+        chain.process(&settings, &registry, &mut [0.0], 1);
+        assert_eq!(30, chain.tail(&settings));
+
+        settings[1].bypass = true;
+        assert_eq!(10, chain.tail(&settings));
+    }
+
+    #[test]
+    fn test_unknown_effect_passes_through() {
+        let mut chain = EffectChain::new(44100);
+        let mut buf = [0.5, 0.25];
+        chain.process(&[EffectSettings::new("missing")], &EffectRegistry::default(), &mut buf, 2);
+        assert_eq!([0.5, 0.25], buf);
+    }
+
+    #[test]
+    fn test_builtin_effects_are_registered() {
+        let registry = EffectRegistry::default();
+
+        for kind in [gain::KIND, biquad::KIND, delay::KIND, reverb::KIND] {
+            let effect = registry.build(kind, 44100).expect("built-in effect must be registered");
+            assert!(!effect.params().is_empty());
+        }
+    }
+}
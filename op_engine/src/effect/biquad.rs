@@ -0,0 +1,202 @@
+use std::f32::consts::PI;
+
+use crate::effect::{Effect, EffectParam};
+
+pub const KIND: &str = "eq";
+
+/// Filter shapes, selected by the `type` parameter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    Peak,
+    LowShelf,
+    HighShelf,
+}
+
+impl FilterType {
+    fn from_param(value: f32) -> Self {
+        match value.round() as i32 {
+            0 => FilterType::LowPass,
+            1 => FilterType::HighPass,
+            3 => FilterType::LowShelf,
+            4 => FilterType::HighShelf,
+            _ => FilterType::Peak,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+/// A single biquad filter band, using the formulas from Robert Bristow-Johnson's Audio EQ
+/// Cookbook.
+pub struct Biquad {
+    sample_rate: u32,
+    filter_type: FilterType,
+    freq: f32,
+    q: f32,
+    gain_db: f32,
+
+    coefficients: Coefficients,
+
+    // Transposed direct form II state for each channel
+    state: Vec<[f32; 2]>,
+}
+
+impl Biquad {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut biquad = Self {
+            sample_rate,
+            filter_type: FilterType::Peak,
+            freq: 1000.0,
+            q: 0.707,
+            gain_db: 0.0,
+
+            coefficients: Coefficients::default(),
+            state: Vec::new(),
+        };
+
+        biquad.update_coefficients();
+        biquad
+    }
+
+    pub fn boxed(sample_rate: u32) -> Box<dyn Effect> {
+        Box::new(Self::new(sample_rate))
+    }
+
+    fn update_coefficients(&mut self) {
+        let nyquist = self.sample_rate as f32 / 2.0;
+        let w0 = 2.0 * PI * self.freq.clamp(1.0, nyquist * 0.99) / self.sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * self.q.max(0.01));
+        let a = 10.0f32.powf(self.gain_db / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match self.filter_type {
+            FilterType::LowPass => (
+                (1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0,
+                1.0 + alpha, -2.0 * cos, 1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0,
+                1.0 + alpha, -2.0 * cos, 1.0 - alpha,
+            ),
+            FilterType::Peak => (
+                1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a,
+                1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + k),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - k),
+                    (a + 1.0) + (a - 1.0) * cos + k,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - k,
+                )
+            }
+            FilterType::HighShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + k),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - k),
+                    (a + 1.0) - (a - 1.0) * cos + k,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - k,
+                )
+            }
+        };
+
+        self.coefficients = Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        };
+    }
+}
+
+impl Effect for Biquad {
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("type", 0.0, 4.0, 2.0),
+            EffectParam::new("freq", 20.0, 20000.0, 1000.0),
+            EffectParam::new("q", 0.1, 10.0, 0.707),
+            EffectParam::new("gain", -24.0, 24.0, 0.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "type" => self.filter_type = FilterType::from_param(value),
+            "freq" => self.freq = value,
+            "q" => self.q = value,
+            "gain" => self.gain_db = value,
+            _ => return,
+        }
+
+        self.update_coefficients();
+    }
+
+    fn process(&mut self, buf: &mut [f32], channels: usize) {
+        if self.state.len() != channels {
+            self.state = vec![[0.0; 2]; channels];
+        }
+
+        let Coefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+
+        for frame in buf.chunks_exact_mut(channels) {
+            for (sample, z) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let x = *sample;
+                let y = b0 * x + z[0];
+                z[0] = b1 * x - a1 * y + z[1];
+                z[1] = b2 * x - a2 * y;
+                *sample = y;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the steady-state amplitude of a sine at `freq` after filtering.
+    fn response(biquad: &mut Biquad, freq: f32) -> f32 {
+        let mut buf: Vec<f32> = (0..44100)
+            .map(|i| (2.0 * PI * freq * i as f32 / 44100.0).sin())
+            .collect();
+        biquad.process(&mut buf, 1);
+        buf[22050..].iter().fold(0.0f32, |max, s| max.max(s.abs()))
+    }
+
+    #[test]
+    fn test_low_pass() {
+        let mut biquad = Biquad::new(44100);
+        biquad.set_param("type", 0.0);
+        biquad.set_param("freq", 1000.0);
+
+        assert!((response(&mut biquad, 100.0) - 1.0).abs() < 0.02);
+        assert!(response(&mut biquad, 10000.0) < 0.02);
+    }
+
+    #[test]
+    fn test_peak() {
+        let mut biquad = Biquad::new(44100);
+        biquad.set_param("type", 2.0);
+        biquad.set_param("freq", 1000.0);
+        biquad.set_param("gain", 6.0);
+
+        assert!((response(&mut biquad, 1000.0) - 10.0f32.powf(6.0 / 20.0)).abs() < 0.02);
+        assert!((response(&mut biquad, 50.0) - 1.0).abs() < 0.02);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::effect::{Effect, EffectParam, repeats_until_silent};
+
+pub const KIND: &str = "delay";
+
+const MAX_DELAY_SEC: f32 = 2.0;
+
+/// A feedback delay. Each channel is delayed independently.
+pub struct Delay {
+    sample_rate: u32,
+    time_ms: f32,
+    feedback: f32,
+    mix: f32,
+
+    buffer: Vec<f32>,  // interleaved
+    channels: usize,
+    pos: usize,
+}
+
+impl Delay {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            time_ms: 300.0,
+            feedback: 0.4,
+            mix: 0.3,
+
+            buffer: Vec::new(),
+            channels: 0,
+            pos: 0,
+        }
+    }
+
+    pub fn boxed(sample_rate: u32) -> Box<dyn Effect> {
+        Box::new(Self::new(sample_rate))
+    }
+
+    fn max_frames(&self) -> usize {
+        (self.sample_rate as f32 * MAX_DELAY_SEC) as usize
+    }
+
+    fn delay_frames(&self) -> usize {
+        ((self.time_ms / 1000.0 * self.sample_rate as f32) as usize).clamp(1, self.max_frames())
+    }
+}
+
+impl Effect for Delay {
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("time", 1.0, MAX_DELAY_SEC * 1000.0, 300.0),
+            EffectParam::new("feedback", 0.0, 0.95, 0.4),
+            EffectParam::new("mix", 0.0, 1.0, 0.3),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "time" => self.time_ms = value,
+            "feedback" => self.feedback = value.clamp(0.0, 0.95),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => (),
+        }
+    }
+
+    fn process(&mut self, buf: &mut [f32], channels: usize) {
+        if channels != self.channels {
+            self.channels = channels;
+            self.buffer = vec![0.0; self.max_frames() * channels];
+            self.pos = 0;
+        }
+
+        let frames = self.max_frames();
+        let delay = self.delay_frames();
+
+        for frame in buf.chunks_exact_mut(channels) {
+            let read = (self.pos + frames - delay) % frames;
+
+            for (c, sample) in frame.iter_mut().enumerate() {
+                let delayed = self.buffer[read * channels + c];
+                self.buffer[self.pos * channels + c] = *sample + delayed * self.feedback;
+                *sample = *sample * (1.0 - self.mix) + delayed * self.mix;
+            }
+
+            self.pos = (self.pos + 1) % frames;
+        }
+    }
+
+    fn tail(&self) -> usize {
+        self.delay_frames() * repeats_until_silent(self.feedback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let mut delay = Delay::new(1000);
+        delay.set_param("time", 3.0);
+        delay.set_param("feedback", 0.5);
+        delay.set_param("mix", 0.5);
+
+        let mut buf = vec![0.0; 8];
+        buf[0] = 1.0;
+        delay.process(&mut buf, 1);
+
+        assert_eq!(buf, vec![0.5, 0.0, 0.0, 0.5, 0.0, 0.0, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn test_delay_tail() {
+        let mut delay = Delay::new(1000);
+        delay.set_param("time", 3.0);
+        delay.set_param("feedback", 0.5);
+        delay.set_param("mix", 1.0);
+
+        let tail = delay.tail();
+        let mut buf = vec![0.0; tail + 100];
+        buf[0] = 1.0;
+        delay.process(&mut buf, 1);
+
+        assert!(buf[tail - 3].abs() > 0.001, "the last audible repeat should be within the tail");
+        assert!(buf[tail..].iter().all(|s| s.abs() < 0.001));
+    }
+}
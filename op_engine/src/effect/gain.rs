@@ -0,0 +1,42 @@
+use crate::db_to_linear;
+use crate::effect::{Effect, EffectParam};
+
+pub const KIND: &str = "gain";
+
+pub struct Gain {
+    gain: f32,
+}
+
+impl Gain {
+    pub fn new() -> Self {
+        Self { gain: 1.0 }
+    }
+
+    pub fn boxed(_sample_rate: u32) -> Box<dyn Effect> {
+        Box::new(Self::new())
+    }
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Effect for Gain {
+    fn params(&self) -> Vec<EffectParam> {
+        vec![EffectParam::new("gain", -60.0, 24.0, 0.0)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if name == "gain" {
+            self.gain = db_to_linear(value);
+        }
+    }
+
+    fn process(&mut self, buf: &mut [f32], _channels: usize) {
+        for sample in buf.iter_mut() {
+            *sample *= self.gain;
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::effect::{Effect, EffectParam, repeats_until_silent};
+
+pub const KIND: &str = "reverb";
+
+// Delay lengths (in samples at 44.1 kHz) from Freeverb
+const COMB_TUNING: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_TUNING: [usize; 2] = [556, 441];
+
+// Offset added to every delay length for each additional channel, to decorrelate them
+const STEREO_SPREAD: usize = 23;
+
+struct Comb {
+    buffer: Vec<f32>,
+    pos: usize,
+    filter_state: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len.max(1)], pos: 0, filter_state: 0.0 }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.pos];
+        self.filter_state = output * (1.0 - damping) + self.filter_state * damping;
+        self.buffer[self.pos] = input + self.filter_state * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len.max(1)], pos: 0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.pos];
+        let output = delayed - input;
+        self.buffer[self.pos] = input + delayed * 0.5;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct ReverbChannel {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+/// A simple Schroeder-style reverb with parallel comb filters followed by series all-pass
+/// filters, based on Freeverb.
+pub struct Reverb {
+    sample_rate: u32,
+    size: f32,
+    damping: f32,
+    mix: f32,
+
+    channels: Vec<ReverbChannel>,
+}
+
+impl Reverb {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            size: 0.5,
+            damping: 0.5,
+            mix: 0.25,
+            channels: Vec::new(),
+        }
+    }
+
+    pub fn boxed(sample_rate: u32) -> Box<dyn Effect> {
+        Box::new(Self::new(sample_rate))
+    }
+
+    fn feedback(&self) -> f32 {
+        0.7 + 0.28 * self.size
+    }
+
+    fn scale(&self, len: usize) -> usize {
+        len * self.sample_rate as usize / 44100
+    }
+
+    fn build_channels(&mut self, channels: usize) {
+        self.channels = (0..channels)
+            .map(|c| ReverbChannel {
+                combs: COMB_TUNING.iter()
+                    .map(|&len| Comb::new(self.scale(len + c * STEREO_SPREAD)))
+                    .collect(),
+                allpasses: ALLPASS_TUNING.iter()
+                    .map(|&len| Allpass::new(self.scale(len + c * STEREO_SPREAD)))
+                    .collect(),
+            })
+            .collect();
+    }
+}
+
+impl Effect for Reverb {
+    fn params(&self) -> Vec<EffectParam> {
+        vec![
+            EffectParam::new("size", 0.0, 1.0, 0.5),
+            EffectParam::new("damping", 0.0, 1.0, 0.5),
+            EffectParam::new("mix", 0.0, 1.0, 0.25),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "size" => self.size = value.clamp(0.0, 1.0),
+            "damping" => self.damping = value.clamp(0.0, 1.0),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => (),
+        }
+    }
+
+    fn process(&mut self, buf: &mut [f32], channels: usize) {
+        if self.channels.len() != channels {
+            self.build_channels(channels);
+        }
+
+        let feedback = self.feedback();
+        let damping = self.damping * 0.4;
+
+        for frame in buf.chunks_exact_mut(channels) {
+            for (sample, channel) in frame.iter_mut().zip(self.channels.iter_mut()) {
+                let input = *sample * 0.015;
+
+                let mut wet: f32 = channel.combs.iter_mut()
+                    .map(|comb| comb.process(input, feedback, damping))
+                    .sum();
+
+                for allpass in channel.allpasses.iter_mut() {
+                    wet = allpass.process(wet);
+                }
+
+                *sample = *sample * (1.0 - self.mix) + wet * self.mix * 3.0;
+            }
+        }
+    }
+
+    fn tail(&self) -> usize {
+        // The longest comb filter decays slowest, and the all-pass filters smear it out further
+        let spread = self.channels.len().saturating_sub(1) * STEREO_SPREAD;
+        let comb = self.scale(COMB_TUNING.iter().max().unwrap() + spread);
+        let allpass: usize = ALLPASS_TUNING.iter().map(|&len| self.scale(len + spread)).sum();
+        comb * repeats_until_silent(self.feedback()) + allpass * repeats_until_silent(0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverb_tail() {
+        let mut reverb = Reverb::new(44100);
+        reverb.set_param("mix", 1.0);
+
+        let mut buf = vec![0.0; 2 * 44100];
+        buf[0] = 1.0;
+        buf[1] = 1.0;
+        reverb.process(&mut buf, 2);
+
+        let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+        let early = energy(&buf[..2 * 4410]);
+        let late = energy(&buf[2 * 4410 * 5..2 * 4410 * 6]);
+
+        assert!(early > 0.0);
+        assert!(late > 0.0, "impulse should still be audible after half a second");
+        assert!(late < early, "tail should decay");
+        assert!(buf.iter().all(|s| s.is_finite() && s.abs() < 1.0));
+
+        // Channels are decorrelated
+        assert!(buf.chunks(2).any(|frame| frame[0] != frame[1]));
+    }
+
+    #[test]
+    fn test_reverb_decays_within_tail() {
+        let mut reverb = Reverb::new(44100);
+        reverb.set_param("size", 1.0);
+        reverb.set_param("damping", 0.0);
+        reverb.set_param("mix", 1.0);
+
+        let mut buf = vec![0.0; 2];
+        buf[0] = 1.0;
+        buf[1] = 1.0;
+        reverb.process(&mut buf, 2);
+
+        let tail = reverb.tail();
+        let mut buf = vec![0.0; 2 * (tail + 4410)];
+        reverb.process(&mut buf, 2);
+
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak(&buf[..2 * 4410]) > 0.001);
+        assert!(peak(&buf[2 * tail..]) < 0.001, "reverb should be silent after its tail");
+    }
+}
@@ -1,6 +1,101 @@
+use midly::MidiMessage;
+
+pub mod click;
+pub mod poly;
 pub mod sine;
 
+/// A MIDI message to be handled `offset` frames into a block.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MidiEvent {
+    pub offset: usize,
+    pub msg: MidiMessage,
+}
+
 pub trait Generator : Send {
-    fn next(&mut self) -> f32;
-    fn handle(&mut self, msg: midly::MidiMessage);
+    /// Fills `out` with the next `out.len()` samples.
+    fn process(&mut self, out: &mut [f32]);
+
+    fn handle(&mut self, msg: MidiMessage);
+
+    /// Sets one of the generator's parameters. Generators without parameters ignore this.
+    fn set_param(&mut self, _param: usize, _value: f32) {}
+
+    /// Returns the current value of a parameter, including ones the generator outputs, such as
+    /// level meters.
+    fn get_param(&self, _param: usize) -> Option<f32> {
+        None
+    }
+
+    /// Fills `out` like `process`, handling each event at its offset within the block. Events must
+    /// be sorted by offset.
+    fn process_events(&mut self, out: &mut [f32], events: &[MidiEvent]) {
+        let mut pos = 0;
+
+        for event in events {
+            let offset = event.offset.clamp(pos, out.len());
+            self.process(&mut out[pos..offset]);
+            self.handle(event.msg);
+            pos = offset;
+        }
+
+        self.process(&mut out[pos..]);
+    }
+
+    /// Returns the next sample. Generating one sample at a time is much slower than calling
+    /// `process` with a whole block.
+    fn next(&mut self) -> f32 {
+        let mut out = [0.0];
+        self.process(&mut out);
+        out[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outputs the number of notes that are held.
+    #[derive(Default)]
+    struct NoteCounter {
+        held: usize,
+    }
+
+    impl Generator for NoteCounter {
+        fn process(&mut self, out: &mut [f32]) {
+            out.fill(self.held as f32);
+        }
+
+        fn handle(&mut self, msg: MidiMessage) {
+            match msg {
+                MidiMessage::NoteOn { .. } => self.held += 1,
+                MidiMessage::NoteOff { .. } => self.held -= 1,
+                _ => (),
+            }
+        }
+    }
+
+    fn event(offset: usize, on: bool) -> MidiEvent {
+        let (key, vel) = (60.into(), 100.into());
+        let msg = if on { MidiMessage::NoteOn { key, vel } } else { MidiMessage::NoteOff { key, vel } };
+        MidiEvent { offset, msg }
+    }
+
+    #[test]
+    fn test_process_events_at_offsets() {
+        let mut generator = NoteCounter::default();
+        let mut out = [0.0; 8];
+        generator.process_events(&mut out, &[event(2, true), event(2, true), event(5, false)]);
+
+        assert_eq!([0.0, 0.0, 2.0, 2.0, 2.0, 1.0, 1.0, 1.0], out);
+    }
+
+    #[test]
+    fn test_process_events_past_end() {
+        let mut generator = NoteCounter::default();
+        let mut out = [0.0; 4];
+        generator.process_events(&mut out, &[event(10, true)]);
+
+        assert_eq!([0.0; 4], out);
+        assert_eq!(1, generator.held, "late events should still be handled");
+    }
 }
@@ -0,0 +1,80 @@
+use std::f32::consts::PI;
+
+use midly::MidiMessage;
+
+use crate::generator::Generator;
+
+/// Time for a click to decay to about a third of its level.
+const DECAY_SEC: f32 = 0.01;
+
+/// Plays a short, decaying sine burst at the pitch and velocity of each note. Note offs are
+/// ignored, since every click ends by itself.
+pub struct ClickGenerator {
+    sample_rate: u32,
+    phase: f32,
+    step: f32,
+    level: f32,
+    decay: f32,
+}
+
+fn midi_note_to_hz(note: u8) -> f32 {
+    440.0 * 2.0f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+impl ClickGenerator {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            phase: 0.0,
+            step: 0.0,
+            level: 0.0,
+            decay: (-1.0 / (sample_rate as f32 * DECAY_SEC)).exp(),
+        }
+    }
+}
+
+impl Default for ClickGenerator {
+    fn default() -> Self {
+        Self::new(44100)
+    }
+}
+
+impl Generator for ClickGenerator {
+    fn process(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.level * self.phase.sin();
+            self.phase = (self.phase + self.step) % (2.0 * PI);
+            self.level *= self.decay;
+        }
+    }
+
+    fn handle(&mut self, msg: MidiMessage) {
+        if let MidiMessage::NoteOn { key, vel } = msg {
+            self.phase = 0.0;
+            self.step = 2.0 * PI * midi_note_to_hz(key.as_int()) / self.sample_rate as f32;
+            self.level = vel.as_int() as f32 / 127.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peak(buf: &[f32]) -> f32 {
+        buf.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn test_click_decays() {
+        let mut generator = ClickGenerator::new(44100);
+        generator.handle(MidiMessage::NoteOn { key: 84.into(), vel: 127.into() });
+        generator.handle(MidiMessage::NoteOff { key: 84.into(), vel: 0.into() });
+
+        let mut buf = vec![0.0; 4410];
+        generator.process(&mut buf);
+
+        assert!(peak(&buf[..100]) > 0.9, "note off should not cut the click short");
+        assert!(peak(&buf[4000..]) < 1e-3);
+    }
+}
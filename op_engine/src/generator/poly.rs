@@ -0,0 +1,333 @@
+use std::fmt::{Display, Formatter};
+
+use midly::MidiMessage;
+
+use crate::generator::Generator;
+
+/// Which voice to take over when a note starts and every voice is held.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum VoiceStealing {
+    /// The voice whose note started first.
+    #[default]
+    Oldest,
+
+    /// The voice with the lowest output level.
+    Quietest,
+
+    /// A voice already playing the same note, or the oldest voice if there is none.
+    SameNote,
+}
+
+impl VoiceStealing {
+    pub const ALL: [VoiceStealing; 3] = [VoiceStealing::Oldest, VoiceStealing::Quietest, VoiceStealing::SameNote];
+}
+
+impl Display for VoiceStealing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoiceStealing::Oldest => write!(f, "Steal oldest"),
+            VoiceStealing::Quietest => write!(f, "Steal quietest"),
+            VoiceStealing::SameNote => write!(f, "Steal same note"),
+        }
+    }
+}
+
+struct Voice<G> {
+    generator: G,
+    note: u8,
+    held: bool,
+
+    // Order in which the voice's note started, for finding the oldest voice
+    started: u64,
+
+    // Peak output level of the last block
+    level: f32,
+}
+
+/// Plays several notes at once by giving each note its own instance of a monophonic generator.
+/// The output is the sum of all voices.
+pub struct PolyGenerator<G: Generator> {
+    voices: Vec<Voice<G>>,
+    stealing: VoiceStealing,
+    notes_started: u64,
+    voice_buf: Vec<f32>,
+}
+
+impl<G: Generator> PolyGenerator<G> {
+    pub fn new(generators: Vec<G>, stealing: VoiceStealing) -> Self {
+        assert!(!generators.is_empty(), "at least one voice is required");
+
+        let voices = generators
+            .into_iter()
+            .map(|generator| Voice {
+                generator,
+                note: 0,
+                held: false,
+                started: 0,
+                level: 0.0,
+            })
+            .collect();
+
+        Self {
+            voices,
+            stealing,
+            notes_started: 0,
+            voice_buf: Vec::new(),
+        }
+    }
+
+    /// Creates a generator with `voices` voices, each created by `make_voice`.
+    pub fn from_fn(voices: usize, stealing: VoiceStealing, make_voice: impl FnMut() -> G) -> Self {
+        Self::new(std::iter::repeat_with(make_voice).take(voices).collect(), stealing)
+    }
+
+    pub fn stealing(&self) -> VoiceStealing {
+        self.stealing
+    }
+
+    pub fn set_stealing(&mut self, stealing: VoiceStealing) {
+        self.stealing = stealing;
+    }
+
+    fn oldest(&self) -> usize {
+        (0..self.voices.len()).min_by_key(|&i| self.voices[i].started).unwrap()
+    }
+
+    fn quietest(&self, candidates: impl Iterator<Item=usize>) -> Option<usize> {
+        candidates.min_by(|&a, &b| {
+            let (a, b) = (&self.voices[a], &self.voices[b]);
+            a.level.total_cmp(&b.level).then(a.started.cmp(&b.started))
+        })
+    }
+
+    /// Picks the voice that should play `note`.
+    fn allocate(&self, note: u8) -> usize {
+        let same_note = self.voices.iter().position(|v| v.note == note && v.started > 0);
+
+        if let (VoiceStealing::SameNote, Some(voice)) = (self.stealing, same_note) {
+            return voice;
+        }
+
+        // Prefer voices that are not held, ideally ones which have finished releasing
+        let free = (0..self.voices.len()).filter(|&i| !self.voices[i].held);
+        if let Some(voice) = self.quietest(free) {
+            return voice;
+        }
+
+        match self.stealing {
+            VoiceStealing::Oldest | VoiceStealing::SameNote => self.oldest(),
+            VoiceStealing::Quietest => self.quietest(0..self.voices.len()).unwrap(),
+        }
+    }
+
+    fn note_on(&mut self, note: u8, msg: MidiMessage) {
+        let index = self.allocate(note);
+        self.notes_started += 1;
+
+        let voice = &mut self.voices[index];
+        voice.note = note;
+        voice.held = true;
+        voice.started = self.notes_started;
+        voice.generator.handle(msg);
+    }
+
+    fn note_off(&mut self, note: u8, msg: MidiMessage) {
+        let voice = self.voices
+            .iter_mut()
+            .filter(|v| v.held && v.note == note)
+            .min_by_key(|v| v.started);
+
+        if let Some(voice) = voice {
+            voice.held = false;
+            voice.generator.handle(msg);
+        }
+    }
+}
+
+impl<G: Generator> Generator for PolyGenerator<G> {
+    fn process(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+
+        if self.voice_buf.len() < out.len() {
+            self.voice_buf.resize(out.len(), 0.0);
+        }
+
+        let voice_buf = &mut self.voice_buf[..out.len()];
+
+        for voice in self.voices.iter_mut() {
+            voice.generator.process(voice_buf);
+            voice.level = voice_buf.iter().fold(0.0, |peak, s| peak.max(s.abs()));
+
+            for (out, sample) in out.iter_mut().zip(voice_buf.iter()) {
+                *out += sample;
+            }
+        }
+    }
+
+    fn handle(&mut self, msg: MidiMessage) {
+        match msg {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => self.note_on(key.as_int(), msg),
+
+            // A note on with zero velocity is a note off
+            MidiMessage::NoteOn { key, vel } => self.note_off(key.as_int(), MidiMessage::NoteOff { key, vel }),
+            MidiMessage::NoteOff { key, .. } => self.note_off(key.as_int(), msg),
+
+            _ => {
+                for voice in self.voices.iter_mut() {
+                    voice.generator.handle(msg);
+                }
+            }
+        }
+    }
+
+    fn set_param(&mut self, param: usize, value: f32) {
+        for voice in self.voices.iter_mut() {
+            voice.generator.set_param(param, value);
+        }
+    }
+
+    /// Returns the parameter of the voice which started most recently.
+    fn get_param(&self, param: usize) -> Option<f32> {
+        let voice = self.voices.iter().max_by_key(|v| v.started).unwrap();
+        voice.generator.get_param(param)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outputs a constant level while a note is held, and remembers the last note it was given.
+    struct TestVoice {
+        note: Option<u8>,
+        level: f32,
+    }
+
+    impl Generator for TestVoice {
+        fn process(&mut self, out: &mut [f32]) {
+            out.fill(if self.note.is_some() { self.level } else { 0.0 });
+        }
+
+        fn set_param(&mut self, param: usize, value: f32) {
+            if param == 0 {
+                self.level = value;
+            }
+        }
+
+        fn get_param(&self, param: usize) -> Option<f32> {
+            (param == 0).then_some(self.level)
+        }
+
+        fn handle(&mut self, msg: MidiMessage) {
+            match msg {
+                MidiMessage::NoteOn { key, .. } => self.note = Some(key.as_int()),
+                MidiMessage::NoteOff { key, .. } if self.note == Some(key.as_int()) => self.note = None,
+                _ => (),
+            }
+        }
+    }
+
+    fn poly(levels: &[f32], stealing: VoiceStealing) -> PolyGenerator<TestVoice> {
+        let voices = levels.iter().map(|&level| TestVoice { note: None, level }).collect();
+        PolyGenerator::new(voices, stealing)
+    }
+
+    fn on(poly: &mut PolyGenerator<TestVoice>, note: u8) {
+        poly.handle(MidiMessage::NoteOn { key: note.into(), vel: 100.into() });
+    }
+
+    fn off(poly: &mut PolyGenerator<TestVoice>, note: u8) {
+        poly.handle(MidiMessage::NoteOff { key: note.into(), vel: 0.into() });
+    }
+
+    fn notes(poly: &PolyGenerator<TestVoice>) -> Vec<Option<u8>> {
+        poly.voices.iter().map(|v| v.generator.note).collect()
+    }
+
+    fn render(poly: &mut PolyGenerator<TestVoice>) -> f32 {
+        let mut out = [0.0; 4];
+        poly.process(&mut out);
+        out[0]
+    }
+
+    #[test]
+    fn test_chord_is_summed() {
+        let mut poly = poly(&[0.25; 4], VoiceStealing::Oldest);
+        on(&mut poly, 60);
+        on(&mut poly, 64);
+        on(&mut poly, 67);
+
+        assert_eq!(0.75, render(&mut poly));
+
+        off(&mut poly, 64);
+        assert_eq!(0.5, render(&mut poly));
+        assert_eq!(vec![Some(60), None, Some(67), None], notes(&poly));
+    }
+
+    #[test]
+    fn test_zero_velocity_note_on_releases() {
+        let mut poly = poly(&[1.0; 2], VoiceStealing::Oldest);
+        on(&mut poly, 60);
+        poly.handle(MidiMessage::NoteOn { key: 60.into(), vel: 0.into() });
+
+        assert_eq!(vec![None, None], notes(&poly));
+    }
+
+    #[test]
+    fn test_steal_oldest() {
+        let mut poly = poly(&[1.0; 2], VoiceStealing::Oldest);
+        on(&mut poly, 60);
+        on(&mut poly, 62);
+        on(&mut poly, 64);
+
+        assert_eq!(vec![Some(64), Some(62)], notes(&poly));
+    }
+
+    #[test]
+    fn test_steal_quietest() {
+        let mut poly = poly(&[1.0, 0.5], VoiceStealing::Quietest);
+        on(&mut poly, 60);
+        on(&mut poly, 62);
+        render(&mut poly);
+        on(&mut poly, 64);
+
+        assert_eq!(vec![Some(60), Some(64)], notes(&poly));
+    }
+
+    #[test]
+    fn test_steal_same_note() {
+        let mut poly = poly(&[1.0; 3], VoiceStealing::SameNote);
+        on(&mut poly, 60);
+        on(&mut poly, 62);
+        on(&mut poly, 60);
+
+        assert_eq!(vec![Some(60), Some(62), None], notes(&poly), "the same note should retrigger its voice");
+
+        on(&mut poly, 64);
+        on(&mut poly, 65);
+        assert_eq!(vec![Some(60), Some(65), Some(64)], notes(&poly), "other notes should steal the oldest voice");
+    }
+
+    #[test]
+    fn test_released_voice_is_reused_before_stealing() {
+        let mut poly = poly(&[1.0; 2], VoiceStealing::Oldest);
+        on(&mut poly, 60);
+        on(&mut poly, 62);
+        off(&mut poly, 62);
+        on(&mut poly, 64);
+
+        assert_eq!(vec![Some(60), Some(64)], notes(&poly));
+    }
+
+    #[test]
+    fn test_params_reach_every_voice() {
+        let mut poly = poly(&[1.0; 3], VoiceStealing::Oldest);
+        poly.set_param(0, 0.5);
+        on(&mut poly, 60);
+        on(&mut poly, 64);
+
+        assert_eq!(1.0, render(&mut poly));
+        assert_eq!(Some(0.5), poly.get_param(0));
+        assert_eq!(None, poly.get_param(1));
+    }
+}
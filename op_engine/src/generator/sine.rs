@@ -30,23 +30,28 @@ impl Default for SineGenerator {
 }
 
 impl Generator for SineGenerator {
-    fn next(&mut self) -> f32 {
+    fn process(&mut self, out: &mut [f32]) {
         if !self.on {
-            return 0.0;
+            out.fill(0.0);
+            return;
         }
 
         let freq = midi_note_to_hz(self.note) as f32 / self.sample_rate as f32;
-        self.phase += 2.0 * PI * freq;
+        let step = 2.0 * PI * freq;
 
-        if self.phase > 2.0 * PI {
-            self.phase -= 2.0 * PI;
-        }
+        for sample in out.iter_mut() {
+            self.phase += step;
 
-        if self.phase < 0.0 {
-            self.phase += 2.0 * PI;
-        }
+            if self.phase > 2.0 * PI {
+                self.phase -= 2.0 * PI;
+            }
+
+            if self.phase < 0.0 {
+                self.phase += 2.0 * PI;
+            }
 
-        self.phase.sin()
+            *sample = self.phase.sin();
+        }
     }
 
     fn handle(&mut self, msg: midly::MidiMessage) {
@@ -65,3 +70,39 @@ impl Generator for SineGenerator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing() -> SineGenerator {
+        let mut generator = SineGenerator::new(44100);
+        generator.handle(midly::MidiMessage::NoteOn { key: 60.into(), vel: 100.into() });
+        generator
+    }
+
+    #[test]
+    fn test_process_matches_closed_form() {
+        let step = 2.0 * std::f64::consts::PI * midi_note_to_hz(60) / 44100.0;
+
+        let mut generator = playing();
+        let mut actual = vec![0.0; 1000];
+        for block in actual.chunks_mut(128) {
+            generator.process(block);
+        }
+
+        // The phase is advanced before each sample, and accumulates rounding errors in f32
+        for (n, sample) in actual.into_iter().enumerate() {
+            let expected = ((n + 1) as f64 * step).sin();
+            assert!((sample as f64 - expected).abs() < 1e-3, "sample {}: {} != {}", n, sample, expected);
+        }
+    }
+
+    #[test]
+    fn test_process_is_silent_when_off() {
+        let mut generator = SineGenerator::new(44100);
+        let mut buf = vec![1.0; 64];
+        generator.process(&mut buf);
+        assert!(buf.iter().all(|&s| s == 0.0));
+    }
+}
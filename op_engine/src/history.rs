@@ -0,0 +1,683 @@
+use std::mem;
+
+use crate::{Clip, Project, Time, Timeline, Track};
+use crate::clip_database::ClipId;
+use crate::effect::EffectSettings;
+use crate::master::LimiterMode;
+use crate::snap::Snap;
+use crate::time::{TimeGrid, TimeSignature};
+use crate::track::{ClipInstance, ClipInstanceId};
+
+/// A setting of a single track.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TrackParam {
+    Gain(f32),
+    Pan(f32),
+    Muted(bool),
+    Soloed(bool),
+}
+
+impl TrackParam {
+    /// Returns the current value of this setting on `track`.
+    fn read(&self, track: &Track) -> Self {
+        match self {
+            TrackParam::Gain(_) => TrackParam::Gain(track.gain_db),
+            TrackParam::Pan(_) => TrackParam::Pan(track.pan),
+            TrackParam::Muted(_) => TrackParam::Muted(track.muted),
+            TrackParam::Soloed(_) => TrackParam::Soloed(track.soloed),
+        }
+    }
+
+    fn write(&self, track: &mut Track) {
+        match *self {
+            TrackParam::Gain(gain_db) => track.gain_db = gain_db,
+            TrackParam::Pan(pan) => track.pan = pan,
+            TrackParam::Muted(muted) => track.muted = muted,
+            TrackParam::Soloed(soloed) => track.soloed = soloed,
+        }
+    }
+}
+
+/// A setting of the whole project.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProjectParam {
+    Tempo(f64),
+    TimeSignature(TimeSignature),
+    MasterGain(f32),
+    Limiter(LimiterMode),
+    Metronome(bool),
+    CountIn(u32),
+}
+
+impl ProjectParam {
+    /// Returns the current value of this setting in `project`.
+    fn read(&self, project: &Project) -> Self {
+        match self {
+            ProjectParam::Tempo(_) => ProjectParam::Tempo(project.tempo),
+            ProjectParam::TimeSignature(_) => ProjectParam::TimeSignature(project.time_signature),
+            ProjectParam::MasterGain(_) => ProjectParam::MasterGain(project.master.gain_db),
+            ProjectParam::Limiter(_) => ProjectParam::Limiter(project.master.limiter),
+            ProjectParam::Metronome(_) => ProjectParam::Metronome(project.metronome.enabled),
+            ProjectParam::CountIn(_) => ProjectParam::CountIn(project.metronome.count_in_bars),
+        }
+    }
+
+    fn write(&self, project: &mut Project) {
+        match *self {
+            ProjectParam::Tempo(tempo) => project.tempo = tempo,
+            ProjectParam::TimeSignature(time_signature) => project.time_signature = time_signature,
+            ProjectParam::MasterGain(gain_db) => project.master.gain_db = gain_db,
+            ProjectParam::Limiter(limiter) => project.master.limiter = limiter,
+            ProjectParam::Metronome(enabled) => project.metronome.enabled = enabled,
+            ProjectParam::CountIn(bars) => project.metronome.count_in_bars = bars,
+        }
+    }
+}
+
+/// A reversible change to a project. Edits store both the old and the new state, so they can be
+/// applied and reverted any number of times.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    MoveClip { id: ClipInstanceId, from: Time, to: Time },
+
+    /// Adds a clip instance on top of the other clips on a track, e.g. after recording it.
+    AddClip { track: usize, instance: ClipInstance },
+
+    RemoveClip { track: usize, index: usize, instance: ClipInstance },
+
+    /// Replaces a clip instance with instances of the two parts before and after a point. The
+    /// audio of the parts is kept here, and is only in the clip database while the edit is
+    /// applied.
+    SplitClip { track: usize, index: usize, original: ClipInstance, parts: [ClipInstance; 2], clips: [Clip; 2] },
+
+    /// Removes clips from the clip database. The clips are kept here so that they can be put
+    /// back.
+    PurgeClips { clips: Vec<(ClipId, Clip)> },
+
+    SetTrackParam { track: usize, from: TrackParam, to: TrackParam },
+    SetProjectParam { from: ProjectParam, to: ProjectParam },
+
+    /// Adds an insert effect at the end of a track's effect chain.
+    AddEffect { track: usize, effect: EffectSettings },
+
+    RemoveEffect { track: usize, index: usize, effect: EffectSettings },
+
+    /// Edits that are applied and undone together, e.g. deleting every selected clip.
+    Group(Vec<Edit>),
+}
+
+impl Edit {
+    /// Returns an edit moving a clip by `delta` samples and snapping its start to the grid.
+    pub fn move_clip(timeline: &Timeline, id: ClipInstanceId, delta: i64, snap: Snap, grid: &TimeGrid) -> Option<Edit> {
+        let from = timeline.find_clip(id)?.1.time;
+        let to = snap.snap_move(grid, from, delta);
+        Some(Edit::MoveClip { id, from, to })
+    }
+
+    /// Returns an edit moving the start of each of the given clips to the nearest grid line.
+    pub fn quantize(timeline: &Timeline, ids: &[ClipInstanceId], snap: Snap, grid: &TimeGrid) -> Edit {
+        Edit::Group(ids.iter()
+            .filter_map(|&id| Edit::move_clip(timeline, id, 0, snap, grid))
+            .collect())
+    }
+
+    pub fn remove_clip(timeline: &Timeline, id: ClipInstanceId) -> Option<Edit> {
+        let (track, index, instance) = find_clip(timeline, id)?;
+        Some(Edit::RemoveClip { track, index, instance: instance.clone() })
+    }
+
+    pub fn remove_clips(timeline: &Timeline, ids: &[ClipInstanceId]) -> Edit {
+        Edit::Group(in_reverse_order(timeline, ids)
+            .filter_map(|id| Edit::remove_clip(timeline, id))
+            .collect())
+    }
+
+    /// Returns an edit splitting a clip in two at `time`, or None if the clip isn't playing at
+    /// that time. IDs for the parts are reserved right away, but they are only added to the clip
+    /// database when the edit is applied.
+    pub fn split_clip(project: &Project, id: ClipInstanceId, time: Time) -> Option<Edit> {
+        let (track, index, original) = find_clip(&project.timeline, id)?;
+        let original = original.clone();
+        let clip = project.clip_database.get(original.clip_id)?;
+
+        if time <= original.time || time >= original.time + clip.len() {
+            return None;
+        }
+
+        let (before, after) = clip.split_at(time - original.time);
+        let parts = [
+            ClipInstance::new(project.timeline.new_instance_id(), original.time, project.clip_database.reserve_id()),
+            ClipInstance::new(project.timeline.new_instance_id(), time, project.clip_database.reserve_id()),
+        ];
+
+        Some(Edit::SplitClip { track, index, original, parts, clips: [before, after] })
+    }
+
+    /// Returns an edit splitting each of the given clips that is playing at `time`.
+    pub fn split_clips(project: &Project, ids: &[ClipInstanceId], time: Time) -> Edit {
+        Edit::Group(in_reverse_order(&project.timeline, ids)
+            .filter_map(|id| Edit::split_clip(project, id, time))
+            .collect())
+    }
+
+    /// Returns an edit removing every clip that isn't on the timeline from the clip database, such
+    /// as discarded takes and the leftovers of undone recordings.
+    pub fn purge_unused_clips(project: &Project) -> Edit {
+        let clips = project.clip_database.unused(&project.timeline)
+            .into_iter()
+            .filter_map(|id| Some((id, project.clip_database.get(id)?.clone())))
+            .collect();
+
+        Edit::PurgeClips { clips }
+    }
+
+    pub fn set_track_param(timeline: &Timeline, track: usize, to: TrackParam) -> Edit {
+        let from = to.read(&timeline.tracks[track]);
+        Edit::SetTrackParam { track, from, to }
+    }
+
+    pub fn set_project_param(project: &Project, to: ProjectParam) -> Edit {
+        let from = to.read(project);
+        Edit::SetProjectParam { from, to }
+    }
+
+    pub fn remove_effect(timeline: &Timeline, track: usize, index: usize) -> Option<Edit> {
+        let effect = timeline.tracks.get(track)?.effects.get(index)?.clone();
+        Some(Edit::RemoveEffect { track, index, effect })
+    }
+
+    pub fn apply(&self, project: &mut Project) {
+        match self {
+            Edit::MoveClip { id, to, .. } => {
+                project.timeline.move_clip(*id, *to);
+            }
+
+            Edit::AddClip { track, instance } => {
+                project.timeline.tracks[*track].add_clip(instance.clone());
+            }
+
+            Edit::RemoveClip { track, instance, .. } => {
+                project.timeline.tracks[*track].remove_clip(instance.id);
+            }
+
+            Edit::SplitClip { track, index, original, parts, clips } => {
+                for (part, clip) in parts.iter().zip(clips) {
+                    project.clip_database.restore(part.clip_id, clip.clone());
+                }
+
+                let track = &mut project.timeline.tracks[*track];
+                track.remove_clip(original.id);
+                track.insert_clip(*index, parts[1].clone());
+                track.insert_clip(*index, parts[0].clone());
+            }
+
+            Edit::PurgeClips { clips } => {
+                for (id, _) in clips {
+                    project.clip_database.remove(*id);
+                }
+            }
+
+            Edit::SetTrackParam { track, to, .. } => to.write(&mut project.timeline.tracks[*track]),
+            Edit::SetProjectParam { to, .. } => to.write(project),
+
+            Edit::AddEffect { track, effect } => {
+                project.timeline.tracks[*track].effects.push(effect.clone());
+            }
+
+            Edit::RemoveEffect { track, index, .. } => {
+                project.timeline.tracks[*track].effects.remove(*index);
+            }
+
+            Edit::Group(edits) => {
+                for edit in edits {
+                    edit.apply(project);
+                }
+            }
+        }
+    }
+
+    pub fn revert(&self, project: &mut Project) {
+        match self {
+            Edit::MoveClip { id, from, .. } => {
+                project.timeline.move_clip(*id, *from);
+            }
+
+            Edit::AddClip { track, instance } => {
+                project.timeline.tracks[*track].remove_clip(instance.id);
+            }
+
+            Edit::RemoveClip { track, index, instance } => {
+                project.timeline.tracks[*track].insert_clip(*index, instance.clone());
+            }
+
+            Edit::SplitClip { track, index, original, parts, .. } => {
+                for part in parts {
+                    project.clip_database.remove(part.clip_id);
+                }
+
+                let track = &mut project.timeline.tracks[*track];
+                for part in parts {
+                    track.remove_clip(part.id);
+                }
+
+                track.insert_clip(*index, original.clone());
+            }
+
+            Edit::PurgeClips { clips } => {
+                for (id, clip) in clips {
+                    project.clip_database.restore(*id, clip.clone());
+                }
+            }
+
+            Edit::SetTrackParam { track, from, .. } => from.write(&mut project.timeline.tracks[*track]),
+            Edit::SetProjectParam { from, .. } => from.write(project),
+
+            Edit::AddEffect { track, .. } => {
+                project.timeline.tracks[*track].effects.pop();
+            }
+
+            Edit::RemoveEffect { track, index, effect } => {
+                project.timeline.tracks[*track].effects.insert(*index, effect.clone());
+            }
+
+            Edit::Group(edits) => {
+                for edit in edits.iter().rev() {
+                    edit.revert(project);
+                }
+            }
+        }
+    }
+
+    /// Whether applying this edit leaves the project as it was.
+    fn is_noop(&self) -> bool {
+        match self {
+            Edit::MoveClip { from, to, .. } => from == to,
+            Edit::SetTrackParam { from, to, .. } => from == to,
+            Edit::SetProjectParam { from, to } => from == to,
+            Edit::PurgeClips { clips } => clips.is_empty(),
+            Edit::Group(edits) => edits.iter().all(Edit::is_noop),
+            _ => false,
+        }
+    }
+
+    /// Merges `next` into this edit if both change the same thing, so that they are undone
+    /// together. Returns whether they were merged.
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (
+                Edit::MoveClip { id, to, .. },
+                Edit::MoveClip { id: next_id, to: next_to, .. },
+            ) if id == next_id => {
+                *to = *next_to;
+                true
+            }
+
+            (
+                Edit::SetTrackParam { track, to, .. },
+                Edit::SetTrackParam { track: next_track, to: next_to, .. },
+            ) if track == next_track && mem::discriminant(to) == mem::discriminant(next_to) => {
+                *to = *next_to;
+                true
+            }
+
+            (
+                Edit::SetProjectParam { to, .. },
+                Edit::SetProjectParam { to: next_to, .. },
+            ) if mem::discriminant(to) == mem::discriminant(next_to) => {
+                *to = *next_to;
+                true
+            }
+
+            _ => false,
+        }
+    }
+}
+
+/// Returns the number of the track a clip is on, its index in the track's clip order, and the
+/// clip.
+fn find_clip(timeline: &Timeline, id: ClipInstanceId) -> Option<(usize, usize, &ClipInstance)> {
+    let (track, instance) = timeline.find_clip(id)?;
+    let index = timeline.tracks[track].clip_index(id)?;
+    Some((track, index, instance))
+}
+
+/// Sorts clips from last to first in their tracks' clip order. Edits that remove or insert
+/// clips are grouped in this order, so that each one's index is unaffected by the edits before it.
+fn in_reverse_order(timeline: &Timeline, ids: &[ClipInstanceId]) -> impl Iterator<Item=ClipInstanceId> {
+    let mut clips: Vec<(usize, usize, ClipInstanceId)> = ids.iter()
+        .filter_map(|&id| find_clip(timeline, id).map(|(track, index, _)| (track, index, id)))
+        .collect();
+
+    clips.sort();
+    clips.into_iter().rev().map(|(_, _, id)| id)
+}
+
+/// Undo and redo stacks for the edits made to a project.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+
+    // Whether the last edit belongs to a gesture that hasn't ended, so that the next edit of the
+    // same thing is merged into it
+    in_gesture: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `edit` to `project` and records it as a single step.
+    pub fn apply(&mut self, project: &mut Project, edit: Edit) {
+        self.record(project, edit, false);
+    }
+
+    /// Applies `edit` to `project` as part of a continuous gesture, like dragging a slider.
+    /// Consecutive edits of the same thing are merged into one step until the gesture ends.
+    pub fn apply_gesture(&mut self, project: &mut Project, edit: Edit) {
+        self.record(project, edit, true);
+    }
+
+    /// Ends the current gesture, so that the next edit is recorded as a new step.
+    pub fn end_gesture(&mut self) {
+        self.in_gesture = false;
+    }
+
+    fn record(&mut self, project: &mut Project, edit: Edit, gesture: bool) {
+        edit.apply(project);
+
+        let merged = gesture && self.in_gesture && self.undo.last_mut().is_some_and(|last| last.merge(&edit));
+        if merged {
+            // A gesture that ends where it started doesn't need to be undone
+            if matches!(self.undo.last(), Some(last) if last.is_noop()) {
+                self.undo.pop();
+                self.in_gesture = false;
+                return;
+            }
+        } else if edit.is_noop() {
+            return;
+        } else {
+            self.undo.push(edit);
+        }
+
+        self.redo.clear();
+        self.in_gesture = gesture;
+    }
+
+    /// Reverts the last edit. Returns false if there was nothing to undo.
+    pub fn undo(&mut self, project: &mut Project) -> bool {
+        self.in_gesture = false;
+
+        match self.undo.pop() {
+            Some(edit) => {
+                edit.revert(project);
+                self.redo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the last undone edit again. Returns false if there was nothing to redo.
+    pub fn redo(&mut self, project: &mut Project) -> bool {
+        self.in_gesture = false;
+
+        match self.redo.pop() {
+            Some(edit) => {
+                edit.apply(project);
+                self.undo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Clip;
+
+    use super::*;
+
+    fn clip_times(project: &Project, track: usize) -> Vec<(ClipInstanceId, Time)> {
+        project.timeline.tracks[track].iter_clips().map(|c| (c.id, c.time)).collect()
+    }
+
+    fn test_project() -> (Project, ClipInstanceId, ClipInstanceId) {
+        let mut project = Project::new();
+        let clip = project.clip_database.add(Clip::new(vec![0.0; 100]));
+        let clip_1 = project.timeline.instantiate_clip(0, clip, 1000);
+        let clip_2 = project.timeline.instantiate_clip(0, clip, 30000);
+        (project, clip_1, clip_2)
+    }
+
+    #[test]
+    fn test_undo_redo_move() {
+        let (mut project, clip_1, _) = test_project();
+        let mut history = History::new();
+        let grid = project.time_grid();
+
+        let edit = Edit::move_clip(&project.timeline, clip_1, 20000, Snap::Beat, &grid).unwrap();
+        history.apply(&mut project, edit);
+        assert_eq!(22050, clip_times(&project, 0)[0].1);
+
+        assert!(history.undo(&mut project));
+        assert_eq!(1000, clip_times(&project, 0)[0].1);
+        assert!(!history.undo(&mut project));
+
+        assert!(history.redo(&mut project));
+        assert_eq!(22050, clip_times(&project, 0)[0].1);
+        assert!(!history.redo(&mut project));
+    }
+
+    #[test]
+    fn test_quantize() {
+        let (mut project, clip_1, clip_2) = test_project();
+        let mut history = History::new();
+        let grid = project.time_grid();
+
+        let edit = Edit::quantize(&project.timeline, &[clip_2], Snap::Bar, &grid);
+        history.apply(&mut project, edit);
+        assert_eq!(vec![(clip_1, 1000), (clip_2, 0)], clip_times(&project, 0), "unselected clips should not move");
+
+        history.undo(&mut project);
+        assert_eq!(vec![(clip_1, 1000), (clip_2, 30000)], clip_times(&project, 0));
+    }
+
+    #[test]
+    fn test_remove_restores_order() {
+        let mut project = Project::new();
+        let clip = project.clip_database.add(Clip::new(vec![0.0; 10]));
+        let clips: Vec<ClipInstanceId> = (0..4)
+            .map(|i| project.timeline.instantiate_clip(0, clip, i * 5))
+            .collect();
+
+        let original = clip_times(&project, 0);
+        let mut history = History::new();
+
+        let edit = Edit::remove_clips(&project.timeline, &[clips[1], clips[2]]);
+        history.apply(&mut project, edit);
+        assert_eq!(vec![original[0], original[3]], clip_times(&project, 0));
+
+        history.undo(&mut project);
+        assert_eq!(original, clip_times(&project, 0), "overlapping clips should keep their order");
+    }
+
+    #[test]
+    fn test_split() {
+        let mut project = Project::new();
+        let clip_id = project.clip_database.add(Clip::new((0..10).map(|i| i as f32).collect()));
+        let clip = project.timeline.instantiate_clip(0, clip_id, 100);
+        let mut history = History::new();
+
+        assert!(Edit::split_clip(&project, clip, 100).is_none(), "splitting at an edge should do nothing");
+        assert!(Edit::split_clip(&project, clip, 110).is_none());
+
+        // Parts are only in the database while the split is applied
+        let edit = Edit::split_clips(&project, &[clip], 104);
+        assert_eq!(1, project.clip_database.len());
+        history.apply(&mut project, edit);
+        assert_eq!(
+            vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0],
+            project.timeline.tracks[0].render_all(&project.clip_database, 1)[100..],
+        );
+
+        let parts = clip_times(&project, 0);
+        assert_eq!(vec![100, 104], parts.iter().map(|(_, t)| *t).collect::<Vec<_>>());
+        let first_part = project.timeline.find_clip(parts[0].0).unwrap().1;
+        assert_eq!(4, first_part.len(&project.clip_database).unwrap());
+
+        history.undo(&mut project);
+        assert_eq!(vec![(clip, 100)], clip_times(&project, 0));
+        assert_eq!(1, project.clip_database.len());
+
+        history.redo(&mut project);
+        assert_eq!(parts, clip_times(&project, 0));
+        assert_eq!(3, project.clip_database.len());
+    }
+
+    #[test]
+    fn test_purge_unused_clips() {
+        let (mut project, clip_1, _) = test_project();
+        let mut history = History::new();
+        let used = project.timeline.find_clip(clip_1).unwrap().1.clip_id;
+        let unused = project.clip_database.add(Clip::new(vec![1.0]));
+
+        let edit = Edit::purge_unused_clips(&project);
+        history.apply(&mut project, edit);
+        assert!(project.clip_database.get(unused).is_none());
+        assert!(project.clip_database.get(used).is_some());
+
+        history.undo(&mut project);
+        assert_eq!(vec![1.0], project.clip_database.get(unused).unwrap().data);
+
+        // Purging again with nothing left to purge is not recorded, so undo goes back to before the
+        // first purge
+        history.redo(&mut project);
+        let edit = Edit::purge_unused_clips(&project);
+        history.apply(&mut project, edit);
+        history.undo(&mut project);
+        assert!(project.clip_database.get(unused).is_some());
+    }
+
+    #[test]
+    fn test_gesture_coalescing() {
+        let (mut project, _, _) = test_project();
+        let mut history = History::new();
+
+        for gain_db in [-1.0, -2.0, -3.0] {
+            let edit = Edit::set_track_param(&project.timeline, 1, TrackParam::Gain(gain_db));
+            history.apply_gesture(&mut project, edit);
+        }
+
+        // A different setting starts a new step even during a gesture
+        let edit = Edit::set_track_param(&project.timeline, 1, TrackParam::Pan(0.5));
+        history.apply_gesture(&mut project, edit);
+        history.end_gesture();
+
+        let edit = Edit::set_track_param(&project.timeline, 1, TrackParam::Pan(-0.5));
+        history.apply_gesture(&mut project, edit);
+
+        history.undo(&mut project);
+        assert_eq!(0.5, project.timeline.tracks[1].pan);
+
+        history.undo(&mut project);
+        assert_eq!(0.0, project.timeline.tracks[1].pan);
+        assert_eq!(-3.0, project.timeline.tracks[1].gain_db);
+
+        history.undo(&mut project);
+        assert_eq!(0.0, project.timeline.tracks[1].gain_db);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn test_noop_edits_are_not_recorded() {
+        let (mut project, clip_1, _) = test_project();
+        let mut history = History::new();
+        let grid = project.time_grid();
+
+        // Clicking a clip without dragging it
+        let edit = Edit::move_clip(&project.timeline, clip_1, 0, Snap::Off, &grid).unwrap();
+        history.apply(&mut project, edit);
+        assert!(!history.can_undo());
+
+        // Dragging a slider back to where it started
+        for tempo in [130.0, 120.0] {
+            let edit = Edit::set_project_param(&project, ProjectParam::Tempo(tempo));
+            history.apply_gesture(&mut project, edit);
+        }
+
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn test_undo_metronome_settings() {
+        let (mut project, _, _) = test_project();
+        let mut history = History::new();
+
+        let edit = Edit::set_project_param(&project, ProjectParam::Metronome(true));
+        history.apply(&mut project, edit);
+        let edit = Edit::set_project_param(&project, ProjectParam::CountIn(2));
+        history.apply(&mut project, edit);
+        assert!(project.metronome.enabled);
+        assert_eq!(2, project.metronome.count_in_bars);
+
+        history.undo(&mut project);
+        assert!(project.metronome.enabled);
+        assert_eq!(1, project.metronome.count_in_bars);
+
+        history.undo(&mut project);
+        assert!(!project.metronome.enabled);
+    }
+
+    #[test]
+    fn test_add_and_remove_effects() {
+        let (mut project, _, _) = test_project();
+        let mut history = History::new();
+        let kinds = |project: &Project| -> Vec<String> {
+            project.timeline.tracks[1].effects.iter().map(|e| e.kind.clone()).collect()
+        };
+
+        history.apply(&mut project, Edit::AddEffect { track: 1, effect: EffectSettings::new("gain") });
+        history.apply(&mut project, Edit::AddEffect { track: 1, effect: EffectSettings::new("delay").with_param("time", 10.0) });
+        assert_eq!(vec!["gain", "delay"], kinds(&project));
+
+        let edit = Edit::remove_effect(&project.timeline, 1, 0).unwrap();
+        history.apply(&mut project, edit);
+        assert_eq!(vec!["delay"], kinds(&project));
+        assert!(Edit::remove_effect(&project.timeline, 1, 1).is_none());
+
+        history.undo(&mut project);
+        assert_eq!(vec!["gain", "delay"], kinds(&project));
+
+        history.undo(&mut project);
+        assert_eq!(vec!["gain"], kinds(&project));
+
+        history.redo(&mut project);
+        assert_eq!(Some(10.0), project.timeline.tracks[1].effects[1].params.get("time").copied());
+    }
+
+    #[test]
+    fn test_new_edit_clears_redo() {
+        let (mut project, _, _) = test_project();
+        let mut history = History::new();
+
+        let edit = Edit::set_track_param(&project.timeline, 0, TrackParam::Muted(true));
+        history.apply(&mut project, edit);
+        history.undo(&mut project);
+        assert!(history.can_redo());
+
+        let edit = Edit::set_track_param(&project.timeline, 0, TrackParam::Soloed(true));
+        history.apply(&mut project, edit);
+        assert!(!history.can_redo());
+    }
+}
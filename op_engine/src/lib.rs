@@ -1,22 +1,60 @@
-pub use clip::Clip;
+pub use clip::{ChannelPolicy, Clip, ClipError};
 pub use player::Player;
 pub use project::Project;
 pub use session::Session;
-pub use timeline::Timeline;
+pub use time::Time;
+pub use timeline::{Timeline, TimelineEffects};
 pub use track::Track;
 
 pub mod track;
 mod clip;
 mod timeline;
 mod player;
+mod recorder;
 mod session;
 mod project;
+mod migration;
+mod resample;
 pub mod generator;
 pub mod clip_database;
+pub mod effect;
+pub mod history;
+pub mod master;
+pub mod metronome;
+pub mod snap;
+pub mod time;
 
-// TODO: Make this type-safe
-pub type Time = usize;  // in samples
+/// Converts a gain in decibels to a linear amplitude factor.
+pub fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// Converts a single frame to a different number of channels. Mono frames are copied to every
+/// channel, frames are averaged when converting to mono, and otherwise channels are copied by
+/// index with any extra destination channels left silent.
+fn convert_frame(src: &[f32], dst: &mut [f32]) {
+    if src.len() == dst.len() {
+        dst.copy_from_slice(src);
+    } else if src.len() == 1 {
+        dst.fill(src[0]);
+    } else if dst.len() == 1 {
+        dst[0] = src.iter().sum::<f32>() / src.len() as f32;
+    } else {
+        let n = src.len().min(dst.len());
+        dst[..n].copy_from_slice(&src[..n]);
+        dst[n..].fill(0.0);
+    }
+}
+
+/// Converts interleaved audio with `src_channels` channels to interleaved audio with
+/// `dst_channels` channels. Stops at the end of the shorter buffer.
+fn convert_channels(src: &[f32], src_channels: usize, dst: &mut [f32], dst_channels: usize) {
+    for (src_frame, dst_frame) in src.chunks_exact(src_channels).zip(dst.chunks_exact_mut(dst_channels)) {
+        convert_frame(src_frame, dst_frame);
+    }
+}
 
+/// Sums interleaved sources into `buf`. All sources must have the same channel layout as `buf`.
 fn mix(sources: &[&[f32]], buf: &mut [f32]) {
     for i in 0..buf.len() {
         buf[i] = 0.0;
@@ -27,8 +65,6 @@ fn mix(sources: &[&[f32]], buf: &mut [f32]) {
 
             buf[i] += source[i];
         }
-
-        buf[i] = buf[i].max(-1.0).min(1.0);
     }
 }
 
@@ -46,4 +82,21 @@ mod tests {
         mix(&[&c1, &c2, &c3, &c4], &mut result);
         assert_eq!(result, [4.0, 3.0, 2.0, 1.0, 0.0]);
     }
+
+    #[test]
+    fn test_convert_channels() {
+        let mono = [0.5f32, -0.5];
+        let mut stereo = [0f32; 4];
+        convert_channels(&mono, 1, &mut stereo, 2);
+        assert_eq!(stereo, [0.5, 0.5, -0.5, -0.5]);
+
+        let stereo = [1.0f32, 0.0, 0.5, 0.5];
+        let mut mono = [0f32; 2];
+        convert_channels(&stereo, 2, &mut mono, 1);
+        assert_eq!(mono, [0.5, 0.5]);
+
+        let mut quad = [1f32; 8];
+        convert_channels(&stereo, 2, &mut quad, 4);
+        assert_eq!(quad, [1.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.0, 0.0]);
+    }
 }
@@ -0,0 +1,340 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db_to_linear;
+use crate::effect::{EffectChain, EffectRegistry, EffectSettings};
+
+/// How long the look-ahead limiter sees peaks before they reach the output.
+const LOOKAHEAD_SEC: f32 = 0.002;
+
+/// Time for the limiter to recover from gain reduction.
+const RELEASE_SEC: f32 = 0.1;
+
+/// Highest sample level the limiter lets through.
+const CEILING: f32 = 0.98;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LimiterMode {
+    /// Samples are passed through as-is, and anything above full scale clips at the output.
+    Off,
+
+    /// Samples are smoothly saturated towards full scale.
+    SoftClip,
+
+    /// Peaks are detected ahead of time and turned down before they reach the output.
+    #[default]
+    LookAhead,
+}
+
+impl LimiterMode {
+    pub const ALL: [LimiterMode; 3] = [LimiterMode::Off, LimiterMode::SoftClip, LimiterMode::LookAhead];
+}
+
+impl Display for LimiterMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimiterMode::Off => write!(f, "Off"),
+            LimiterMode::SoftClip => write!(f, "Soft clip"),
+            LimiterMode::LookAhead => write!(f, "Limiter"),
+        }
+    }
+}
+
+/// Persistent settings for the master bus.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MasterSettings {
+    /// Effects applied to the whole mix, in order, before the master gain.
+    pub effects: Vec<EffectSettings>,
+
+    /// Gain applied to the whole mix in dB, before limiting.
+    pub gain_db: f32,
+    pub limiter: LimiterMode,
+}
+
+impl Default for MasterSettings {
+    fn default() -> Self {
+        Self {
+            effects: Vec::new(),
+            gain_db: 0.0,
+            limiter: LimiterMode::default(),
+        }
+    }
+}
+
+/// Levels measured by the master bus, for display.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct MasterMeter {
+    /// Highest absolute sample level entering the limiter since the meter was last taken.
+    pub peak: f32,
+
+    /// Whether the mix has gone above full scale since the clip indicator was last reset. This is
+    /// measured before the limiter, so it shows when the limiter or output had to intervene.
+    pub clipped: bool,
+
+    /// Current gain reduction applied by the limiter, as a linear factor.
+    pub gain_reduction: f32,
+}
+
+/// The master bus is the final stage of the mix. It applies the master effects, the master gain
+/// and then a limiter, and measures levels along the way.
+///
+/// The bus always delays its output by `latency()` frames so that switching the limiter mode does
+/// not cause a jump in the output.
+pub struct MasterBus {
+    channels: usize,
+    lookahead: usize,
+
+    registry: Arc<EffectRegistry>,
+    effects: EffectChain,
+
+    delay: Vec<f32>,
+    delay_pos: usize,
+
+    // Sliding window maximum of frame peaks over the look-ahead window, as (frame, peak) pairs
+    peaks: VecDeque<(usize, f32)>,
+    frame: usize,
+
+    gain: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+
+    meter: MasterMeter,
+}
+
+impl MasterBus {
+    pub fn new(sample_rate: u32, channels: usize, registry: Arc<EffectRegistry>) -> Self {
+        let lookahead = ((sample_rate as f32 * LOOKAHEAD_SEC) as usize).max(1);
+
+        // Reach the target gain within the look-ahead window
+        let attack_samples = lookahead as f32 / 5.0;
+        let release_samples = sample_rate as f32 * RELEASE_SEC;
+
+        Self {
+            channels,
+            lookahead,
+
+            registry,
+            effects: EffectChain::new(sample_rate),
+
+            delay: vec![0.0; lookahead * channels],
+            delay_pos: 0,
+
+            peaks: VecDeque::with_capacity(lookahead + 1),
+            frame: 0,
+
+            gain: 1.0,
+            attack_coeff: (-1.0 / attack_samples).exp(),
+            release_coeff: (-1.0 / release_samples).exp(),
+
+            meter: MasterMeter {
+                gain_reduction: 1.0,
+                ..MasterMeter::default()
+            },
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Returns the number of frames the output is delayed by.
+    pub fn latency(&self) -> usize {
+        self.lookahead
+    }
+
+    /// Returns how many frames the master effects keep producing output for after the mix falls
+    /// silent, as of the last call to [MasterBus::process] with `settings`.
+    pub fn tail(&self, settings: &MasterSettings) -> usize {
+        self.effects.tail(&settings.effects)
+    }
+
+    /// Processes interleaved audio in place.
+    pub fn process(&mut self, settings: &MasterSettings, buf: &mut [f32]) {
+        self.effects.process(&settings.effects, &self.registry, buf, self.channels);
+
+        let master_gain = db_to_linear(settings.gain_db);
+
+        for frame in buf.chunks_exact_mut(self.channels) {
+            let mut frame_peak = 0.0f32;
+            for sample in frame.iter_mut() {
+                *sample *= master_gain;
+                frame_peak = frame_peak.max(sample.abs());
+            }
+
+            self.meter.peak = self.meter.peak.max(frame_peak);
+            self.meter.clipped |= frame_peak > 1.0;
+
+            while let Some(&(peak_frame, _)) = self.peaks.front() {
+                if peak_frame + self.lookahead > self.frame {
+                    break;
+                }
+                self.peaks.pop_front();
+            }
+
+            while let Some(&(_, peak)) = self.peaks.back() {
+                if peak > frame_peak {
+                    break;
+                }
+                self.peaks.pop_back();
+            }
+
+            self.peaks.push_back((self.frame, frame_peak));
+            self.frame += 1;
+
+            let window_peak = self.peaks.front().map_or(0.0, |&(_, peak)| peak);
+            let target = if window_peak > CEILING { CEILING / window_peak } else { 1.0 };
+            let coeff = if target < self.gain { self.attack_coeff } else { self.release_coeff };
+            self.gain = target + (self.gain - target) * coeff;
+
+            let delayed = &mut self.delay[self.delay_pos..self.delay_pos + self.channels];
+            for (sample, delayed) in frame.iter_mut().zip(delayed.iter_mut()) {
+                let input = *sample;
+
+                *sample = match settings.limiter {
+                    LimiterMode::Off => *delayed,
+                    LimiterMode::SoftClip => delayed.tanh(),
+                    LimiterMode::LookAhead => (*delayed * self.gain).clamp(-CEILING, CEILING),
+                };
+
+                *delayed = input;
+            }
+
+            self.delay_pos = (self.delay_pos + self.channels) % self.delay.len();
+        }
+
+        self.meter.gain_reduction = match settings.limiter {
+            LimiterMode::LookAhead => self.gain,
+            _ => 1.0,
+        };
+    }
+
+    /// Returns the current meter readings and resets the peak level.
+    pub fn take_meter(&mut self) -> MasterMeter {
+        let meter = self.meter;
+        self.meter.peak = 0.0;
+        meter
+    }
+
+    pub fn reset_clip(&mut self) {
+        self.meter.clipped = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn sine(amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| amplitude * (2.0 * PI * 440.0 * i as f32 / 44100.0).sin())
+            .collect()
+    }
+
+    fn bus(channels: usize) -> MasterBus {
+        MasterBus::new(44100, channels, Arc::new(EffectRegistry::default()))
+    }
+
+    fn settings(limiter: LimiterMode) -> MasterSettings {
+        MasterSettings {
+            limiter,
+            ..MasterSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_quiet_signal_is_only_delayed() {
+        for limiter in LimiterMode::ALL {
+            let mut bus = bus(1);
+            let input = sine(0.1, 1000);
+            let mut buf = input.clone();
+            bus.process(&settings(limiter), &mut buf);
+
+            let latency = bus.latency();
+            assert!(buf[..latency].iter().all(|&s| s == 0.0));
+
+            for (output, input) in buf[latency..].iter().zip(&input) {
+                // tanh is not perfectly linear, even for quiet signals
+                assert!((output - input).abs() < 1e-3, "{:?}: {} != {}", limiter, output, input);
+            }
+
+            let meter = bus.take_meter();
+            assert!(!meter.clipped);
+            assert!((meter.peak - 0.1).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_limiter_keeps_loud_signal_below_ceiling() {
+        let mut bus = bus(2);
+        let mut buf: Vec<f32> = sine(4.0, 4410).iter().flat_map(|&s| [s, s * 0.5]).collect();
+        bus.process(&settings(LimiterMode::LookAhead), &mut buf);
+
+        assert!(buf.iter().all(|s| s.abs() <= CEILING));
+
+        // The limiter should turn the signal down rather than squaring it off
+        let clamped = buf.iter().filter(|s| s.abs() >= CEILING).count();
+        assert!(clamped < buf.len() / 100, "{} of {} samples hit the ceiling", clamped, buf.len());
+
+        let meter = bus.take_meter();
+        assert!(meter.clipped);
+        assert!(meter.peak > 3.9);
+        assert!(meter.gain_reduction < 0.3);
+    }
+
+    #[test]
+    fn test_soft_clip_is_bounded() {
+        let mut bus = bus(1);
+        let mut buf = sine(10.0, 1000);
+        bus.process(&settings(LimiterMode::SoftClip), &mut buf);
+
+        assert!(buf.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn test_master_gain() {
+        let mut bus = bus(1);
+        let mut buf = vec![0.6; 1000];
+        let settings = MasterSettings {
+            gain_db: 6.0,
+            limiter: LimiterMode::Off,
+            ..MasterSettings::default()
+        };
+
+        bus.process(&settings, &mut buf);
+        assert!((buf[999] - 0.6 * db_to_linear(6.0)).abs() < 1e-6);
+        assert!(bus.take_meter().clipped);
+    }
+
+    #[test]
+    fn test_master_effects() {
+        let mut bus = bus(1);
+        let mut buf = vec![0.5; 1000];
+        let settings = MasterSettings {
+            effects: vec![EffectSettings::new("gain").with_param("gain", -6.0)],
+            limiter: LimiterMode::Off,
+            ..MasterSettings::default()
+        };
+
+        bus.process(&settings, &mut buf);
+        assert!((buf[999] - 0.5 * db_to_linear(-6.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_meter_reset() {
+        let mut bus = bus(1);
+        bus.process(&settings(LimiterMode::LookAhead), &mut [2.0, 0.0]);
+
+        assert_eq!(2.0, bus.take_meter().peak);
+        assert_eq!(0.0, bus.take_meter().peak, "peak should reset when taken");
+        assert!(bus.take_meter().clipped, "clip indicator should latch");
+
+        bus.reset_clip();
+        assert!(!bus.take_meter().clipped);
+    }
+}
@@ -0,0 +1,174 @@
+use midly::MidiMessage;
+use serde::{Deserialize, Serialize};
+
+use crate::Time;
+use crate::generator::{Generator, MidiEvent};
+
+/// Note played on the first beat of each bar.
+const ACCENT_KEY: u8 = 96;
+const ACCENT_VELOCITY: u8 = 127;
+
+/// Note played on the other beats.
+const BEAT_KEY: u8 = 84;
+const BEAT_VELOCITY: u8 = 80;
+
+/// Persistent settings for the metronome.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetronomeSettings {
+    /// Whether the metronome clicks during playback. Count-ins click either way.
+    pub enabled: bool,
+
+    /// Number of bars to count in before recording starts.
+    pub count_in_bars: u32,
+
+    /// Level of the clicks in dB.
+    pub level_db: f32,
+
+    /// Whether clicks are mixed into recordings as well as the live output.
+    pub record: bool,
+}
+
+impl Default for MetronomeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            count_in_bars: 1,
+            level_db: -6.0,
+            record: false,
+        }
+    }
+}
+
+/// Plays a click on every beat, using a generator for the sound. Each click is a note: a higher
+/// and louder one on the first beat of each bar, and a lower one on the others.
+pub struct Metronome {
+    click: Box<dyn Generator>,
+    events: Vec<MidiEvent>,
+
+    // Note of the last click, released at the start of the next block
+    held: Option<u8>,
+}
+
+impl Metronome {
+    pub fn new(click: Box<dyn Generator>) -> Self {
+        Self {
+            click,
+            events: Vec::new(),
+            held: None,
+        }
+    }
+
+    pub fn set_click(&mut self, click: Box<dyn Generator>) {
+        self.click = click;
+        self.held = None;
+    }
+
+    /// Fills `out` with the clicks for a block starting `position` frames after the first beat,
+    /// where beats are `beat_len` frames long. If `position` is None, no new clicks are started
+    /// but earlier ones keep ringing out.
+    pub fn process(&mut self, position: Option<Time>, beat_len: f64, beats_per_bar: u32, out: &mut [f32]) {
+        self.events.clear();
+
+        if let Some(key) = self.held.take() {
+            self.events.push(MidiEvent { offset: 0, msg: MidiMessage::NoteOff { key: key.into(), vel: 0.into() } });
+        }
+
+        if let Some(position) = position {
+            let end = position + out.len();
+            let mut beat = (position as f64 / beat_len).floor() as u64;
+
+            loop {
+                let beat_time = (beat as f64 * beat_len).round() as Time;
+                if beat_time >= end {
+                    break;
+                }
+
+                if beat_time >= position {
+                    let beat_in_bar = beat % beats_per_bar.max(1) as u64;
+                    let (key, vel) = if beat_in_bar == 0 {
+                        (ACCENT_KEY, ACCENT_VELOCITY)
+                    } else {
+                        (BEAT_KEY, BEAT_VELOCITY)
+                    };
+
+                    let msg = MidiMessage::NoteOn { key: key.into(), vel: vel.into() };
+                    self.events.push(MidiEvent { offset: beat_time - position, msg });
+                    self.held = Some(key);
+                }
+
+                beat += 1;
+            }
+        }
+
+        self.click.process_events(out, &self.events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outputs the key of each note on at the frame it starts, and zero elsewhere.
+    #[derive(Default)]
+    struct KeyRecorder {
+        key: Option<u8>,
+    }
+
+    impl Generator for KeyRecorder {
+        fn process(&mut self, out: &mut [f32]) {
+            out.fill(0.0);
+
+            if let (Some(key), Some(first)) = (self.key.take(), out.first_mut()) {
+                *first = key as f32;
+            }
+        }
+
+        fn handle(&mut self, msg: MidiMessage) {
+            if let MidiMessage::NoteOn { key, .. } = msg {
+                self.key = Some(key.as_int());
+            }
+        }
+    }
+
+    fn clicks(metronome: &mut Metronome, start: Time, len: usize, beat_len: f64) -> Vec<(usize, u8)> {
+        let mut out = vec![0.0; len];
+        for (i, block) in out.chunks_mut(64).enumerate() {
+            metronome.process(Some(start + i * 64), beat_len, 3, block);
+        }
+
+        out.iter().enumerate().filter(|(_, &s)| s != 0.0).map(|(i, &s)| (start + i, s as u8)).collect()
+    }
+
+    #[test]
+    fn test_clicks_on_beats_with_accent() {
+        let mut metronome = Metronome::new(Box::<KeyRecorder>::default());
+        let clicks = clicks(&mut metronome, 0, 1000, 100.5);
+
+        let expected: Vec<(usize, u8)> = (0..10)
+            .map(|beat| {
+                let time = (beat as f64 * 100.5).round() as usize;
+                (time, if beat % 3 == 0 { ACCENT_KEY } else { BEAT_KEY })
+            })
+            .collect();
+
+        assert_eq!(expected, clicks);
+    }
+
+    #[test]
+    fn test_clicks_from_position() {
+        let mut metronome = Metronome::new(Box::<KeyRecorder>::default());
+        let clicks = clicks(&mut metronome, 250, 300, 100.0);
+
+        assert_eq!(vec![(300, ACCENT_KEY), (400, BEAT_KEY), (500, BEAT_KEY)], clicks);
+    }
+
+    #[test]
+    fn test_no_clicks_without_position() {
+        let mut metronome = Metronome::new(Box::<KeyRecorder>::default());
+        let mut out = vec![0.0; 256];
+        metronome.process(None, 100.0, 4, &mut out);
+
+        assert!(out.iter().all(|&s| s == 0.0));
+    }
+}
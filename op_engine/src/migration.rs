@@ -0,0 +1,204 @@
+use serde_json::{Map, Value};
+
+use crate::project::ProjectError;
+
+/// Version of the project format written by this build. Bump it whenever a change to the project
+/// file would be read differently by older builds, and add a migration from the previous version.
+pub(crate) const FORMAT_VERSION: u64 = 1;
+
+/// Upgrades a project document by one version, in place.
+type Migration = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[n]` upgrades a document from version `n` to version `n + 1`.
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [
+    v0_to_v1,
+];
+
+/// Upgrades a project document of any older version to the current format, one version at a time.
+/// Documents without a version are version 0.
+pub(crate) fn migrate(document: &mut Value) -> Result<(), ProjectError> {
+    let document = document.as_object_mut()
+        .ok_or_else(|| invalid_document("project file is not an object"))?;
+
+    let version = match document.get("format_version") {
+        None => 0,
+        Some(version) => version.as_u64()
+            .ok_or_else(|| invalid_document("format_version is not a number"))?,
+    };
+
+    if version > FORMAT_VERSION {
+        return Err(ProjectError::UnsupportedFormatVersion { version });
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(document);
+    }
+
+    document.insert("format_version".to_string(), FORMAT_VERSION.into());
+    Ok(())
+}
+
+fn invalid_document(message: &str) -> ProjectError {
+    ProjectError::LoadProjectError {
+        message: message.to_string(),
+        line: 0,
+        column: 0,
+    }
+}
+
+/// Version 0 covers every project saved before the format was versioned. Depending on their age
+/// they may lack channel counts, clip instance IDs and the next IDs to allocate, all of which are
+/// filled in here.
+fn v0_to_v1(document: &mut Map<String, Value>) {
+    // Projects saved before multi-channel support were always mono
+    document.entry("channels").or_insert(1.into());
+
+    if let Some(timeline) = document.get_mut("timeline").and_then(Value::as_object_mut) {
+        let instances = timeline.get_mut("tracks")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
+            .filter_map(|track| track.get_mut("clips").and_then(Value::as_array_mut))
+            .flatten()
+            .filter_map(Value::as_object_mut);
+
+        // Either every instance has an ID or none do, since IDs were added all at once
+        let mut next_instance_id = 0;
+        for instance in instances {
+            let id = instance.entry("id").or_insert(next_instance_id.into()).as_u64().unwrap_or(0);
+            next_instance_id = next_instance_id.max(id + 1);
+        }
+
+        timeline.entry("next_instance_id").or_insert(next_instance_id.into());
+    }
+
+    if let Some(clip_database) = document.get_mut("clip_database").and_then(Value::as_object_mut) {
+        let next_id = clip_database.get("clips")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flat_map(|clips| clips.keys())
+            .filter_map(|id| id.parse::<u64>().ok())
+            .map(|id| id + 1)
+            .max()
+            .unwrap_or(0);
+
+        clip_database.entry("next_id").or_insert(next_id.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use serde_json::json;
+
+    use crate::Project;
+    use crate::track::ClipInstanceId;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/projects").join(name)
+    }
+
+    /// Returns the time and clip data of every clip instance on a track.
+    fn track_clips(project: &Project, track: usize) -> Vec<(usize, Vec<f32>)> {
+        project.timeline.tracks[track].iter_clips()
+            .map(|c| (c.time, project.clip_database.get(c.clip_id).unwrap().data.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_v0_to_v1() {
+        let mut document = json!({
+            "sample_rate": 44100,
+            "timeline": {"tracks": [
+                {"clips": [{"time": 0, "clip_id": 1}, {"time": 10, "clip_id": 0}]},
+                {"clips": []},
+                {"clips": [{"time": 5, "clip_id": 1}]},
+            ]},
+            "clip_database": {"clips": {"0": {"data": [1.0]}, "1": {"data": [2.0]}}},
+        });
+
+        migrate(&mut document).unwrap();
+
+        assert_eq!(json!(1), document["format_version"]);
+        assert_eq!(json!(1), document["channels"]);
+        assert_eq!(json!(0), document["timeline"]["tracks"][0]["clips"][0]["id"]);
+        assert_eq!(json!(1), document["timeline"]["tracks"][0]["clips"][1]["id"]);
+        assert_eq!(json!(2), document["timeline"]["tracks"][2]["clips"][0]["id"]);
+        assert_eq!(json!(3), document["timeline"]["next_instance_id"]);
+        assert_eq!(json!(2), document["clip_database"]["next_id"]);
+    }
+
+    #[test]
+    fn test_v0_to_v1_keeps_existing_values() {
+        let mut document = json!({
+            "channels": 2,
+            "timeline": {"tracks": [{"clips": [{"id": 4, "time": 0, "clip_id": 0}]}], "next_instance_id": 9},
+            "clip_database": {"clips": {"0": {"hash": "0000000000000000"}}, "next_id": 3},
+        });
+
+        migrate(&mut document).unwrap();
+
+        assert_eq!(json!(2), document["channels"]);
+        assert_eq!(json!(4), document["timeline"]["tracks"][0]["clips"][0]["id"]);
+        assert_eq!(json!(9), document["timeline"]["next_instance_id"]);
+        assert_eq!(json!(3), document["clip_database"]["next_id"]);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut document = json!({"format_version": FORMAT_VERSION + 1});
+        assert!(matches!(migrate(&mut document), Err(ProjectError::UnsupportedFormatVersion { .. })));
+
+        let mut document = json!({"format_version": "1"});
+        assert!(matches!(migrate(&mut document), Err(ProjectError::LoadProjectError { .. })));
+    }
+
+    #[test]
+    fn test_load_v0_baseline() {
+        // Saved before projects had channels, track settings or clip instance IDs
+        let project = Project::load(&fixture("v0-baseline")).unwrap();
+
+        assert_eq!(1, project.channels);
+        assert_eq!(vec![(0, vec![0.5, 0.25]), (4, vec![-0.5])], track_clips(&project, 0));
+        assert_eq!(vec![(2, vec![0.5, 0.25])], track_clips(&project, 1));
+
+        let ids: Vec<ClipInstanceId> = project.timeline.iter_clips().map(|(_, c)| c.id).collect();
+        assert_eq!(vec![ClipInstanceId(0), ClipInstanceId(1), ClipInstanceId(2)], ids);
+        assert_eq!(ClipInstanceId(3), project.timeline.new_instance_id());
+    }
+
+    #[test]
+    fn test_load_v0_inline_clips() {
+        // Saved with track settings and stereo clips, but still with clip audio in the project file
+        let project = Project::load(&fixture("v0-inline-clips")).unwrap();
+
+        assert_eq!(2, project.channels);
+        assert_eq!(96.0, project.tempo);
+        assert_eq!(-6.0, project.timeline.tracks[1].gain_db);
+        assert!(project.timeline.tracks[2].muted);
+        assert_eq!(vec![(100, vec![0.5, -0.5, 0.25, -0.25])], track_clips(&project, 1));
+        assert_eq!(2, project.clip_database.get(project.timeline.tracks[1].iter_clips().next().unwrap().clip_id).unwrap().channels);
+    }
+
+    #[test]
+    fn test_load_v0_audio_files() {
+        // Saved with clip audio in files, just before the format was versioned
+        let project = Project::load(&fixture("v0-audio-files")).unwrap();
+
+        assert_eq!(vec![(0, vec![0.5, 0.25, -0.5, -0.25])], track_clips(&project, 0));
+        assert_eq!(1, project.clip_database.len());
+    }
+
+    #[test]
+    fn test_load_v1() {
+        let project = Project::load(&fixture("v1")).unwrap();
+
+        assert_eq!(120.0, project.tempo);
+        assert_eq!(vec![(0, vec![0.5, 0.25, -0.5, -0.25])], track_clips(&project, 0));
+        assert_eq!(vec![(30, vec![0.5, 0.25, -0.5, -0.25]), (44100, vec![0.5, 0.25, -0.5, -0.25])], track_clips(&project, 3));
+        assert_eq!(ClipInstanceId(6), project.timeline.new_instance_id(), "IDs of removed instances should not be reused");
+    }
+}
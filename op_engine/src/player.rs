@@ -1,29 +1,85 @@
 use std::fmt::Debug;
-use std::mem::take;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use cpal::{BufferSize, StreamConfig};
 use dasp::{signal, Signal};
 use dasp::interpolate::linear::Linear;
+use midly::MidiMessage;
 
-use crate::{Clip, Project, Time};
-use crate::generator::Generator;
+use crate::{convert_channels, db_to_linear, Project, Time, TimelineEffects};
+use crate::effect::EffectRegistry;
+use crate::generator::{Generator, MidiEvent};
+use crate::generator::click::ClickGenerator;
 use crate::generator::sine::SineGenerator;
+use crate::master::{MasterBus, MasterMeter};
+use crate::metronome::Metronome;
+use crate::recorder::Recorder;
+
+/// Assigns MIDI messages to positions within audio blocks.
+#[derive(Default)]
+struct MidiScheduler {
+    queue: Vec<(Instant, MidiMessage)>,  // messages received since the last block, in order
+    events: Vec<MidiEvent>,
+    last_block: Option<Instant>,
+}
+
+impl MidiScheduler {
+    /// Returns the queued messages as events for a block of `frames` frames starting at `now`.
+    /// Messages are delayed by one block and keep their position relative to the start of the
+    /// previous block, so timing does not depend on when they arrive relative to the audio callback.
+    fn schedule(&mut self, now: Instant, frames: usize, sample_rate: u32) -> &[MidiEvent] {
+        let block_start = self.last_block.replace(now).unwrap_or(now);
+        let last_frame = frames.saturating_sub(1);
+
+        self.events.clear();
+
+        // The queue is in arrival order, so the events stay sorted by offset
+        for (time, msg) in self.queue.drain(..) {
+            let elapsed = time.saturating_duration_since(block_start).as_secs_f64();
+            let offset = ((elapsed * sample_rate as f64) as usize).min(last_frame);
+            self.events.push(MidiEvent { offset, msg });
+        }
+
+        &self.events
+    }
+}
+
+/// A recording that was stopped. Its audio is still being written by its recorder.
+pub(crate) struct Take {
+    pub recorder: Recorder,
+    pub track: usize,
+    pub start: Time,
+}
 
 pub struct Player {
     config: StreamConfig,
-    output_buf: Vec<f32>,  // FIXME: Currently assuming mono
+    output_buf: Vec<f32>,  // interleaved, with the project's channel count
+    device_buf: Vec<f32>,  // interleaved, with the output device's channel count
+    generator_buf: Vec<f32>,
+    metronome_buf: Vec<f32>,
 
     project: Arc<RwLock<Project>>,
     pub generator: Box<dyn Generator>,
 
+    midi: MidiScheduler,
+    metronome: Metronome,
+
+    effect_registry: Arc<EffectRegistry>,
+    timeline_effects: TimelineEffects,
+    master: MasterBus,
+
     pub playing_project: bool,
     time: Time,
 
-    recording: bool,
+    recorder: Option<Recorder>,  // set while recording
     record_track: usize,
     record_start: Time,
-    record_buf: Vec<f32>,
+
+    // Frames counted in so far, out of the count-in's length. Recording starts once the count-in
+    // is over.
+    count_in_pos: Time,
+    count_in_len: Time,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -34,48 +90,111 @@ pub enum PlayerError {
 
 impl Player {
     pub fn new(project: Arc<RwLock<Project>>, config: StreamConfig) -> Result<Self, PlayerError> {
-        let buf_size = match config.buffer_size {
+        let frame_count = match config.buffer_size {
             BufferSize::Fixed(frame_count) => frame_count as usize,
             _ => return Err(PlayerError::InvalidBufferSize(config.buffer_size)),
         };
+        let buf_size = frame_count * config.channels as usize;
+
+        let effect_registry = Arc::new(EffectRegistry::default());
+        let (timeline_effects, master, metronome) = {
+            let project = project.read().unwrap();
+            (
+                TimelineEffects::new(project.sample_rate, effect_registry.clone()),
+                MasterBus::new(project.sample_rate, project.channels, effect_registry.clone()),
+                Metronome::new(Box::new(ClickGenerator::new(project.sample_rate))),
+            )
+        };
 
         Ok(Player {
             project,
             generator: Box::new(SineGenerator::new(44100)),
 
+            midi: MidiScheduler::default(),
+            metronome,
+
+            effect_registry,
+            timeline_effects,
+            master,
+
             time: 0,
             config,
             output_buf: vec![0.0; buf_size],
+            device_buf: vec![0.0; buf_size],
+            generator_buf: vec![0.0; frame_count],
+            metronome_buf: vec![0.0; frame_count],
 
             playing_project: false,
 
-            recording: false,
+            recorder: None,
             record_track: 0,
             record_start: 0,
-            record_buf: vec![],
+
+            count_in_pos: 0,
+            count_in_len: 0,
         })
     }
 
-    pub fn set_recording(&mut self, recording: bool, record_track: usize) {
-        if self.recording == recording {
-            return;
-        }
+    /// Starts recording to `recorder`. Any recording in progress is replaced.
+    pub(crate) fn start_recording(&mut self, recorder: Recorder, record_track: usize) {
+        let project = self.project.read().unwrap();
+        let bar_len = project.time_grid().samples_per_bar();
 
-        self.recording = recording;
+        self.count_in_pos = 0;
+        self.count_in_len = (bar_len * project.metronome.count_in_bars as f64).round() as Time;
+        self.record_start = self.time;
+        self.record_track = record_track;
+        self.recorder = Some(recorder);
+    }
 
-        if !recording && !self.record_buf.is_empty() {
-            self.write_recorded_clip();
-        } else if recording {
-            self.record_start = self.time;
-            self.record_track = record_track;
-            self.record_buf.clear();
-        }
+    /// Stops recording, and returns what was recorded along with where it goes on the timeline.
+    pub(crate) fn stop_recording(&mut self) -> Option<Take> {
+        self.recorder.take().map(|recorder| Take {
+            recorder,
+            track: self.record_track,
+            start: self.record_start,
+        })
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
     }
 
-    fn write_recorded_clip(&mut self) {
-        let mut project = self.project.write().unwrap();
-        let id = project.clip_database.add(Clip::new(take(&mut self.record_buf)));
-        project.timeline.tracks[self.record_track].instantiate_clip(id, self.record_start);
+    /// Whether recording is waiting for the count-in to finish. The count-in only runs while the
+    /// project is playing, and holds the playhead in place.
+    pub fn counting_in(&self) -> bool {
+        self.recording() && self.count_in_pos < self.count_in_len
+    }
+
+    /// Replaces the generator used for metronome clicks.
+    pub fn set_metronome_click(&mut self, click: Box<dyn Generator>) {
+        self.metronome.set_click(click);
+    }
+
+    pub fn effect_registry(&self) -> Arc<EffectRegistry> {
+        self.effect_registry.clone()
+    }
+
+    /// Replaces the effects available to tracks and the master bus. Running effects are recreated.
+    pub fn set_effect_registry(&mut self, registry: EffectRegistry) {
+        let project = self.project.read().unwrap();
+
+        self.effect_registry = Arc::new(registry);
+        self.timeline_effects = TimelineEffects::new(project.sample_rate, self.effect_registry.clone());
+        self.master = MasterBus::new(project.sample_rate, project.channels, self.effect_registry.clone());
+    }
+
+    pub fn take_meter(&mut self) -> MasterMeter {
+        self.master.take_meter()
+    }
+
+    pub fn reset_clip(&mut self) {
+        self.master.reset_clip();
+    }
+
+    /// Queues a MIDI message received at `time`. It is handled by the generator in the next block.
+    pub fn handle(&mut self, msg: MidiMessage, time: Instant) {
+        self.midi.queue.push((time, msg));
     }
 
     pub fn seek(&mut self, time: Time) {
@@ -86,15 +205,13 @@ impl Player {
         self.time
     }
 
-    fn write_signal<T, U>(signal: &mut impl Signal<Frame=T>, output: &mut [U], channels: usize)
+    /// Writes `signal` to a single channel of the interleaved `output`.
+    fn write_signal<T, U>(signal: &mut impl Signal<Frame=T>, output: &mut [U], channels: usize, channel: usize)
         where
             U: cpal::Sample + cpal::FromSample<T>
     {
         for frame in output.chunks_mut(channels) {
-            let value = U::from_sample(signal.next());
-            for sample in frame.iter_mut() {
-                *sample = value;
-            }
+            frame[channel] = U::from_sample(signal.next());
         }
     }
 
@@ -102,7 +219,9 @@ impl Player {
         where
             T: cpal::Sample + cpal::FromSample<f32>,
     {
+        let now = Instant::now();
         let project = self.project.read().unwrap();
+        let project_channels = project.channels;
 
         let dst_samples = output.len() / channels;
         let src_sample_rate = project.sample_rate as f64;
@@ -110,41 +229,234 @@ impl Player {
         let src_samples_per_dst = src_sample_rate / dst_sample_rate;
         let src_samples = (dst_samples as f64 * src_samples_per_dst) as usize;
 
-        if self.output_buf.len() < src_samples {
-            eprintln!("increasing render buffer size from {} to {}", self.output_buf.len(), src_samples);
-            self.output_buf.resize(src_samples, 0.0);
+        let output_len = src_samples * project_channels;
+        if self.output_buf.len() < output_len {
+            eprintln!("increasing render buffer size from {} to {}", self.output_buf.len(), output_len);
+            self.output_buf.resize(output_len, 0.0);
+        }
+
+        let device_len = src_samples * channels;
+        if self.device_buf.len() < device_len {
+            self.device_buf.resize(device_len, 0.0);
+        }
+
+        if self.generator_buf.len() < src_samples {
+            self.generator_buf.resize(src_samples, 0.0);
+            self.metronome_buf.resize(src_samples, 0.0);
         }
 
         self.output_buf.fill(0.0);
 
-        if self.playing_project {
-            if self.recording {
-                project.timeline.render_exclude(&project.clip_database, self.time, &mut self.output_buf[..src_samples], &[self.record_track]);
+        let counting_in = self.playing_project && self.counting_in();
+        let block_start = self.time;
+
+        if self.playing_project && !counting_in {
+            if self.recording() {
+                project.timeline.render_exclude(&project.clip_database, self.time, &mut self.output_buf[..output_len], project_channels, &mut self.timeline_effects, &[self.record_track]);
             } else {
-                project.timeline.render(&project.clip_database, self.time, &mut self.output_buf[..src_samples]);
+                project.timeline.render(&project.clip_database, self.time, &mut self.output_buf[..output_len], project_channels, &mut self.timeline_effects);
             }
 
             self.time += src_samples;
         }
 
-        for i in 0..src_samples {
-            let sample = self.generator.next();
-            self.output_buf[i] += sample;
-            self.output_buf[i] = self.output_buf[i].clamp(-1.0, 1.0);
+        let midi_events = self.midi.schedule(now, src_samples, project.sample_rate);
+        let generator_buf = &mut self.generator_buf[..src_samples];
+        self.generator.process_events(generator_buf, midi_events);
 
-            if self.playing_project && self.recording {
-                self.record_buf.push(sample);
+        // Generators are mono, so their output is added to every channel
+        for (frame, sample) in self.output_buf[..output_len].chunks_mut(project_channels).zip(generator_buf.iter()) {
+            for s in frame.iter_mut() {
+                *s += sample;
             }
         }
 
-        let mut src_signal = signal::from_iter(self.output_buf[..src_samples].iter().cloned());
-
-        if src_sample_rate == dst_sample_rate {
-            Self::write_signal(&mut src_signal, output, channels);
+        // The count-in clicks from its own start, so that recording starts on a downbeat
+        let click_position = if counting_in {
+            Some(self.count_in_pos)
+        } else if self.playing_project && project.metronome.enabled {
+            Some(block_start)
         } else {
-            let interpolator = Linear::new(self.output_buf[0], self.output_buf[1]);
-            let mut resampled = src_signal.scale_hz(interpolator, src_samples_per_dst);
-            Self::write_signal(&mut resampled, output, channels);
+            None
+        };
+
+        let metronome_buf = &mut self.metronome_buf[..src_samples];
+        let grid = project.time_grid();
+        self.metronome.process(click_position, grid.samples_per_beat(), grid.beats_per_bar(), metronome_buf);
+
+        let click_level = db_to_linear(project.metronome.level_db);
+        for sample in metronome_buf.iter_mut() {
+            *sample *= click_level;
+        }
+
+        if counting_in {
+            // Recording starts with the first block after the count-in
+            self.count_in_pos += src_samples;
+            if self.count_in_pos >= self.count_in_len {
+                self.record_start = self.time;
+            }
+        } else if self.playing_project {
+            if let Some(recorder) = &mut self.recorder {
+                if project.metronome.record {
+                    recorder.write(generator_buf.iter().zip(metronome_buf.iter()).map(|(s, click)| s + click));
+                } else {
+                    recorder.write(generator_buf.iter().copied());
+                }
+            }
+        }
+
+        if self.master.channels() != project_channels {
+            self.master = MasterBus::new(project.sample_rate, project_channels, self.effect_registry.clone());
+        }
+
+        self.master.process(&project.master, &mut self.output_buf[..output_len]);
+
+        // Clicks are added after the master bus so that its effects and limiter don't affect them
+        for (frame, click) in self.output_buf[..output_len].chunks_mut(project_channels).zip(metronome_buf.iter()) {
+            for s in frame.iter_mut() {
+                *s += click;
+            }
+        }
+
+        convert_channels(&self.output_buf[..output_len], project_channels, &mut self.device_buf[..device_len], channels);
+
+        // Anything the master bus let through above full scale is clipped here rather than being
+        // left to the device
+        for sample in self.device_buf[..device_len].iter_mut() {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+
+        for channel in 0..channels {
+            let channel_samples = self.device_buf[..device_len].iter().skip(channel).step_by(channels);
+            let mut src_signal = signal::from_iter(channel_samples.cloned());
+
+            if src_sample_rate == dst_sample_rate {
+                Self::write_signal(&mut src_signal, output, channels, channel);
+            } else {
+                let interpolator = Linear::new(self.device_buf[channel], self.device_buf[channels + channel]);
+                let mut resampled = src_signal.scale_hz(interpolator, src_samples_per_dst);
+                Self::write_signal(&mut resampled, output, channels, channel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::recorder::TakeInfo;
+
+    use super::*;
+
+    fn note_on() -> MidiMessage {
+        MidiMessage::NoteOn { key: 60.into(), vel: 100.into() }
+    }
+
+    #[test]
+    fn test_midi_offsets_follow_arrival_time() {
+        let mut midi = MidiScheduler::default();
+        let start = Instant::now();
+        let block = Duration::from_millis(10);
+
+        assert!(midi.schedule(start, 441, 44100).is_empty());
+
+        midi.queue.push((start + Duration::from_millis(1), note_on()));
+        midi.queue.push((start + Duration::from_millis(5), note_on()));
+        let offsets: Vec<usize> = midi.schedule(start + block, 441, 44100).iter().map(|e| e.offset).collect();
+        assert_eq!(vec![44, 220], offsets);
+
+        // Messages that arrive late in a stalled block are clamped to the end of the next one
+        midi.queue.push((start + 4 * block, note_on()));
+        let offsets: Vec<usize> = midi.schedule(start + 5 * block, 441, 44100).iter().map(|e| e.offset).collect();
+        assert_eq!(vec![440], offsets);
+    }
+
+    #[test]
+    fn test_count_in_holds_recording() {
+        let mut project = Project::new();
+        project.tempo = 60.0;
+        project.time_signature.beats_per_bar = 2;
+        project.metronome.count_in_bars = 1;
+
+        let config = StreamConfig {
+            channels: 1,
+            sample_rate: cpal::SampleRate(44100),
+            buffer_size: BufferSize::Fixed(4410),
+        };
+
+        let dir = std::env::temp_dir().join("op_engine_test_count_in_holds_recording");
+        let mut player = Player::new(Arc::new(RwLock::new(project)), config).unwrap();
+        let mut output = vec![0.0f32; 4410];
+
+        player.start_recording(Recorder::start(&dir, 44100, TakeInfo { track: 0, start: 0 }).unwrap(), 0);
+        assert!(player.counting_in());
+
+        // The count-in waits for playback
+        player.write_next_block(&mut output, 1);
+        assert!(player.counting_in());
+
+        // Two beats at 60 BPM take 20 blocks
+        player.playing_project = true;
+        for _ in 0..19 {
+            player.write_next_block(&mut output, 1);
+            assert!(player.counting_in());
+            assert_eq!(0, player.time(), "the playhead should not move during the count-in");
+        }
+
+        player.write_next_block(&mut output, 1);
+        assert!(!player.counting_in());
+        assert_eq!(0, player.recorder.as_ref().unwrap().len());
+
+        player.write_next_block(&mut output, 1);
+        assert_eq!(4410, player.time());
+        assert_eq!(4410, player.recorder.as_ref().unwrap().len());
+
+        player.stop_recording().unwrap().recorder.finish().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stop_recording() {
+        let config = StreamConfig {
+            channels: 1,
+            sample_rate: cpal::SampleRate(44100),
+            buffer_size: BufferSize::Fixed(100),
+        };
+
+        let mut project = Project::new();
+        project.metronome.count_in_bars = 0;
+
+        let dir = std::env::temp_dir().join("op_engine_test_stop_recording");
+        let mut player = Player::new(Arc::new(RwLock::new(project)), config).unwrap();
+        let mut output = vec![0.0f32; 100];
+        player.playing_project = true;
+
+        assert!(player.stop_recording().is_none());
+
+        player.write_next_block(&mut output, 1);
+        player.start_recording(Recorder::start(&dir, 44100, TakeInfo { track: 2, start: 100 }).unwrap(), 2);
+        for _ in 0..3 {
+            player.write_next_block(&mut output, 1);
         }
+
+        let take = player.stop_recording().unwrap();
+        assert!(!player.recording());
+        assert_eq!((2, 100), (take.track, take.start));
+        assert_eq!(300, take.recorder.finish().unwrap().unwrap().len());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_midi_before_first_block() {
+        let mut midi = MidiScheduler::default();
+        let start = Instant::now();
+
+        midi.queue.push((start, note_on()));
+        let events = midi.schedule(start + Duration::from_millis(3), 128, 44100);
+        assert_eq!(1, events.len());
+        assert_eq!(0, events[0].offset);
     }
 }
\ No newline at end of file
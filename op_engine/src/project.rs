@@ -1,8 +1,15 @@
 use std::{fs, io};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::{Time, Timeline};
-use crate::clip_database::ClipDatabase;
+use crate::{ClipError, Time, Timeline, TimelineEffects};
+use crate::clip_database::{ClipDatabase, StoredClipDatabase};
+use crate::effect::EffectRegistry;
+use crate::master::{MasterBus, MasterSettings};
+use crate::metronome::MetronomeSettings;
+use crate::migration;
+use crate::recorder::find_takes;
+use crate::time::{TimeGrid, TimeSignature};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ProjectError {
@@ -20,6 +27,33 @@ pub enum ProjectError {
     SaveProjectError {
         message: String,
     },
+
+    #[error("failed to load clip from {path}: {source}")]
+    LoadClipError {
+        path: String,
+        source: ClipError,
+    },
+
+    #[error("failed to save clip to {path}: {source}")]
+    SaveClipError {
+        path: String,
+        source: ClipError,
+    },
+
+    #[error("failed to export to {path}: {source}")]
+    ExportError {
+        path: String,
+        source: hound::Error,
+    },
+
+    #[error("project format version {version} is newer than this version supports ({})", migration::FORMAT_VERSION)]
+    UnsupportedFormatVersion {
+        version: u64,
+    },
+}
+
+fn default_tempo() -> f64 {
+    120.0
 }
 
 /// Owns persistent project data. This is what is saved, loaded, and exported by the user. Its main
@@ -27,8 +61,28 @@ pub enum ProjectError {
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Project {
     pub sample_rate: u32,
+
+    /// Number of channels the timeline is mixed down to.
+    pub channels: usize,
+
     pub timeline: Timeline,
+
+    /// Stored next to the rest of the project, with the audio in separate files.
+    #[serde(skip)]
     pub clip_database: ClipDatabase,
+
+    #[serde(default)]
+    pub master: MasterSettings,
+
+    /// Tempo in beats per minute.
+    #[serde(default = "default_tempo")]
+    pub tempo: f64,
+
+    #[serde(default)]
+    pub time_signature: TimeSignature,
+
+    #[serde(default)]
+    pub metronome: MetronomeSettings,
 }
 
 const PROJECT_EXPORT_SPEC: hound::WavSpec = hound::WavSpec {
@@ -40,69 +94,141 @@ const PROJECT_EXPORT_SPEC: hound::WavSpec = hound::WavSpec {
 
 const PROJECT_FILE_NAME: &str = "project.json";
 
+/// Directory in a project where clip audio is stored.
+const AUDIO_DIR_NAME: &str = "audio";
+
+/// Directory in a project where takes are written while they are recorded.
+const RECORDINGS_DIR_NAME: &str = "recordings";
+
+/// A project as it is stored in the project file. Clip audio is stored in separate files, which
+/// the clip database refers to. Older project files are upgraded by [migration] before they are
+/// deserialized.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ProjectFile<P> {
+    format_version: u64,
+
+    #[serde(flatten)]
+    project: P,
+
+    #[serde(default)]
+    clip_database: StoredClipDatabase,
+}
+
+fn to_save_error(e: serde_json::Error) -> ProjectError {
+    ProjectError::SaveProjectError {
+        message: e.to_string()
+    }
+}
+
+/// Writes the project file to the project directory at `path`, then removes the audio files that
+/// it no longer refers to.
+fn write_project_file(path: &Path, project: impl serde::Serialize, clip_database: StoredClipDatabase) -> Result<(), ProjectError> {
+    let file = ProjectFile {
+        format_version: migration::FORMAT_VERSION,
+        project,
+        clip_database,
+    };
+
+    let serialized = serde_json::to_string(&file).map_err(to_save_error)?;
+
+    // Replaced in one step, so that the project file always refers to a complete set of audio
+    let project_file = path.join(PROJECT_FILE_NAME);
+    let temp_file = project_file.with_extension("json.tmp");
+    fs::write(&temp_file, serialized)?;
+    fs::rename(&temp_file, &project_file)?;
+
+    file.clip_database.remove_unused_files(&path.join(AUDIO_DIR_NAME))
+}
+
 impl Project {
     pub fn new() -> Self {
         Self {
             sample_rate: 44100,
+            channels: 2,
             timeline: Timeline::new(),
             clip_database: ClipDatabase::new(),
+            master: MasterSettings::default(),
+            tempo: default_tempo(),
+            time_signature: TimeSignature::default(),
+            metronome: MetronomeSettings::default(),
         }
     }
 
+    /// Whether there is a project saved in the directory at `path`.
+    pub fn exists(path: &Path) -> bool {
+        path.join(PROJECT_FILE_NAME).is_file()
+    }
+
     /// Loads a new Project. The path should be a directory containing a project file.
     pub fn load(path: &Path) -> Result<Self, ProjectError> {
         let serialized_session = fs::read_to_string(path.join(PROJECT_FILE_NAME))?;
-        let project: Project = serde_json::from_str(serialized_session.as_str())
-            .map_err(|e| {
-                ProjectError::LoadProjectError {
-                    message: e.to_string(),
-                    line: e.line(),
-                    column: e.column(),
-                }
-            })?;
+        let to_load_error = |e: serde_json::Error| {
+            ProjectError::LoadProjectError {
+                message: e.to_string(),
+                line: e.line(),
+                column: e.column(),
+            }
+        };
+
+        let mut document = serde_json::from_str(serialized_session.as_str()).map_err(to_load_error)?;
+        migration::migrate(&mut document)?;
+        let file: ProjectFile<Project> = serde_json::from_value(document).map_err(to_load_error)?;
 
+        let mut project = file.project;
+        project.clip_database = ClipDatabase::load(file.clip_database, &path.join(AUDIO_DIR_NAME), project.sample_rate)?;
         Ok(project)
     }
 
-    pub fn export_wav(&self, path: &Path) -> Result<(), ProjectError> {
-        let samples = self.timeline.render_all(&self.clip_database);
+    /// Renders the project to a WAV file. `effects` is used to create the effects used by tracks
+    /// and the master bus.
+    pub fn export_wav(&self, path: &Path, effects: Arc<EffectRegistry>) -> Result<(), ProjectError> {
+        let mut timeline_effects = TimelineEffects::new(self.sample_rate, effects.clone());
+        let mut samples = self.timeline.render_all(&self.clip_database, self.channels, &mut timeline_effects);
 
-        let mut writer = hound::WavWriter::create(path, PROJECT_EXPORT_SPEC).unwrap();
+        // Run the mix through the master bus, then keep going until the master effects have
+        // decayed and the latency of the bus has been made up for
+        let mut master = MasterBus::new(self.sample_rate, self.channels, effects);
+        master.process(&self.master, &mut samples);
+
+        let mut tail = vec![0.0f32; (master.tail(&self.master) + master.latency()) * self.channels];
+        master.process(&self.master, &mut tail);
+        samples.extend(tail);
+        let samples = &samples[master.latency() * self.channels..];
+        let spec = hound::WavSpec {
+            channels: self.channels as u16,
+            sample_rate: self.sample_rate,
+            ..PROJECT_EXPORT_SPEC
+        };
+
+        let to_export_error = |e| ProjectError::ExportError { path: path.display().to_string(), source: e };
+        let mut writer = hound::WavWriter::create(path, spec).map_err(to_export_error)?;
 
         for sample in samples {
-            writer.write_sample((sample * i16::MAX as f32) as i16)
-                .map_err(|e| {
-                    match e {
-                        hound::Error::IoError(io_error) => ProjectError::IoError(io_error),
-                        _ => panic!("sample calculation was incorrect"),
-                    }
-                })?;
+            writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).map_err(to_export_error)?;
         }
 
-        writer.finalize()
-            .map_err(|e| {
-                match e {
-                    hound::Error::IoError(io_error) => ProjectError::IoError(io_error),
-                    hound::Error::UnfinishedSample => panic!("unfinished sample at export"),
-                    _ => panic!("unexpected error"),
-                }
-            })?;
-
+        writer.finalize().map_err(to_export_error)?;
         Ok(())
     }
 
+    /// Saves the project to a directory, which is created if needed. Clip audio is written to
+    /// files in an audio directory within it.
     pub fn save(&self, path: &Path) -> Result<(), ProjectError> {
         fs::create_dir_all(path)?;
 
-        let serialized = serde_json::to_string(self)
-            .map_err(|e| {
-                ProjectError::SaveProjectError {
-                    message: e.to_string()
-                }
-            })?;
+        let audio_dir = path.join(AUDIO_DIR_NAME);
+        let clip_database = self.clip_database.save(&audio_dir, self.sample_rate)?;
+        write_project_file(path, self, clip_database)
+    }
 
-        fs::write(path.join(PROJECT_FILE_NAME), serialized)?;
-        Ok(())
+    /// Returns the directory that takes should be recorded to for a project or autosave at `path`.
+    pub fn recordings_dir(path: &Path) -> PathBuf {
+        path.join(RECORDINGS_DIR_NAME)
+    }
+
+    /// Whether there are takes in the recordings directory of the project or autosave at `path`.
+    pub fn has_takes(path: &Path) -> bool {
+        !find_takes(&Self::recordings_dir(path)).is_empty()
     }
 
     pub fn sec_to_samples(&self, sec: f32) -> Time {
@@ -112,4 +238,71 @@ impl Project {
     pub fn samples_to_sec(&self, samples: Time) -> f32 {
         samples as f32 / self.sample_rate as f32
     }
+
+    /// Returns the grid of bars and beats for the project's tempo and time signature.
+    pub fn time_grid(&self) -> TimeGrid {
+        TimeGrid::new(self.sample_rate, self.tempo, self.time_signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Clip;
+    use crate::effect::{delay, EffectSettings};
+
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join("op_engine_test_project_save_and_load");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut project = Project::new();
+        project.tempo = 97.0;
+        let clip = project.clip_database.add(Clip::new(vec![0.1, 0.2, 0.3]));
+        let instance = project.timeline.instantiate_clip(1, clip, 1234);
+        project.save(&dir).unwrap();
+
+        let json = fs::read_to_string(dir.join(PROJECT_FILE_NAME)).unwrap();
+        assert!(!json.contains("0.2"), "clip audio should not be in the project file: {}", json);
+
+        assert!(Project::exists(&dir));
+        let loaded = Project::load(&dir).unwrap();
+        assert_eq!(97.0, loaded.tempo);
+        assert_eq!(Some((1, 1234)), loaded.timeline.find_clip(instance).map(|(t, c)| (t, c.time)));
+        assert_eq!(vec![0.1, 0.2, 0.3], loaded.clip_database.get(clip).unwrap().data);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_to_missing_dir() {
+        let path = std::env::temp_dir().join("op_engine_test_missing_dir").join("export.wav");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        let project = Project::new();
+        let result = project.export_wav(&path, Arc::new(EffectRegistry::default()));
+        assert!(matches!(result, Err(ProjectError::ExportError { .. })), "exporting should fail without panicking");
+    }
+
+    #[test]
+    fn test_export_includes_master_tail() {
+        let path = std::env::temp_dir().join("op_engine_test_export_tail.wav");
+
+        let mut project = Project::new();
+        let clip = project.clip_database.add(Clip::new(vec![0.5]));
+        project.timeline.instantiate_clip(0, clip, 0);
+        project.master.effects.push(EffectSettings::new(delay::KIND)
+            .with_param("time", 10.0)
+            .with_param("feedback", 0.0)
+            .with_param("mix", 1.0));
+        project.export_wav(&path, Arc::new(EffectRegistry::default())).unwrap();
+
+        let samples: Vec<i16> = hound::WavReader::open(&path).unwrap().into_samples().map(Result::unwrap).collect();
+        let frames: Vec<&[i16]> = samples.chunks(project.channels).collect();
+        assert_eq!(1 + 441, frames.len(), "the delay should not be cut off");
+        assert!(frames[441].iter().all(|&s| s != 0));
+
+        fs::remove_file(path).unwrap();
+    }
 }
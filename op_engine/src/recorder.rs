@@ -0,0 +1,373 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::{ChannelPolicy, Clip, ClipError, Project, Time};
+
+/// Seconds of audio the ring buffer holds. The writer thread has this long to catch up when the
+/// disk stalls before samples are dropped.
+const BUFFER_SECONDS: usize = 2;
+
+/// How often the writer thread checks for new samples.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the file's header is updated, so that a crash leaves a readable file with everything
+/// up to the last update.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Streams a mono recording to a WAV file. Samples are handed from the audio thread to a writer
+/// thread through a lock-free ring buffer, so the audio thread never waits on the disk.
+pub(crate) struct Recorder {
+    producer: Producer<f32>,
+    writer: JoinHandle<Result<(), ClipError>>,
+    path: PathBuf,
+    sample_rate: u32,
+
+    // Samples handed to the writer thread, and samples dropped because the ring buffer was full
+    len: usize,
+    dropped: usize,
+}
+
+/// Where a take goes on the timeline. It is written next to the take's file, so that a take left
+/// behind by a crash can be put back in place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct TakeInfo {
+    pub track: usize,
+    pub start: Time,
+}
+
+fn to_write_error(e: io::Error) -> ClipError {
+    ClipError::ClipWriteError { source: e.into() }
+}
+
+fn info_path(take_path: &Path) -> PathBuf {
+    take_path.with_extension("json")
+}
+
+/// Creates a new file for a take in `dir`, numbered after the takes already there.
+fn create_take_file(dir: &Path) -> io::Result<(PathBuf, File)> {
+    fs::create_dir_all(dir)?;
+
+    for n in 1.. {
+        let path = dir.join(format!("take-{}.wav", n));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!()
+}
+
+/// Writes samples from `consumer` until the producer is dropped and everything it sent has been
+/// written.
+fn write_take(mut consumer: Consumer<f32>, mut writer: WavWriter<BufWriter<File>>) -> Result<(), hound::Error> {
+    let mut last_flush = Instant::now();
+
+    loop {
+        // Checked before reading, so that samples sent just before the producer was dropped are
+        // still written
+        let abandoned = consumer.is_abandoned();
+
+        let available = consumer.slots();
+        if available > 0 {
+            let chunk = consumer.read_chunk(available).expect("slots should be readable");
+            let (first, second) = chunk.as_slices();
+            for &sample in first.iter().chain(second) {
+                writer.write_sample(sample)?;
+            }
+
+            chunk.commit_all();
+        } else if abandoned {
+            break;
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            writer.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+
+    writer.finalize()
+}
+
+impl Recorder {
+    /// Starts a take in a new file in `dir`, and the thread that writes it.
+    pub(crate) fn start(dir: &Path, sample_rate: u32, info: TakeInfo) -> Result<Self, ClipError> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+
+        let (path, file) = create_take_file(dir).map_err(to_write_error)?;
+        let info_file = File::create(info_path(&path)).map_err(to_write_error)?;
+        serde_json::to_writer(info_file, &info).map_err(|e| to_write_error(e.into()))?;
+
+        let mut writer = WavWriter::new(BufWriter::new(file), spec).map_err(|e| ClipError::ClipWriteError { source: e })?;
+
+        // Writes the header, so that the file can be read before any audio is flushed
+        writer.flush().map_err(|e| ClipError::ClipWriteError { source: e })?;
+        let (producer, consumer) = RingBuffer::new(sample_rate as usize * BUFFER_SECONDS);
+
+        let writer = thread::spawn(move || {
+            write_take(consumer, writer).map_err(|e| ClipError::ClipWriteError { source: e })
+        });
+
+        Ok(Self {
+            producer,
+            writer,
+            path,
+            sample_rate,
+            len: 0,
+            dropped: 0,
+        })
+    }
+
+    /// Hands samples to the writer thread. Never blocks or allocates, so it is safe to call from
+    /// the audio thread.
+    pub(crate) fn write(&mut self, samples: impl ExactSizeIterator<Item=f32>) {
+        let count = samples.len();
+        let written = match self.producer.write_chunk_uninit(count.min(self.producer.slots())) {
+            Ok(chunk) => chunk.fill_from_iter(samples),
+            Err(_) => 0,
+        };
+
+        self.len += written;
+        self.dropped += count - written;
+    }
+
+    /// Returns the number of samples recorded so far.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of samples that were dropped because the writer thread fell behind.
+    /// They are missing from the take.
+    pub(crate) fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns the file the take is written to.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits for the writer thread to write everything recorded, and returns the take as a clip.
+    /// The take's files are kept until the clip has been saved in a project, see [remove_take],
+    /// unless nothing was recorded. If the writer thread panicked, they are left for
+    /// [recover_takes].
+    pub(crate) fn finish(self) -> Result<Option<Clip>, ClipError> {
+        let Recorder { producer, writer, path, sample_rate, len, .. } = self;
+
+        // Dropping the producer tells the writer thread to finish
+        drop(producer);
+        writer.join().map_err(|_| ClipError::RecordingAborted { path: path.clone() })??;
+
+        if len == 0 {
+            remove_take(&path).map_err(to_write_error)?;
+            return Ok(None);
+        }
+
+        read_take(&path, sample_rate).map(Some)
+    }
+}
+
+/// Removes a take's files.
+pub(crate) fn remove_take(path: &Path) -> io::Result<()> {
+    fs::remove_file(path)?;
+
+    match fs::remove_file(info_path(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the files of the takes in `dir`, in the order they were started.
+pub(crate) fn find_takes(dir: &Path) -> Vec<PathBuf> {
+    let mut takes: Vec<(u64, PathBuf)> = match dir.read_dir() {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "wav"))
+            .filter_map(|path| {
+                let n = path.file_stem()?.to_str()?.strip_prefix("take-")?.parse().ok()?;
+                Some((n, path))
+            })
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+
+    takes.sort();
+    takes.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Adds the takes left in `dir` by a session that didn't finish them, or didn't save them, to
+/// `project` at the place they were recorded. Takes whose audio is already in the project, because
+/// it was saved after they finished, are not added again.
+///
+/// Every take is moved to `record_dir`, and its new file returned, so that it can be removed once
+/// the project is saved. Files that can't be read are left where they are.
+pub(crate) fn recover_takes(project: &mut Project, dir: &Path, record_dir: &Path) -> Result<Vec<PathBuf>, ClipError> {
+    let mut recovered = Vec::new();
+
+    for path in find_takes(dir) {
+        let clip = match read_take(&path, project.sample_rate) {
+            Ok(clip) => clip,
+            Err(_) => continue,
+        };
+
+        if clip.data.is_empty() {
+            remove_take(&path).map_err(to_write_error)?;
+            continue;
+        }
+
+        let info = fs::read(info_path(&path)).ok()
+            .and_then(|json| serde_json::from_slice::<TakeInfo>(&json).ok());
+
+        if !project.clip_database.contains(&clip) {
+            let clip_id = project.clip_database.add(clip);
+
+            // Without its info, the take is only added to the clip database
+            if let Some(info) = info.filter(|info| info.track < project.timeline.tracks.len()) {
+                project.timeline.instantiate_clip(info.track, clip_id, info.start);
+            }
+        }
+
+        // Replaces an empty file created under a new number
+        let (new_path, _) = create_take_file(record_dir).map_err(to_write_error)?;
+        fs::rename(&path, &new_path).map_err(to_write_error)?;
+        if info.is_some() {
+            fs::rename(info_path(&path), info_path(&new_path)).map_err(to_write_error)?;
+        }
+
+        recovered.push(new_path);
+    }
+
+    Ok(recovered)
+}
+
+/// Reads a take's file. The file of a take that is still being recorded is only updated every
+/// [FLUSH_INTERVAL], so its most recent audio may be missing.
+pub(crate) fn read_take(path: &Path, sample_rate: u32) -> Result<Clip, ClipError> {
+    Clip::load_wav(sample_rate, &path.display().to_string(), ChannelPolicy::Keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: TakeInfo = TakeInfo { track: 0, start: 0 };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("op_engine_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_record_to_file() {
+        let dir = temp_dir("recorder_record_to_file");
+        let mut recorder = Recorder::start(&dir, 44100, INFO).unwrap();
+        assert!(recorder.path().exists());
+
+        let samples: Vec<f32> = (0..10000).map(|i| (i as f32 / 100.0).sin()).collect();
+        for block in samples.chunks(128) {
+            recorder.write(block.iter().copied());
+        }
+
+        assert_eq!(10000, recorder.len());
+
+        let path = recorder.path().to_path_buf();
+        let clip = recorder.finish().unwrap().unwrap();
+        assert_eq!(samples, clip.data);
+        assert!(path.exists(), "the take's file should be kept until its clip is saved");
+
+        remove_take(&path).unwrap();
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_while_recording() {
+        let dir = temp_dir("recorder_read_while_recording");
+        let mut recorder = Recorder::start(&dir, 44100, INFO).unwrap();
+        assert!(read_take(recorder.path(), 44100).unwrap().data.is_empty());
+
+        recorder.write([0.5; 1000].into_iter());
+        thread::sleep(FLUSH_INTERVAL + 5 * POLL_INTERVAL);
+        assert_eq!(vec![0.5; 1000], read_take(recorder.path(), 44100).unwrap().data);
+
+        recorder.finish().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_takes_get_new_files() {
+        let dir = temp_dir("recorder_new_files");
+        let first = Recorder::start(&dir, 44100, INFO).unwrap();
+        let second = Recorder::start(&dir, 44100, INFO).unwrap();
+        assert_ne!(first.path(), second.path());
+
+        assert!(first.finish().unwrap().is_none(), "an empty take should not become a clip");
+        assert!(second.finish().unwrap().is_none());
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_drops_samples_when_full() {
+        let dir = temp_dir("recorder_full");
+
+        // The ring buffer holds 2 samples at this sample rate, so the last of 3 doesn't fit
+        let mut recorder = Recorder::start(&dir, 1, INFO).unwrap();
+        recorder.write([1.0, 2.0, 3.0].into_iter());
+        assert_eq!(2, recorder.len());
+        assert_eq!(1, recorder.dropped());
+
+        recorder.finish().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_recover_takes() {
+        let dir = temp_dir("recorder_recover_takes");
+        let record_dir = temp_dir("recorder_recover_takes_new");
+
+        // The first take made it into the project before the crash, the second didn't
+        let mut project = Project::new();
+        let mut saved = Recorder::start(&dir, 44100, TakeInfo { track: 0, start: 10 }).unwrap();
+        saved.write([0.1; 100].into_iter());
+        let clip_id = project.clip_database.add(saved.finish().unwrap().unwrap());
+        project.timeline.instantiate_clip(0, clip_id, 10);
+
+        let mut lost = Recorder::start(&dir, 44100, TakeInfo { track: 2, start: 500 }).unwrap();
+        lost.write([0.2; 300].into_iter());
+        lost.finish().unwrap();
+
+        let recovered = recover_takes(&mut project, &dir, &record_dir).unwrap();
+        assert_eq!(2, recovered.len());
+        assert!(find_takes(&dir).is_empty());
+        assert_eq!(recovered, find_takes(&record_dir));
+
+        assert_eq!(2, project.clip_database.len(), "the saved take should not be added again");
+        let (_, instance) = project.timeline.iter_clips().find(|(track, _)| *track == 2).unwrap();
+        assert_eq!(500, instance.time);
+        assert_eq!(vec![0.2; 300], project.clip_database.get(instance.clip_id).unwrap().data);
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(record_dir).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use std::f64::consts::PI;
+
+/// Number of zero crossings of the sinc kernel on each side of the interpolation point.
+const KERNEL_ZERO_CROSSINGS: usize = 32;
+
+/// Number of precomputed kernel values between two zero crossings. Values in between are linearly
+/// interpolated.
+const KERNEL_RESOLUTION: usize = 512;
+
+/// A band-limited sample rate converter based on a Blackman-windowed sinc kernel.
+///
+/// When downsampling, the kernel's cutoff is lowered to the destination Nyquist frequency so that
+/// content which cannot be represented at the new rate is filtered out instead of aliasing.
+pub struct Resampler {
+    src_rate: u32,
+    dst_rate: u32,
+    kernel: Vec<f64>,
+}
+
+fn blackman(x: f64) -> f64 {
+    // x is in [-1, 1]
+    let n = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Resampler {
+    pub fn new(src_rate: u32, dst_rate: u32) -> Self {
+        let len = KERNEL_ZERO_CROSSINGS * KERNEL_RESOLUTION + 1;
+        let kernel = (0..len)
+            .map(|i| {
+                let x = i as f64 / KERNEL_RESOLUTION as f64;
+                sinc(x) * blackman(x / KERNEL_ZERO_CROSSINGS as f64)
+            })
+            .collect();
+
+        Self { src_rate, dst_rate, kernel }
+    }
+
+    /// Returns the kernel value at `x` zero crossings from the center.
+    fn kernel_at(&self, x: f64) -> f64 {
+        let pos = x.abs() * KERNEL_RESOLUTION as f64;
+        let i = pos as usize;
+
+        if i + 1 >= self.kernel.len() {
+            return 0.0;
+        }
+
+        let frac = pos - i as f64;
+        self.kernel[i] * (1.0 - frac) + self.kernel[i + 1] * frac
+    }
+
+    /// Returns the number of samples produced when converting `src_len` samples.
+    pub fn output_len(&self, src_len: usize) -> usize {
+        (src_len as u64 * self.dst_rate as u64).div_ceil(self.src_rate as u64) as usize
+    }
+
+    pub fn process(&self, input: &[f32]) -> Vec<f32> {
+        if self.src_rate == self.dst_rate || input.is_empty() {
+            return input.to_vec();
+        }
+
+        let step = self.src_rate as f64 / self.dst_rate as f64;
+
+        // Fraction of the source Nyquist frequency that is passed through
+        let cutoff = (1.0 / step).min(1.0);
+        let half_width = KERNEL_ZERO_CROSSINGS as f64 / cutoff;
+
+        (0..self.output_len(input.len()))
+            .map(|n| {
+                let t = n as f64 * step;
+                let first = (t - half_width).ceil().max(0.0) as usize;
+                let last = ((t + half_width).floor() as usize).min(input.len() - 1);
+
+                let sum: f64 = (first..=last)
+                    .map(|k| input[k] as f64 * self.kernel_at((t - k as f64) * cutoff))
+                    .sum();
+
+                (sum * cutoff) as f32
+            })
+            .collect()
+    }
+}
+
+/// Converts `input` from `src_rate` to `dst_rate`.
+pub fn resample(input: &[f32], src_rate: u32, dst_rate: u32) -> Vec<f32> {
+    Resampler::new(src_rate, dst_rate).process(input)
+}
+
+/// Converts interleaved `input` with `channels` channels from `src_rate` to `dst_rate`.
+pub fn resample_interleaved(input: &[f32], channels: usize, src_rate: u32, dst_rate: u32) -> Vec<f32> {
+    if channels == 1 || src_rate == dst_rate {
+        return resample(input, src_rate, dst_rate);
+    }
+
+    let resampler = Resampler::new(src_rate, dst_rate);
+    let resampled: Vec<Vec<f32>> = (0..channels)
+        .map(|c| {
+            let channel: Vec<f32> = input.iter().skip(c).step_by(channels).cloned().collect();
+            resampler.process(&channel)
+        })
+        .collect();
+
+    let frames = resampled[0].len();
+    (0..frames * channels)
+        .map(|i| resampled[i % channels][i / channels])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn sine(freq: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    /// Estimates the frequency of a signal from the positions of its rising zero crossings,
+    /// ignoring the edges where the filter has not settled.
+    fn estimate_freq(signal: &[f32], sample_rate: u32) -> f32 {
+        let margin = signal.len() / 10;
+        let crossings: Vec<f32> = (margin..signal.len() - margin)
+            .filter(|&i| signal[i - 1] < 0.0 && signal[i] >= 0.0)
+            .map(|i| {
+                let (a, b) = (signal[i - 1], signal[i]);
+                (i - 1) as f32 + a / (a - b)
+            })
+            .collect();
+
+        let periods = (crossings.len() - 1) as f32;
+        periods * sample_rate as f32 / (crossings.last().unwrap() - crossings.first().unwrap())
+    }
+
+    fn rms(signal: &[f32]) -> f32 {
+        let margin = signal.len() / 10;
+        let middle = &signal[margin..signal.len() - margin];
+        (middle.iter().map(|s| s * s).sum::<f32>() / middle.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_same_rate_is_identity() {
+        let input = sine(440.0, 44100, 1000);
+        assert_eq!(resample(&input, 44100, 44100), input);
+    }
+
+    #[test]
+    fn test_output_len() {
+        assert_eq!(Resampler::new(48000, 44100).output_len(48000), 44100);
+        assert_eq!(Resampler::new(22050, 44100).output_len(100), 200);
+        assert_eq!(Resampler::new(44100, 48000).output_len(0), 0);
+    }
+
+    #[test]
+    fn test_downsample_preserves_frequency() {
+        let input = sine(1000.0, 48000, 48000);
+        let output = resample(&input, 48000, 44100);
+
+        assert_eq!(output.len(), 44100);
+        assert!((estimate_freq(&output, 44100) - 1000.0).abs() < 0.5);
+        assert!((rms(&output) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_upsample_preserves_frequency() {
+        let input = sine(440.0, 22050, 22050);
+        let output = resample(&input, 22050, 44100);
+
+        assert_eq!(output.len(), 44100);
+        assert!((estimate_freq(&output, 44100) - 440.0).abs() < 0.5);
+        assert!((rms(&output) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_resample_interleaved_keeps_channels_separate() {
+        let left = sine(1000.0, 48000, 48000);
+        let right = sine(250.0, 48000, 48000);
+        let input: Vec<f32> = left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]).collect();
+
+        let output = resample_interleaved(&input, 2, 48000, 44100);
+        assert_eq!(output.len(), 2 * 44100);
+
+        let left: Vec<f32> = output.iter().step_by(2).cloned().collect();
+        let right: Vec<f32> = output.iter().skip(1).step_by(2).cloned().collect();
+        assert!((estimate_freq(&left, 44100) - 1000.0).abs() < 0.5);
+        assert!((estimate_freq(&right, 44100) - 250.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_downsample_removes_content_above_nyquist() {
+        // 30 kHz is representable at 96 kHz but not at 44.1 kHz, so it must be filtered out
+        // rather than aliased down to 14.1 kHz.
+        let input = sine(30000.0, 96000, 9600);
+        let output = resample(&input, 96000, 44100);
+
+        assert!(rms(&output) < 0.01);
+    }
+}
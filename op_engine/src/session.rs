@@ -1,19 +1,33 @@
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 use cpal::{BufferSize, StreamConfig};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 
-use crate::{Player, Time};
+use crate::{ClipError, Player, Time};
+use crate::effect::EffectRegistry;
 use crate::generator::Generator;
+use crate::history::{Edit, History};
+use crate::master::MasterMeter;
 use crate::player::PlayerError;
-use crate::project::Project;
+use crate::project::{Project, ProjectError};
+use crate::recorder::{recover_takes, remove_take, Recorder, TakeInfo};
+use crate::track::ClipInstance;
 
 /// A Session is a loaded Project plus a context for playing and recording audio.
 pub struct Session {
     pub project: Arc<RwLock<Project>>,
     player: Arc<Mutex<Player>>,
-    output_stream: cpal::Stream,
+    history: History,
+    record_dir: Option<PathBuf>,
+
+    // Files of finished takes, which are kept until the project is saved with their clips
+    unsaved_takes: Vec<PathBuf>,
+
+    // None if the session has no audio output
+    output_stream: Option<cpal::Stream>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -29,8 +43,26 @@ pub enum SessionError {
 
     #[error(transparent)]
     PlayerError(#[from] PlayerError),
+
+    #[error("recording failed: {0}")]
+    RecordingFailed(#[from] ClipError),
+
+    #[error("no directory to record to has been set")]
+    NoRecordDir,
+
+    #[error("no audio output device is available")]
+    NoOutputDevice,
+
+    #[error("failed to get the output device's configuration: {0}")]
+    OutputConfigFailed(#[from] cpal::DefaultStreamConfigError),
+
+    #[error("the output device's sample format '{0}' is not supported")]
+    UnsupportedSampleFormat(cpal::SampleFormat),
 }
 
+/// Number of frames the player renders at a time.
+const BUFFER_SIZE: u32 = 128;
+
 fn stream_error_callback(err: cpal::StreamError) {
     eprintln!("an error occurred on stream: {}", err);
 }
@@ -59,14 +91,11 @@ impl Session {
 
         // TODO: Hosts and devices will eventually need to be configurable.
         let host = cpal::default_host();
-        let output_device = host.default_output_device().expect("output device required");
-
-        let output_supported_config = output_device
-            .default_output_config()
-            .expect("default config for host-provided default output device must be valid");
+        let output_device = host.default_output_device().ok_or(SessionError::NoOutputDevice)?;
+        let output_supported_config = output_device.default_output_config()?;
 
         // TODO: Validate that buffer size is supported.
-        let buffer_size = BufferSize::Fixed(128);
+        let buffer_size = BufferSize::Fixed(BUFFER_SIZE);
         let output_sample_format = output_supported_config.sample_format();
         println!("Supported config: {:?}", output_supported_config);
 
@@ -90,11 +119,11 @@ impl Session {
                 U64 => build_output_stream::<u64>(&output_device, &output_config, player_ref),
                 F32 => build_output_stream::<f32>(&output_device, &output_config, player_ref),
                 F64 => build_output_stream::<f64>(&output_device, &output_config, player_ref),
-                f => panic!("unsupported sample format '{}'", f),
+                f => return Err(SessionError::UnsupportedSampleFormat(f)),
             }?;
         }
 
-        output_stream.play().expect("could not start output stream");
+        output_stream.play()?;
 
         println!("Session information:");
         println!("  Output: {}\n    {:?}", output_device.name().unwrap_or("<error>".to_string()), output_config);
@@ -102,12 +131,42 @@ impl Session {
         let session = Session {
             project,
             player,
-            output_stream,
+            history: History::new(),
+            record_dir: None,
+            unsaved_takes: Vec::new(),
+            output_stream: Some(output_stream),
         };
 
         Ok(session)
     }
 
+    /// Creates a session for `project` without audio output, for when no output device can be
+    /// opened. Everything but listening to the project works as usual.
+    pub fn new_without_output(project: Project) -> Self {
+        let config = StreamConfig {
+            channels: project.channels as u16,
+            sample_rate: cpal::SampleRate(project.sample_rate),
+            buffer_size: BufferSize::Fixed(BUFFER_SIZE),
+        };
+
+        let project = Arc::new(RwLock::new(project));
+        let player = Player::new(project.clone(), config).expect("the buffer size is fixed");
+
+        Session {
+            project,
+            player: Arc::new(Mutex::new(player)),
+            history: History::new(),
+            record_dir: None,
+            unsaved_takes: Vec::new(),
+            output_stream: None,
+        }
+    }
+
+    /// Whether the session plays to an output device.
+    pub fn has_output(&self) -> bool {
+        self.output_stream.is_some()
+    }
+
     pub fn new_empty() -> Result<Self, SessionError> {
         Self::new_with_project(Project::new())
     }
@@ -134,18 +193,199 @@ impl Session {
         player.time()
     }
 
-    pub fn set_recording(&self, recording: bool, record_track: usize) {
+    /// Sets the directory that takes are written to. It should be next to the session's autosave,
+    /// see [Project::recordings_dir], so that takes can be recovered along with it.
+    pub fn set_record_dir(&mut self, dir: PathBuf) {
+        self.record_dir = Some(dir);
+    }
+
+    /// Starts or stops recording. Takes are streamed to a file while they are recorded, and added
+    /// to the project when recording stops. The file is kept until the project is saved with
+    /// [Session::save]. A finished recording can be undone like any other edit.
+    ///
+    /// Returns the number of samples dropped from a take that was stopped, because they couldn't
+    /// be written to its file in time. They are missing from its clip.
+    pub fn set_recording(&mut self, recording: bool, record_track: usize) -> Result<usize, SessionError> {
+        if recording == self.player.lock().unwrap().recording() {
+            return Ok(0);
+        }
+
+        if recording {
+            let dir = self.record_dir.as_ref().ok_or(SessionError::NoRecordDir)?;
+
+            // The playhead is held during the count-in, so the take starts where it is now
+            let info = TakeInfo { track: record_track, start: self.time() };
+
+            // The file and its writer thread are set up before the player is locked
+            let sample_rate = self.project.read().unwrap().sample_rate;
+            let recorder = Recorder::start(dir, sample_rate, info)?;
+            self.player.lock().unwrap().start_recording(recorder, record_track);
+            return Ok(0);
+        }
+
+        let take = match self.player.lock().unwrap().stop_recording() {
+            Some(take) => take,
+            None => return Ok(0),
+        };
+
+        let dropped = take.recorder.dropped();
+        let path = take.recorder.path().to_path_buf();
+        let clip = match take.recorder.finish()? {
+            Some(clip) => clip,
+            None => return Ok(dropped),
+        };
+
+        self.unsaved_takes.push(path);
+
+        let edit = {
+            let mut project = self.project.write().unwrap();
+            let clip_id = project.clip_database.add(clip);
+            let id = project.timeline.new_instance_id();
+
+            Edit::AddClip {
+                track: take.track,
+                instance: ClipInstance::new(id, take.start, clip_id),
+            }
+        };
+
+        self.edit(edit);
+        Ok(dropped)
+    }
+
+    /// Saves the project to `path`, then removes the files of the takes recorded since the last
+    /// save, whose clips are now in the project.
+    pub fn save(&mut self, path: &Path) -> Result<(), ProjectError> {
+        self.project.read().unwrap().save(path)?;
+
+        // Kept to be removed by the next save if they can't be removed now
+        self.unsaved_takes.retain(|take| remove_take(take).is_err());
+        Ok(())
+    }
+
+    /// Whether takes were recorded since the last save. Their clips are lost if the project is
+    /// closed without saving it.
+    pub fn has_unsaved_takes(&self) -> bool {
+        !self.unsaved_takes.is_empty()
+    }
+
+    /// Removes the files of the takes recorded since the last save, for when the project is closed
+    /// without saving it.
+    pub fn discard_takes(&mut self) {
+        for take in self.unsaved_takes.drain(..) {
+            let _ = remove_take(&take);
+        }
+    }
+
+    /// Saves a copy of the project to `path` that can be loaded after a crash. Takes which haven't
+    /// been saved, including one in progress, are left in their files to be recovered with
+    /// [Session::recover_takes].
+    pub fn autosave(&self, path: &Path) -> Result<(), ProjectError> {
+        self.project.read().unwrap().save(path)
+    }
+
+    /// Adds the takes left next to an autosave at `path` by a session that crashed to the project,
+    /// where they were recorded. Takes already in the project are not added again.
+    ///
+    /// The takes' files are moved to the record directory, and removed once the project is saved.
+    pub fn recover_takes(&mut self, path: &Path) -> Result<(), SessionError> {
+        let record_dir = self.record_dir.as_ref().ok_or(SessionError::NoRecordDir)?;
+
+        let mut project = self.project.write().unwrap();
+        let takes = recover_takes(&mut project, &Project::recordings_dir(path), record_dir)?;
+        self.unsaved_takes.extend(takes);
+        Ok(())
+    }
+
+    /// Applies an edit to the project so that it can be undone.
+    pub fn edit(&mut self, edit: Edit) {
+        let mut project = self.project.write().unwrap();
+        self.history.apply(&mut project, edit);
+    }
+
+    /// Applies an edit that is part of a continuous gesture, like dragging a slider. The edits of
+    /// one gesture are undone together.
+    pub fn edit_gesture(&mut self, edit: Edit) {
+        let mut project = self.project.write().unwrap();
+        self.history.apply_gesture(&mut project, edit);
+    }
+
+    pub fn end_gesture(&mut self) {
+        self.history.end_gesture();
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let mut project = self.project.write().unwrap();
+        self.history.undo(&mut project)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let mut project = self.project.write().unwrap();
+        self.history.redo(&mut project)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Whether recording is waiting for the count-in to finish.
+    pub fn counting_in(&self) -> bool {
+        let player = self.player.lock().unwrap();
+        player.counting_in()
+    }
+
+    pub fn effect_registry(&self) -> Arc<EffectRegistry> {
+        let player = self.player.lock().unwrap();
+        player.effect_registry()
+    }
+
+    pub fn set_effect_registry(&self, registry: EffectRegistry) {
+        let mut player = self.player.lock().unwrap();
+        player.set_effect_registry(registry);
+    }
+
+    /// Returns the master bus meter readings since the last call.
+    pub fn take_meter(&self) -> MasterMeter {
+        let mut player = self.player.lock().unwrap();
+        player.take_meter()
+    }
+
+    pub fn reset_clip(&self) {
         let mut player = self.player.lock().unwrap();
-        player.set_recording(recording, record_track);
+        player.reset_clip();
     }
 
+    /// Sends a MIDI message to the generator. The message is timestamped here and handled at the
+    /// matching position within a later block.
     pub fn handle(&mut self, msg: midly::MidiMessage) {
+        // Taken before locking, which may wait for the audio callback
+        let time = Instant::now();
+
         let mut player = self.player.lock().unwrap();
-        player.generator.handle(msg);
+        player.handle(msg, time);
     }
 
     pub fn set_generator(&mut self, generator: Box<dyn Generator>) {
         let mut player = self.player.lock().unwrap();
         player.generator = generator;
     }
+
+    /// Sets the generator that plays the metronome's clicks.
+    pub fn set_metronome_click(&self, click: Box<dyn Generator>) {
+        let mut player = self.player.lock().unwrap();
+        player.set_metronome_click(click);
+    }
+
+    pub fn set_generator_param(&self, param: usize, value: f32) {
+        let mut player = self.player.lock().unwrap();
+        player.generator.set_param(param, value);
+    }
+
+    pub fn generator_param(&self, param: usize) -> Option<f32> {
+        let player = self.player.lock().unwrap();
+        player.generator.get_param(param)
+    }
 }
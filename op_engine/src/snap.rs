@@ -0,0 +1,107 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Time;
+use crate::time::TimeGrid;
+
+/// Grid that clips snap to when they are moved or quantized.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Snap {
+    /// Clips are placed exactly where they are dropped.
+    Off,
+
+    Bar,
+
+    #[default]
+    Beat,
+
+    /// A fraction of a beat, e.g. 4 for sixteenth notes in 4/4.
+    Subdivision(u32),
+
+    Second,
+}
+
+impl Snap {
+    pub const ALL: [Snap; 7] = [
+        Snap::Off,
+        Snap::Bar,
+        Snap::Beat,
+        Snap::Subdivision(2),
+        Snap::Subdivision(3),
+        Snap::Subdivision(4),
+        Snap::Second,
+    ];
+
+    /// Returns the distance between grid lines in samples, or None if snapping is off.
+    pub fn spacing(&self, grid: &TimeGrid) -> Option<f64> {
+        match *self {
+            Snap::Off => None,
+            Snap::Bar => Some(grid.samples_per_bar()),
+            Snap::Beat => Some(grid.samples_per_beat()),
+            Snap::Subdivision(n) => Some(grid.samples_per_beat() / n.max(1) as f64),
+            Snap::Second => Some(grid.seconds_to_samples(1.0) as f64),
+        }
+    }
+
+    /// Returns the grid line closest to `time`, or `time` itself if snapping is off.
+    pub fn snap(&self, grid: &TimeGrid, time: Time) -> Time {
+        match self.spacing(grid) {
+            Some(spacing) => ((time as f64 / spacing).round() * spacing).round() as Time,
+            None => time,
+        }
+    }
+
+    /// Returns where something at `time` lands when dragged by `delta` samples: the grid line
+    /// closest to `time + delta`, but never before the start of the project.
+    pub fn snap_move(&self, grid: &TimeGrid, time: Time, delta: i64) -> Time {
+        let moved = (time as i64 + delta).max(0) as Time;
+        self.snap(grid, moved)
+    }
+}
+
+impl Display for Snap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Snap::Off => write!(f, "No snap"),
+            Snap::Bar => write!(f, "Bar"),
+            Snap::Beat => write!(f, "Beat"),
+            Snap::Subdivision(n) => write!(f, "1/{} beat", n),
+            Snap::Second => write!(f, "Second"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::time::TimeSignature;
+
+    use super::*;
+
+    fn grid() -> TimeGrid {
+        // 22050 samples per beat
+        TimeGrid::new(44100, 120.0, TimeSignature::new(3, 4))
+    }
+
+    #[test]
+    fn test_snap_to_nearest_line() {
+        let grid = grid();
+
+        assert_eq!(22050, Snap::Beat.snap(&grid, 30000));
+        assert_eq!(44100, Snap::Beat.snap(&grid, 34000));
+        assert_eq!(66150, Snap::Bar.snap(&grid, 40000));
+        assert_eq!(0, Snap::Bar.snap(&grid, 30000));
+        assert_eq!(27563, Snap::Subdivision(4).snap(&grid, 28000));
+        assert_eq!(44100, Snap::Second.snap(&grid, 40000));
+        assert_eq!(12345, Snap::Off.snap(&grid, 12345));
+    }
+
+    #[test]
+    fn test_snap_move() {
+        let grid = grid();
+
+        assert_eq!(44100, Snap::Beat.snap_move(&grid, 22050, 20000));
+        assert_eq!(0, Snap::Beat.snap_move(&grid, 1000, -5000), "clips should not move before the start");
+        assert_eq!(3000, Snap::Off.snap_move(&grid, 1000, 2000));
+    }
+}
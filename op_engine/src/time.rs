@@ -0,0 +1,198 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+// TODO: Make this type-safe
+pub type Time = usize;  // in samples
+
+/// Subdivisions of a beat, for positions written as bars:beats:ticks.
+pub const TICKS_PER_BEAT: u32 = 960;
+
+/// Tempos outside this range, e.g. from a damaged project file, are clamped into it.
+pub const MIN_TEMPO: f64 = 1.0;
+pub const MAX_TEMPO: f64 = 1000.0;
+
+/// Number of beats in a bar and the note value that gets one beat, e.g. 6/8.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSignature {
+    pub beats_per_bar: u32,
+    pub beat_unit: u32,
+}
+
+impl TimeSignature {
+    pub const fn new(beats_per_bar: u32, beat_unit: u32) -> Self {
+        Self { beats_per_bar, beat_unit }
+    }
+
+    /// Time signatures offered to the user.
+    pub const COMMON: [TimeSignature; 6] = [
+        TimeSignature::new(2, 4),
+        TimeSignature::new(3, 4),
+        TimeSignature::new(4, 4),
+        TimeSignature::new(5, 4),
+        TimeSignature::new(6, 8),
+        TimeSignature::new(7, 8),
+    ];
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+impl Display for TimeSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.beats_per_bar, self.beat_unit)
+    }
+}
+
+/// A position in musical time. Bars and beats count from 1, as they do on a score.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BarsBeatsTicks {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl Display for BarsBeatsTicks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{:03}", self.bar, self.beat, self.tick)
+    }
+}
+
+/// Converts between samples, seconds and musical time. The tempo is constant for the whole
+/// project.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimeGrid {
+    sample_rate: u32,
+
+    /// Beats per minute, where a beat is the time signature's note value.
+    tempo: f64,
+    time_signature: TimeSignature,
+}
+
+impl TimeGrid {
+    pub fn new(sample_rate: u32, tempo: f64, time_signature: TimeSignature) -> Self {
+        let tempo = if tempo.is_nan() { MIN_TEMPO } else { tempo.clamp(MIN_TEMPO, MAX_TEMPO) };
+
+        Self {
+            sample_rate,
+            tempo,
+            time_signature: TimeSignature {
+                beats_per_bar: time_signature.beats_per_bar.max(1),
+                ..time_signature
+            },
+        }
+    }
+
+    pub fn time_signature(&self) -> TimeSignature {
+        self.time_signature
+    }
+
+    pub fn beats_per_bar(&self) -> u32 {
+        self.time_signature.beats_per_bar
+    }
+
+    pub fn samples_per_beat(&self) -> f64 {
+        self.sample_rate as f64 * 60.0 / self.tempo
+    }
+
+    pub fn samples_per_bar(&self) -> f64 {
+        self.samples_per_beat() * self.beats_per_bar() as f64
+    }
+
+    pub fn samples_to_seconds(&self, time: Time) -> f64 {
+        time as f64 / self.sample_rate as f64
+    }
+
+    pub fn seconds_to_samples(&self, seconds: f64) -> Time {
+        (seconds * self.sample_rate as f64).round().max(0.0) as Time
+    }
+
+    /// Returns the number of beats from the start of the project to `time`.
+    pub fn samples_to_beats(&self, time: Time) -> f64 {
+        time as f64 / self.samples_per_beat()
+    }
+
+    /// Returns the sample closest to `beats` beats from the start of the project.
+    pub fn beats_to_samples(&self, beats: f64) -> Time {
+        (beats * self.samples_per_beat()).round().max(0.0) as Time
+    }
+
+    /// Returns the position of `time`, rounded to the nearest tick.
+    pub fn samples_to_bbt(&self, time: Time) -> BarsBeatsTicks {
+        let ticks = (self.samples_to_beats(time) * TICKS_PER_BEAT as f64).round() as u64;
+        let beats = ticks / TICKS_PER_BEAT as u64;
+        let beats_per_bar = self.beats_per_bar() as u64;
+
+        BarsBeatsTicks {
+            bar: (beats / beats_per_bar) as u32 + 1,
+            beat: (beats % beats_per_bar) as u32 + 1,
+            tick: (ticks % TICKS_PER_BEAT as u64) as u32,
+        }
+    }
+
+    pub fn bbt_to_samples(&self, bbt: BarsBeatsTicks) -> Time {
+        let beats = bbt.bar.saturating_sub(1) as f64 * self.beats_per_bar() as f64
+            + bbt.beat.saturating_sub(1) as f64
+            + bbt.tick as f64 / TICKS_PER_BEAT as f64;
+
+        self.beats_to_samples(beats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbt(bar: u32, beat: u32, tick: u32) -> BarsBeatsTicks {
+        BarsBeatsTicks { bar, beat, tick }
+    }
+
+    #[test]
+    fn test_bbt() {
+        let grid = TimeGrid::new(44100, 120.0, TimeSignature::new(3, 4));
+        assert_eq!(22050.0, grid.samples_per_beat());
+
+        assert_eq!(bbt(1, 1, 0), grid.samples_to_bbt(0));
+        assert_eq!(bbt(1, 2, 480), grid.samples_to_bbt(22050 + 11025));
+        assert_eq!(bbt(2, 1, 0), grid.samples_to_bbt(3 * 22050));
+        assert_eq!("2:1:000", grid.samples_to_bbt(3 * 22050).to_string());
+    }
+
+    #[test]
+    fn test_bbt_round_trip() {
+        let grid = TimeGrid::new(48000, 97.0, TimeSignature::new(7, 8));
+
+        for time in (0..10_000_000).step_by(99_991) {
+            let bbt = grid.samples_to_bbt(time);
+            let tick_len = grid.samples_per_beat() / TICKS_PER_BEAT as f64;
+            let error = grid.bbt_to_samples(bbt) as f64 - time as f64;
+            assert!(error.abs() <= tick_len / 2.0 + 1.0, "{} became {} ({})", time, grid.bbt_to_samples(bbt), bbt);
+        }
+
+        for beat in 0..100 {
+            let time = grid.beats_to_samples(beat as f64);
+            assert_eq!(0, grid.samples_to_bbt(time).tick, "beat {} should land on a tick boundary", beat);
+        }
+    }
+
+    #[test]
+    fn test_invalid_tempo_is_clamped() {
+        for tempo in [0.0, -120.0, f64::NAN, f64::NEG_INFINITY] {
+            let grid = TimeGrid::new(44100, tempo, TimeSignature::default());
+            assert_eq!(44100.0 * 60.0 / MIN_TEMPO, grid.samples_per_beat(), "tempo {}", tempo);
+        }
+
+        let grid = TimeGrid::new(44100, f64::INFINITY, TimeSignature::default());
+        assert_eq!(44100.0 * 60.0 / MAX_TEMPO, grid.samples_per_beat());
+    }
+
+    #[test]
+    fn test_seconds() {
+        let grid = TimeGrid::new(44100, 120.0, TimeSignature::default());
+        assert_eq!(66150, grid.seconds_to_samples(1.5));
+        assert_eq!(1.5, grid.samples_to_seconds(66150));
+    }
+}
@@ -1,21 +1,98 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use crate::{mix, Time, Track};
-use crate::clip_database::ClipDatabase;
-use crate::track::ClipInstance;
+use crate::clip_database::{ClipDatabase, ClipId};
+use crate::effect::{EffectChain, EffectRegistry};
+use crate::track::{ClipInstance, ClipInstanceId};
 
 /// A Timeline is a composition of a fixed number of tracks. All of the tracks can be mixed down to
 /// a single audio signal.
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Timeline {
     pub tracks: Vec<Track>,
+
+    // ID of the next clip instance. IDs are never reused, even after their instance is removed.
+    // Atomic so that edits can reserve IDs while the project is only locked for reading.
+    next_instance_id: AtomicU64,
+}
+
+impl Clone for Timeline {
+    fn clone(&self) -> Self {
+        Self {
+            tracks: self.tracks.clone(),
+            next_instance_id: AtomicU64::new(self.next_instance_id.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Runtime state used while rendering a Timeline, holding the effect chain of each track. The same
+/// TimelineEffects should be used for consecutive blocks so that effects like delays carry over.
+pub struct TimelineEffects {
+    sample_rate: u32,
+    registry: Arc<EffectRegistry>,
+    tracks: Vec<EffectChain>,
+}
+
+impl TimelineEffects {
+    pub fn new(sample_rate: u32, registry: Arc<EffectRegistry>) -> Self {
+        Self {
+            sample_rate,
+            registry,
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Returns how many frames the track effects of `timeline` keep producing output for after
+    /// the last clip.
+    fn tail(&self, timeline: &Timeline) -> usize {
+        timeline.tracks.iter()
+            .zip(self.tracks.iter())
+            .map(|(track, chain)| chain.tail(&track.effects))
+            .max()
+            .unwrap_or(0)
+    }
 }
 
 impl Timeline {
     pub fn new() -> Self {
         Timeline {
             tracks: vec![Track::new(); 4],
+            next_instance_id: AtomicU64::new(0),
         }
     }
 
+    /// Returns an ID that no clip instance on this timeline has had before.
+    pub fn new_instance_id(&self) -> ClipInstanceId {
+        ClipInstanceId(self.next_instance_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Adds a new instance of `clip_id` on top of the other clips on a track, and returns its ID.
+    pub fn instantiate_clip(&mut self, track: usize, clip_id: ClipId, time: Time) -> ClipInstanceId {
+        let id = self.new_instance_id();
+        self.tracks[track].add_clip(ClipInstance::new(id, time, clip_id));
+        id
+    }
+
+    /// Returns the number of the track a clip is on, and the clip.
+    pub fn find_clip(&self, id: ClipInstanceId) -> Option<(usize, &ClipInstance)> {
+        self.tracks.iter()
+            .enumerate()
+            .find_map(|(i, track)| track.get_clip(id).map(|c| (i, c)))
+    }
+
+    /// Moves a clip to start at `time`. Returns false if the clip is not on the timeline.
+    pub fn move_clip(&mut self, id: ClipInstanceId, time: Time) -> bool {
+        self.tracks.iter_mut().any(|track| track.move_clip(id, time))
+    }
+
+    /// Removes a clip, returning the number of the track it was on and the removed instance.
+    pub fn remove_clip(&mut self, id: ClipInstanceId) -> Option<(usize, ClipInstance)> {
+        self.tracks.iter_mut()
+            .enumerate()
+            .find_map(|(i, track)| track.remove_clip(id).map(|(_, c)| (i, c)))
+    }
+
     pub fn len(&self, database: &ClipDatabase) -> usize {
         self.tracks.iter()
             .map(|t| t.len(database))
@@ -32,22 +109,45 @@ impl Timeline {
             })
     }
 
-    pub fn render(&self, database: &ClipDatabase, start_time: Time, buf: &mut [f32]) {
-        self.render_exclude(database, start_time, buf, &[]);
+    /// Returns whether the track at `index` should be heard. Soloed tracks are always heard, and if
+    /// any track is soloed then all other tracks are silent. Otherwise, only muted tracks are silent.
+    pub fn is_audible(&self, index: usize) -> bool {
+        let track = &self.tracks[index];
+
+        if self.tracks.iter().any(|t| t.soloed) {
+            track.soloed
+        } else {
+            !track.muted
+        }
+    }
+
+    /// Mixes all tracks into `buf`, which holds interleaved frames with `channels` channels. Each
+    /// track is passed through its effect chain before its gain and pan are applied.
+    pub fn render(&self, database: &ClipDatabase, start_time: Time, buf: &mut [f32], channels: usize, effects: &mut TimelineEffects) {
+        self.render_exclude(database, start_time, buf, channels, effects, &[]);
     }
 
-    pub fn render_exclude(&self, database: &ClipDatabase, start_time: Time, buf: &mut [f32], exclude: &[usize]) {
-        if start_time >= self.len(database) {
+    pub fn render_exclude(&self, database: &ClipDatabase, start_time: Time, buf: &mut [f32], channels: usize, effects: &mut TimelineEffects, exclude: &[usize]) {
+        // Effects may still be producing a tail after the last clip
+        if start_time >= self.len(database) && self.tracks.iter().all(|t| t.effects.is_empty()) {
             buf.fill(0.0);
             return;
         }
 
+        while effects.tracks.len() < self.tracks.len() {
+            effects.tracks.push(EffectChain::new(effects.sample_rate));
+        }
+
+        let registry = &effects.registry;
         let rendered: Vec<Vec<f32>> = self.tracks.iter()
+            .zip(effects.tracks.iter_mut())
             .enumerate()
-            .filter(|(i, _)| !exclude.contains(i))
-            .map(|(_, t)| {
+            .filter(|(i, _)| !exclude.contains(i) && self.is_audible(*i))
+            .map(|(_, (t, chain))| {
                 let mut track_buf = vec![0.0f32; buf.len()];
-                t.render(database, start_time, &mut track_buf);
+                t.render(database, start_time, &mut track_buf, channels);
+                chain.process(&t.effects, registry, &mut track_buf, channels);
+                t.apply_gain_and_pan(&mut track_buf, channels);
                 track_buf
             }).collect();
 
@@ -55,13 +155,173 @@ impl Timeline {
         mix(&sources, buf)
     }
 
-    pub fn render_all(&self, database: &ClipDatabase) -> Vec<f32> {
+    /// Renders the whole timeline, continuing past the last clip until the track effects have
+    /// decayed.
+    pub fn render_all(&self, database: &ClipDatabase, channels: usize, effects: &mut TimelineEffects) -> Vec<f32> {
         if self.tracks.is_empty() {
             return Vec::new();
         }
 
-        let mut buf = vec![0.0f32; self.len(database)];
-        self.render(database, 0, &mut buf);
+        let len = self.len(database);
+        let mut buf = vec![0.0f32; len * channels];
+        self.render(database, 0, &mut buf, channels, effects);
+
+        let mut tail = vec![0.0f32; effects.tail(self) * channels];
+        self.render(database, len, &mut tail, channels, effects);
+        buf.extend(tail);
         buf
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::Clip;
+    use crate::effect::EffectSettings;
+
+    use super::*;
+
+    fn render_all(timeline: &Timeline, db: &ClipDatabase, channels: usize) -> Vec<f32> {
+        let mut effects = TimelineEffects::new(44100, Arc::new(EffectRegistry::default()));
+        timeline.render_all(db, channels, &mut effects)
+    }
+
+    fn assert_samples_eq(expected: &[f32], actual: &[f32]) {
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(actual) {
+            assert!((e - a).abs() < 1e-4, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    /// Creates a timeline where track `i` plays a single sample with value 0.1 * (i + 1).
+    fn test_timeline() -> (Timeline, ClipDatabase) {
+        let mut timeline = Timeline::new();
+        let mut db = ClipDatabase::new();
+
+        for i in 0..timeline.tracks.len() {
+            timeline.instantiate_clip(i, db.add(Clip::new(vec![0.1 * (i + 1) as f32])), 0);
+        }
+
+        (timeline, db)
+    }
+
+    #[test]
+    fn test_instance_ids() {
+        let (mut timeline, mut db) = test_timeline();
+        let clip = db.add(Clip::new(vec![1.0]));
+        let first = timeline.instantiate_clip(2, clip, 10);
+        let second = timeline.instantiate_clip(2, clip, 20);
+        assert_ne!(first, second);
+
+        assert!(timeline.move_clip(second, 30));
+        assert_eq!(Some((2, 10)), timeline.find_clip(first).map(|(t, c)| (t, c.time)));
+        assert_eq!(Some((2, 30)), timeline.find_clip(second).map(|(t, c)| (t, c.time)));
+
+        assert_eq!(Some(2), timeline.remove_clip(first).map(|(t, _)| t));
+        assert!(timeline.find_clip(first).is_none());
+        assert_ne!(first, timeline.instantiate_clip(0, clip, 0), "IDs should not be reused");
+    }
+
+    #[test]
+    fn test_mute() {
+        let (mut timeline, db) = test_timeline();
+        timeline.tracks[1].muted = true;
+        timeline.tracks[3].muted = true;
+
+        assert_samples_eq(&[0.4], &render_all(&timeline, &db, 1));
+    }
+
+    #[test]
+    fn test_solo_overrides_mute() {
+        let (mut timeline, db) = test_timeline();
+        timeline.tracks[0].muted = true;
+        timeline.tracks[0].soloed = true;
+        timeline.tracks[2].soloed = true;
+
+        assert!(timeline.is_audible(0));
+        assert!(!timeline.is_audible(1));
+        assert_samples_eq(&[0.4], &render_all(&timeline, &db, 1));
+    }
+
+    #[test]
+    fn test_gain() {
+        let (mut timeline, db) = test_timeline();
+        for track in timeline.tracks.iter_mut() {
+            track.muted = true;
+        }
+
+        timeline.tracks[1].muted = false;
+        timeline.tracks[1].gain_db = -6.0;
+
+        assert_samples_eq(&[0.2 * 0.501187], &render_all(&timeline, &db, 1));
+    }
+
+    #[test]
+    fn test_pan() {
+        let (mut timeline, db) = test_timeline();
+        timeline.tracks[0].soloed = true;
+
+        // Centered tracks are at unity gain in both channels
+        assert_samples_eq(&[0.1, 0.1], &render_all(&timeline, &db, 2));
+
+        timeline.tracks[0].pan = -1.0;
+        assert_samples_eq(&[0.1 * 2f32.sqrt(), 0.0], &render_all(&timeline, &db, 2));
+
+        timeline.tracks[0].pan = 1.0;
+        assert_samples_eq(&[0.0, 0.1 * 2f32.sqrt()], &render_all(&timeline, &db, 2));
+
+        // Total power stays constant across the pan range
+        for pan in [-0.5, 0.25, 0.8] {
+            timeline.tracks[0].pan = pan;
+            let rendered = render_all(&timeline, &db, 2);
+            let power = rendered[0] * rendered[0] + rendered[1] * rendered[1];
+            assert!((power - 0.02).abs() < 1e-6);
+        }
+
+        // Pan is ignored for mono output
+        timeline.tracks[0].pan = -1.0;
+        assert_samples_eq(&[0.1], &render_all(&timeline, &db, 1));
+    }
+
+    #[test]
+    fn test_track_effects() {
+        let (mut timeline, db) = test_timeline();
+        timeline.tracks[1].soloed = true;
+        timeline.tracks[1].effects.push(EffectSettings::new("gain").with_param("gain", 6.0));
+
+        assert_samples_eq(&[0.2 * 1.995262], &render_all(&timeline, &db, 1));
+
+        timeline.tracks[1].effects[0].bypass = true;
+        assert_samples_eq(&[0.2], &render_all(&timeline, &db, 1));
+    }
+
+    #[test]
+    fn test_effect_tail_continues_after_clips() {
+        let (mut timeline, db) = test_timeline();
+        timeline.tracks[0].soloed = true;
+        timeline.tracks[0].effects.push(EffectSettings::new("delay")
+            .with_param("time", 1.0)
+            .with_param("mix", 1.0));
+
+        let mut effects = TimelineEffects::new(1000, Arc::new(EffectRegistry::default()));
+        let mut buf = [0.0f32; 1];
+
+        timeline.render(&db, 0, &mut buf, 1, &mut effects);
+        assert_samples_eq(&[0.0], &buf);
+
+        timeline.render(&db, 1, &mut buf, 1, &mut effects);
+        assert_samples_eq(&[0.1], &buf);
+    }
+
+    #[test]
+    fn test_render_all_includes_effect_tail() {
+        let (mut timeline, db) = test_timeline();
+        timeline.tracks[0].soloed = true;
+        timeline.tracks[0].effects.push(EffectSettings::new("delay")
+            .with_param("time", 2.0)
+            .with_param("feedback", 0.0)
+            .with_param("mix", 1.0));
+
+        let mut effects = TimelineEffects::new(1000, Arc::new(EffectRegistry::default()));
+        assert_samples_eq(&[0.0, 0.0, 0.1], &timeline.render_all(&db, 1, &mut effects));
+    }
+}
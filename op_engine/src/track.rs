@@ -1,22 +1,31 @@
 use std::cmp::min;
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
 use std::slice::Iter;
 
 use serde::{Deserialize, Serialize};
 
 use crate::clip::Clip;
 use crate::clip_database::{ClipDatabase, ClipId};
-use crate::Time;
+use crate::{convert_channels, db_to_linear, Time};
+use crate::effect::EffectSettings;
 
-/// A ClipInstance is a clip with a defined starting time.
+/// Identifies a clip instance. IDs are unique within a timeline and are saved with the project.
+#[derive(Debug, Default, Eq, Hash, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize, Deserialize)]
+pub struct ClipInstanceId(pub(crate) u64);
+
+/// A ClipInstance is a clip with a defined starting time. The same clip may be instantiated any
+/// number of times.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClipInstance {
+    pub id: ClipInstanceId,
+
     pub time: Time,
     pub clip_id: ClipId,
 }
 
 impl ClipInstance {
-    pub fn new(time: Time, clip_id: ClipId) -> ClipInstance {
-        ClipInstance { time, clip_id }
+    pub fn new(id: ClipInstanceId, time: Time, clip_id: ClipId) -> ClipInstance {
+        ClipInstance { id, time, clip_id }
     }
 
     /// Returns the first sample on the timeline that this clip is playing.
@@ -30,7 +39,7 @@ impl ClipInstance {
     }
 
     pub fn len(&self, database: &ClipDatabase) -> Option<Time> {
-        database.get(self.clip_id).map(|clip| clip.data.len())
+        database.get(self.clip_id).map(|clip| clip.len())
     }
 }
 
@@ -39,31 +48,57 @@ impl ClipInstance {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Track {
     clips: Vec<ClipInstance>,
+
+    /// Gain applied to the track in dB.
+    #[serde(default)]
+    pub gain_db: f32,
+
+    /// Stereo position of the track, from -1 (left) to 1 (right).
+    #[serde(default)]
+    pub pan: f32,
+
+    #[serde(default)]
+    pub muted: bool,
+
+    #[serde(default)]
+    pub soloed: bool,
+
+    /// Insert effects, applied in order before gain and pan.
+    #[serde(default)]
+    pub effects: Vec<EffectSettings>,
 }
 
-/// Copy up to `max_copy` samples from `clip` starting at `clip_start` to `buf` starting at
-/// `buf_start`. Fewer than `max_copy` samples will be copied when:
+/// Copy up to `max_copy` frames from `clip` starting at `clip_start` to `buf` (interleaved, with
+/// `channels` channels) starting at frame `buf_start`. The clip is converted to the buffer's
+/// channel count. Fewer than `max_copy` frames will be copied when:
 ///     - There is not enough space in the buffer
 ///     - The clip is not long enough
 fn copy_clip_data(clip: &Clip,
                   buf: &mut [f32],
+                  channels: usize,
                   clip_start: usize,
                   buf_start: usize,
                   max_copy: usize,
 ) -> usize {
-    debug_assert!(clip_start < clip.data.len());
-    debug_assert!(buf_start <= buf.len());
+    let buf_frames = buf.len() / channels;
+
+    debug_assert!(clip_start < clip.len());
+    debug_assert!(buf_start <= buf_frames);
 
-    let buf_space = buf.len() - buf_start;
-    let clip_space = clip.data.len() - clip_start;
+    let buf_space = buf_frames - buf_start;
+    let clip_space = clip.len() - clip_start;
     let actual_copy = min(max_copy, min(buf_space, clip_space));
 
     if actual_copy == 0 {
         return 0;
     }
 
-    buf[buf_start..buf_start + actual_copy]
-        .copy_from_slice(&clip.data[clip_start..clip_start + actual_copy]);
+    convert_channels(
+        &clip.data[clip_start * clip.channels..(clip_start + actual_copy) * clip.channels],
+        clip.channels,
+        &mut buf[buf_start * channels..(buf_start + actual_copy) * channels],
+        channels,
+    );
 
     actual_copy
 }
@@ -73,8 +108,11 @@ impl Track {
         Track::default()
     }
 
-    pub fn instantiate_clip(&mut self, clip_id: ClipId, time: Time) -> &ClipInstance {
-        self.clips.push(ClipInstance::new(time, clip_id));
+    /// Adds a clip instance on top of the track's other clips. Use
+    /// [Timeline::instantiate_clip](crate::Timeline::instantiate_clip) to create an instance with
+    /// a new ID.
+    pub fn add_clip(&mut self, instance: ClipInstance) -> &ClipInstance {
+        self.clips.push(instance);
         self.clips.last().unwrap()
     }
 
@@ -97,7 +135,8 @@ impl Track {
             .rfind(|c| { c.start() <= t && c.end(database) > Some(t) })
     }
 
-    pub fn render(&self, database: &ClipDatabase, start_time: Time, buf: &mut [f32]) {
+    /// Renders this track into `buf`, which holds interleaved frames with `channels` channels.
+    pub fn render(&self, database: &ClipDatabase, start_time: Time, buf: &mut [f32], channels: usize) {
         buf.fill(0.0);
 
         if start_time >= self.len(database) {
@@ -105,14 +144,15 @@ impl Track {
         }
 
         let mut time = start_time;
-        let end_time = time + buf.len();
+        let end_time = time + buf.len() / channels;
 
         // If there is a clip ongoing at the start, copy it partially
         if let Some(clip_instance) = self.clip_at(database, time) {
             if let Some(clip) = database.get(clip_instance.clip_id) {
                 copy_clip_data(
-                    &clip,
+                    clip,
                     buf,
+                    channels,
                     time - clip_instance.start(),
                     0,
                     clip.len(),
@@ -134,8 +174,9 @@ impl Track {
             }
 
             copy_clip_data(
-                &clip,
+                clip,
                 buf,
+                channels,
                 0,
                 time - start_time,
                 clip.len(),
@@ -143,20 +184,49 @@ impl Track {
         }
     }
 
+    /// Returns the linear gain for each channel of a frame, combining the track gain with its pan.
+    /// Pan is only applied to stereo output and uses a constant-power law, scaled so that a
+    /// centered track is at unity gain.
+    fn channel_gains(&self, channels: usize) -> Vec<f32> {
+        let gain = db_to_linear(self.gain_db);
+
+        if channels != 2 {
+            return vec![gain; channels];
+        }
+
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+        vec![
+            gain * SQRT_2 * angle.cos(),
+            gain * SQRT_2 * angle.sin(),
+        ]
+    }
+
+    /// Applies this track's gain and pan to `buf`, which holds interleaved frames with `channels`
+    /// channels.
+    pub fn apply_gain_and_pan(&self, buf: &mut [f32], channels: usize) {
+        let gains = self.channel_gains(channels);
+
+        for frame in buf.chunks_exact_mut(channels) {
+            for (sample, gain) in frame.iter_mut().zip(&gains) {
+                *sample *= gain;
+            }
+        }
+    }
+
     pub fn len(&self, database: &ClipDatabase) -> usize {
         self.last_clip(database)
             .and_then(|c| c.end(database))
             .unwrap_or(0)
     }
 
-    pub fn render_all(&self, database: &ClipDatabase) -> Vec<f32> {
+    pub fn render_all(&self, database: &ClipDatabase, channels: usize) -> Vec<f32> {
         let end = match self.last_clip(database).and_then(|c| c.end(database)) {
             None => return vec![0.0; 0],
             Some(end) => end,
         };
 
-        let mut buf = vec![0.0; end];
-        self.render(database, 0, buf.as_mut_slice());
+        let mut buf = vec![0.0; end * channels];
+        self.render(database, 0, buf.as_mut_slice(), channels);
         buf
     }
 
@@ -164,9 +234,40 @@ impl Track {
         self.clips.iter()
     }
 
-    // TODO: Using Clip ID to uniquely identify ClipInstances isn't correct, but currently works
-    pub fn get_clip_mut(&mut self, id: ClipId) -> Option<&mut ClipInstance> {
-        self.clips.iter_mut().find(|c| c.clip_id == id)
+    pub fn get_clip(&self, id: ClipInstanceId) -> Option<&ClipInstance> {
+        self.clips.iter().find(|c| c.id == id)
+    }
+
+    pub fn get_clip_mut(&mut self, id: ClipInstanceId) -> Option<&mut ClipInstance> {
+        self.clips.iter_mut().find(|c| c.id == id)
+    }
+
+    /// Returns the index of a clip in the track's clip order.
+    pub fn clip_index(&self, id: ClipInstanceId) -> Option<usize> {
+        self.clips.iter().position(|c| c.id == id)
+    }
+
+    /// Moves a clip to start at `time`. Returns false if the clip is not on this track.
+    pub fn move_clip(&mut self, id: ClipInstanceId, time: Time) -> bool {
+        match self.get_clip_mut(id) {
+            Some(instance) => {
+                instance.time = time;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Inserts a clip instance at `index` in the track's clip order, where later clips play over
+    /// earlier ones.
+    pub fn insert_clip(&mut self, index: usize, instance: ClipInstance) {
+        self.clips.insert(index.min(self.clips.len()), instance);
+    }
+
+    /// Removes a clip, returning its index in the track's clip order and the removed instance.
+    pub fn remove_clip(&mut self, id: ClipInstanceId) -> Option<(usize, ClipInstance)> {
+        let index = self.clip_index(id)?;
+        Some((index, self.clips.remove(index)))
     }
 }
 
@@ -174,20 +275,46 @@ impl Track {
 mod test {
     use super::*;
 
+    /// Adds an instance of `clip_id` with an ID unique within the track.
+    fn instantiate(track: &mut Track, clip_id: ClipId, time: Time) -> &ClipInstance {
+        let id = ClipInstanceId(track.clips.len() as u64);
+        track.add_clip(ClipInstance::new(id, time, clip_id))
+    }
+
     #[test]
     fn test_add_clip() {
         let mut track = Track::new();
         let mut db = ClipDatabase::new();
 
         let clip_1 = db.add(Clip::new(vec![1.0]));
-        let result = track.instantiate_clip(clip_1, 0);
+        let result = instantiate(&mut track, clip_1, 0);
         assert_eq!(clip_1, result.clip_id);
 
         let clip_2 = db.add(Clip::new(vec![2.0]));
-        let result = track.instantiate_clip(clip_2, 1);
+        let result = instantiate(&mut track, clip_2, 1);
         assert_eq!(clip_2, result.clip_id);
     }
 
+    #[test]
+    fn test_instances_of_same_clip() {
+        let mut track = Track::new();
+        let mut db = ClipDatabase::new();
+
+        let clip = db.add(Clip::new(vec![1.0]));
+        let first = instantiate(&mut track, clip, 0).id;
+        let second = instantiate(&mut track, clip, 10).id;
+
+        assert!(track.move_clip(second, 20));
+        assert_eq!(0, track.get_clip(first).unwrap().time, "moving one instance should not move the other");
+        assert_eq!(20, track.get_clip(second).unwrap().time);
+
+        let (index, removed) = track.remove_clip(first).unwrap();
+        assert_eq!((0, first), (index, removed.id));
+        assert!(track.get_clip(first).is_none());
+        assert!(!track.move_clip(first, 5));
+        assert_eq!(1, track.iter_clips().count());
+    }
+
     #[test]
     fn test_last_clip() {
         let mut track = Track::new();
@@ -201,7 +328,7 @@ mod test {
         // v
         // 1--------------
         let clip = db.add(Clip::new(vec![1.0]));
-        track.instantiate_clip(clip, 0);
+        instantiate(&mut track, clip, 0);
         let last_clip = track.last_clip(&db)
             .expect("last clip must be present when track contains clips");
 
@@ -212,7 +339,7 @@ mod test {
         //   v
         // 1-2------------
         let clip = db.add(Clip::new(vec![2.0]));
-        track.instantiate_clip(clip, 2);
+        instantiate(&mut track, clip, 2);
         let last_clip = track.last_clip(&db)
             .expect("last clip must be present when track contains clips");
 
@@ -224,7 +351,7 @@ mod test {
         // 1-2------------
         // 3333333333-----
         let clip = db.add(Clip::new(vec![3.0; 10]));
-        track.instantiate_clip(clip, 0);
+        instantiate(&mut track, clip, 0);
         let last_clip = track.last_clip(&db)
             .expect("last clip must be present when track contains clips");
 
@@ -239,14 +366,14 @@ mod test {
         assert!(track.clip_at(&db, 0).is_none());
 
         let clip = db.add(Clip::new(vec![1.0, 1.0]));
-        track.instantiate_clip(clip, 0);
+        instantiate(&mut track, clip, 0);
 
         assert_eq!(clip, track.clip_at(&db, 0).unwrap().clip_id);
         assert_eq!(clip, track.clip_at(&db, 1).unwrap().clip_id);
         assert!(track.clip_at(&db, 2).is_none());
 
         let short_clip = db.add(Clip::new(vec![2.0]));
-        track.instantiate_clip(short_clip, 1);
+        instantiate(&mut track, short_clip, 1);
 
         assert_eq!(clip, track.clip_at(&db, 0).unwrap().clip_id);
         assert_eq!(short_clip, track.clip_at(&db, 1).unwrap().clip_id,
@@ -266,7 +393,7 @@ mod test {
         // v        next=None
         // 11-----------
         let clip = db.add(Clip::new(vec![1.0, 1.0]));
-        track.instantiate_clip(clip, 0);
+        instantiate(&mut track, clip, 0);
         assert!(track.next_clip(0).is_none(),
                 "next_clip should not return clips where start <= t < end");
 
@@ -275,7 +402,7 @@ mod test {
         // v  v
         // 11-2---------
         let clip = db.add(Clip::new(vec![2.0]));
-        track.instantiate_clip(clip, 3);
+        instantiate(&mut track, clip, 3);
         let result = track.next_clip(0).unwrap();
         assert_eq!(3, result.time);
         assert_eq!(clip, result.clip_id);
@@ -289,7 +416,7 @@ mod test {
         //  ^
         //  next
         let clip = db.add(Clip::new(vec![3.0]));
-        track.instantiate_clip(clip, 1);
+        instantiate(&mut track, clip, 1);
         let result = track.next_clip(0).unwrap();
         assert_eq!(1, result.time);
         assert_eq!(clip, result.clip_id);
@@ -304,7 +431,7 @@ mod test {
             let track = Track::new();
             let db = ClipDatabase::new();
             let mut buf = vec![0.0; 4];
-            track.render(&db, 0, &mut buf);
+            track.render(&db, 0, &mut buf, 1);
             assert_eq!(buf, vec![0.0; 4]);
         }
 
@@ -316,8 +443,8 @@ mod test {
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 4];
 
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 4])), 0);
-            track.render(&db, 0, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 4])), 0);
+            track.render(&db, 0, &mut buf, 1);
             assert_eq!(buf, vec![1.0; 4])
         }
 
@@ -328,8 +455,8 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 4];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 4])), 0);
-            track.render(&db, 2, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 4])), 0);
+            track.render(&db, 2, &mut buf, 1);
             assert_eq!(buf, vec![1.0, 1.0, 0.0, 0.0])
         }
 
@@ -340,8 +467,8 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 4];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 4])), 2);
-            track.render(&db, 0, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 4])), 2);
+            track.render(&db, 0, &mut buf, 1);
             assert_eq!(buf, vec![0.0, 0.0, 1.0, 1.0])
         }
 
@@ -352,8 +479,8 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 6];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 2])), 2);
-            track.render(&db, 0, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 2])), 2);
+            track.render(&db, 0, &mut buf, 1);
             assert_eq!(buf, vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0])
         }
 
@@ -364,8 +491,8 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 4];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 2])), 2);
-            track.render(&db, 100, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 2])), 2);
+            track.render(&db, 100, &mut buf, 1);
             assert_eq!(buf, vec![0.0; 4])
         }
 
@@ -376,9 +503,9 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 3];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0])), 0);
-            track.instantiate_clip(db.add(Clip::new(vec![2.0])), 2);
-            track.render(&db, 0, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0])), 0);
+            instantiate(&mut track, db.add(Clip::new(vec![2.0])), 2);
+            track.render(&db, 0, &mut buf, 1);
             assert_eq!(buf, vec![1.0, 0.0, 2.0])
         }
 
@@ -389,9 +516,9 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 3];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 2])), 0);
-            track.instantiate_clip(db.add(Clip::new(vec![2.0; 2])), 3);
-            track.render(&db, 1, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 2])), 0);
+            instantiate(&mut track, db.add(Clip::new(vec![2.0; 2])), 3);
+            track.render(&db, 1, &mut buf, 1);
             assert_eq!(buf, vec![1.0, 0.0, 2.0])
         }
 
@@ -403,9 +530,9 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 6];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 4])), 0);
-            track.instantiate_clip(db.add(Clip::new(vec![2.0; 4])), 2);
-            track.render(&db, 0, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 4])), 0);
+            instantiate(&mut track, db.add(Clip::new(vec![2.0; 4])), 2);
+            track.render(&db, 0, &mut buf, 1);
             assert_eq!(buf, vec![1.0, 1.0, 2.0, 2.0, 2.0, 2.0])
         }
 
@@ -417,10 +544,48 @@ mod test {
             let mut track = Track::new();
             let mut db = ClipDatabase::new();
             let mut buf = vec![0.0; 6];
-            track.instantiate_clip(db.add(Clip::new(vec![1.0; 6])), 0);
-            track.instantiate_clip(db.add(Clip::new(vec![2.0; 2])), 2);
-            track.render(&db, 0, &mut buf);
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 6])), 0);
+            instantiate(&mut track, db.add(Clip::new(vec![2.0; 2])), 2);
+            track.render(&db, 0, &mut buf, 1);
             assert_eq!(buf, vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0])
         }
     }
+
+    #[test]
+    fn test_render_stereo() {
+        // mono clip is copied to both channels
+        // [  ]
+        // --11
+        {
+            let mut track = Track::new();
+            let mut db = ClipDatabase::new();
+            let mut buf = vec![0.0; 8];
+            instantiate(&mut track, db.add(Clip::new(vec![1.0; 2])), 2);
+            track.render(&db, 0, &mut buf, 2);
+            assert_eq!(buf, vec![0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0])
+        }
+
+        // stereo clip keeps its channels, including when starting partway through
+        //  [  ]
+        // 123456
+        {
+            let mut track = Track::new();
+            let mut db = ClipDatabase::new();
+            let mut buf = vec![0.0; 6];
+            let data = vec![1.0, -1.0, 2.0, -2.0, 3.0, -3.0, 4.0, -4.0];
+            instantiate(&mut track, db.add(Clip::new_interleaved(data, 2)), 0);
+            track.render(&db, 1, &mut buf, 2);
+            assert_eq!(buf, vec![2.0, -2.0, 3.0, -3.0, 4.0, -4.0])
+        }
+
+        // stereo clip rendered to a mono buffer is downmixed
+        {
+            let mut track = Track::new();
+            let mut db = ClipDatabase::new();
+            let mut buf = vec![0.0; 2];
+            instantiate(&mut track, db.add(Clip::new_interleaved(vec![1.0, 0.0, 0.5, 0.5], 2)), 0);
+            track.render(&db, 0, &mut buf, 1);
+            assert_eq!(buf, vec![0.5, 0.5])
+        }
+    }
 }